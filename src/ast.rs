@@ -0,0 +1,304 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Parsed expressions. Names are kept as written and only resolved against
+//! constants, variables and functions when the tree is evaluated.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::bitwise;
+use crate::env::Environment;
+use crate::error::{CalcError, MathError};
+use crate::lex::{self, Function, FunctionContext, Relation, Span, FUNCTIONS, OPERATORS};
+use crate::logic;
+use crate::matrix::{self, Matrix};
+use crate::value::{self, Value};
+
+/// Every node keeps the byte range of the input it was parsed from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal {
+        value: Value,
+        span: Span,
+    },
+    /// A constant or variable, e.g. `pi` or `rate`.
+    Ident {
+        name: String,
+        span: Span,
+    },
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+        span: Span,
+    },
+    /// `lhs op rhs`, where `op` is a key of `lex::OPERATORS`.
+    Binary {
+        op: &'static str,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        span: Span,
+    },
+    Call {
+        name: String,
+        args: Vec<Expr>,
+        span: Span,
+    },
+    /// `[1, 2; 3, 4]`, rows all have the same length.
+    Matrix {
+        rows: Vec<Vec<Expr>>,
+        span: Span,
+    },
+    /// `lhs = rhs`, only as an argument of functions like `solve`.
+    Equation {
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        span: Span,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    /// Prefix `-`, binds tighter than every binary operator.
+    Neg,
+    /// Postfix `!`.
+    Factorial,
+    /// Prefix `~`, the two's complement of an integer.
+    BitNot,
+    /// Prefix `!`, negates a boolean.
+    Not,
+}
+
+impl Expr {
+    /// Evaluate the tree, errors point at the node that caused them.
+    pub fn eval(&self, ctx: &FunctionContext, env: &Environment) -> Result<Value, CalcError> {
+        match self {
+            Expr::Literal { value, .. } => Ok(value.clone()),
+            Expr::Ident { name, span } => match lex::lookup(name, ctx, env) {
+                Some(x) => Ok(x),
+                None if FUNCTIONS.contains_key(&name[..]) || env.function(name).is_some() => Err(
+                    CalcError::Syntax(format!("Function '{}' expected parentheses", name))
+                        .at(span.clone()),
+                ),
+                None => {
+                    Err(CalcError::Syntax(format!("Unknown variable '{}'", name)).at(span.clone()))
+                }
+            },
+            Expr::Unary { op, expr, span } => {
+                let x = expr.eval(ctx, env)?.number();
+                match op {
+                    UnaryOp::Not => logic::truth(&x)
+                        .map(|b| Value::Bool(!b))
+                        .map_err(|e| e.at(span.clone())),
+                    UnaryOp::Neg | UnaryOp::BitNot if matches!(x, Value::Bool(_)) => {
+                        Err(CalcError::Math(MathError::Type(format!(
+                            "Operator '{}' needs a number, got a boolean",
+                            if *op == UnaryOp::Neg { "-" } else { "~" }
+                        )))
+                        .at(span.clone()))
+                    }
+                    UnaryOp::Neg => Ok(-x),
+                    UnaryOp::Factorial => lex::factorial(x).map_err(|e| e.at(span.clone())),
+                    UnaryOp::BitNot => bitwise::not(&x).map_err(|e| e.at(span.clone())),
+                }
+            }
+            Expr::Binary { op, lhs, rhs, .. } if matches!(*op, "&&" | "||") => {
+                // the right operand is only evaluated if the left one does not decide
+                let x = lhs.eval(ctx, env)?;
+                let x = logic::truth(&x).map_err(|e| e.at(lhs.span()))?;
+                if x == (*op == "||") {
+                    return Ok(Value::Bool(x));
+                }
+                let y = rhs.eval(ctx, env)?;
+                logic::truth(&y)
+                    .map(Value::Bool)
+                    .map_err(|e| e.at(rhs.span()))
+            }
+            Expr::Binary { op, lhs, rhs, span } => {
+                let x = lhs.eval(ctx, env)?;
+                let y = rhs.eval(ctx, env)?;
+                OPERATORS[*op]
+                    .operate(x, y)
+                    .map(|x| value::conform(ctx, x))
+                    .map_err(|e| e.at(span.clone()))
+            }
+            Expr::Call { name, args, span } => call(name, args, span, ctx, env),
+            Expr::Matrix { rows, .. } => {
+                let rows = rows
+                    .iter()
+                    .map(|row| {
+                        row.iter()
+                            .map(|x| {
+                                let value = x.eval(ctx, env)?;
+                                matrix::scalar(&value).map_err(|e| e.at(x.span()))
+                            })
+                            .collect::<Result<Vec<_>, _>>()
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::Matrix(Matrix::new(rows).unwrap()))
+            }
+            Expr::Equation { span, .. } => Err(CalcError::Syntax(
+                "Equations are only understood by 'solve'".into(),
+            )
+            .at(span.clone())),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Literal { span, .. }
+            | Expr::Ident { span, .. }
+            | Expr::Unary { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Matrix { span, .. }
+            | Expr::Equation { span, .. } => span.clone(),
+        }
+    }
+
+    pub(crate) fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Expr::Literal { span, .. }
+            | Expr::Ident { span, .. }
+            | Expr::Unary { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Matrix { span, .. }
+            | Expr::Equation { span, .. } => span,
+        }
+    }
+
+    /// Binding strength when printed, operands bind tighter than any operator.
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary { op, .. } => OPERATORS[*op].precedence,
+            Expr::Unary { .. } => u8::MAX - 1,
+            _ => u8::MAX,
+        }
+    }
+}
+
+/// Evaluate a call, kept out of `Expr::eval` so that deep recursion through
+/// user functions does not carry its locals in every frame.
+fn call(
+    name: &str,
+    args: &[Expr],
+    span: &Span,
+    ctx: &FunctionContext,
+    env: &Environment,
+) -> Result<Value, CalcError> {
+    let func = resolve_call(name, args.len(), span, env)?;
+    if let Relation::Special(_, special) = func.relation.select(args.len()) {
+        return special(ctx, env, args).map_err(|e| e.at(span.clone()));
+    }
+    let args = args
+        .iter()
+        .map(|arg| arg.eval(ctx, env))
+        .collect::<Result<Vec<_>, _>>()?;
+    // spans inside a user function body refer to the body, not the input
+    func.apply(ctx, env, &args)
+        .map(|x| value::conform(ctx, x))
+        .map_err(|e| e.without_span().at(span.clone()))
+}
+
+/// The function a call of `name` with `count` arguments refers to, errors
+/// point at the name.
+pub(crate) fn resolve_call(
+    name: &str,
+    count: usize,
+    span: &Span,
+    env: &Environment,
+) -> Result<Function, CalcError> {
+    let name_span = span.start..span.start + name.len();
+    let func = resolve(name, env).map_err(|e| e.at(name_span.clone()))?;
+    if !func.accepts(count) {
+        return Err(CalcError::Parser(format!(
+            "Function '{}' takes {}, got {}",
+            name,
+            func.describe_arity(),
+            count
+        ))
+        .at(name_span));
+    }
+    Ok(func)
+}
+
+fn resolve(name: &str, env: &Environment) -> Result<Function, CalcError> {
+    if let Some(func) = FUNCTIONS.get(name) {
+        return Ok(func.clone());
+    }
+    match env.function(name) {
+        Some(func) => Ok(Function {
+            token: Cow::Owned(func.name.clone()),
+            relation: Relation::User(func.clone()),
+        }),
+        None => Err(CalcError::Syntax(format!("Unknown function '{}'", name))),
+    }
+}
+
+/// Prints `expr`, parenthesized if it binds looser than `precedence`.
+fn operand(f: &mut fmt::Formatter<'_>, expr: &Expr, precedence: u8) -> fmt::Result {
+    if expr.precedence() < precedence {
+        write!(f, "({})", expr)
+    } else {
+        write!(f, "{}", expr)
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Literal { value: x, .. } => {
+                // `1/2`, `-1` or `1+2i` would not read back as a single operand
+                let x = x.to_string();
+                if x.chars().all(|c| c.is_ascii_digit() || c == '.') {
+                    write!(f, "{}", x)
+                } else {
+                    write!(f, "({})", x)
+                }
+            }
+            Expr::Ident { name, .. } => write!(f, "{}", name),
+            Expr::Unary { op, expr, .. } => match op {
+                UnaryOp::Neg | UnaryOp::BitNot | UnaryOp::Not => {
+                    let symbol = match op {
+                        UnaryOp::Neg => "-",
+                        UnaryOp::BitNot => "~",
+                        _ => "!",
+                    };
+                    write!(f, "{}", symbol)?;
+                    operand(f, expr, u8::MAX)
+                }
+                UnaryOp::Factorial => {
+                    operand(f, expr, u8::MAX)?;
+                    write!(f, "!")
+                }
+            },
+            Expr::Binary { op, lhs, rhs, .. } => {
+                let operator = &OPERATORS[*op];
+                let (left, right) = match operator.is_left_associative {
+                    true => (operator.precedence, operator.precedence + 1),
+                    false => (operator.precedence + 1, operator.precedence),
+                };
+                operand(f, lhs, left)?;
+                write!(f, " {} ", op)?;
+                operand(f, rhs, right)
+            }
+            Expr::Call { name, args, .. } => {
+                let args: Vec<_> = args.iter().map(|arg| arg.to_string()).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+            Expr::Matrix { rows, .. } => {
+                let rows: Vec<_> = rows
+                    .iter()
+                    .map(|row| {
+                        let row: Vec<_> = row.iter().map(|x| x.to_string()).collect();
+                        row.join(", ")
+                    })
+                    .collect();
+                write!(f, "[{}]", rows.join("; "))
+            }
+            Expr::Equation { lhs, rhs, .. } => write!(f, "{} = {}", lhs, rhs),
+        }
+    }
+}
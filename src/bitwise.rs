@@ -0,0 +1,88 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Bitwise operators, computed on big integers in two's complement so that
+//! negative operands behave as they do in C. Floats past 2^53 are rejected,
+//! they no longer stand for a single integer.
+
+use num::{BigInt, Integer, ToPrimitive};
+
+use crate::error::{CalcError, MathError};
+use crate::integer::{from_value, like};
+use crate::value::Value;
+
+/// Largest shift accepted, beyond it results would not fit in memory.
+const MAX_SHIFT: i64 = 100_000;
+
+pub fn is_bitwise(token: &str) -> bool {
+    matches!(token, "&" | "|" | "xor" | "<<" | ">>")
+}
+
+/// `x op y` for the operators of `is_bitwise`, in the representation of the
+/// operands.
+pub fn operate(token: &str, x: &Value, y: &Value) -> Result<Value, CalcError> {
+    let (a, b) = (from_value(x)?, from_value(y)?);
+    let result = match token {
+        "&" => a & b,
+        "|" => a | b,
+        "xor" => a ^ b,
+        "<<" => shift(a, shift_amount(&b)?),
+        ">>" => shift(a, -shift_amount(&b)?),
+        _ => unreachable!(),
+    };
+    like(&[x, y], result)
+}
+
+/// `~x`, the bitwise complement.
+pub fn not(x: &Value) -> Result<Value, CalcError> {
+    like(&[x], !from_value(x)?)
+}
+
+fn shift_amount(n: &BigInt) -> Result<i64, CalcError> {
+    n.to_i64()
+        .filter(|n| n.abs() <= MAX_SHIFT)
+        .ok_or(CalcError::Math(MathError::TooLarge))
+}
+
+/// `n` shifted left by `amount`, or right if it is negative, rounding down.
+fn shift(n: BigInt, amount: i64) -> BigInt {
+    if amount >= 0 {
+        n << amount as usize
+    } else {
+        n.div_floor(&(BigInt::from(1) << (-amount) as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_operands() {
+        let f = Value::Float;
+        assert_eq!(operate("&", &f(-1.), &f(12.)), Ok(f(12.)));
+        assert_eq!(operate(">>", &f(-5.), &f(1.)), Ok(f(-3.)));
+        assert_eq!(operate("<<", &f(3.), &f(-1.)), Ok(f(1.)));
+        assert_eq!(not(&f(-1.)), Ok(f(0.)));
+        assert_eq!(
+            operate("|", &f(1.5), &f(1.)),
+            Err(CalcError::Math(MathError::NotAnInteger))
+        );
+    }
+    #[test]
+    fn floats_past_exact_integers() {
+        let f = Value::Float;
+        let too_large = Err(CalcError::Math(MathError::ArgumentTooLarge((1 << 53) - 1)));
+        // 2^53 + 1 and 2^64 - 1 read as even floats
+        assert_eq!(operate("&", &f(9007199254740993.), &f(1.)), too_large);
+        assert_eq!(operate("&", &f(u64::MAX as f64), &f(1.)), too_large);
+        assert_eq!(not(&f(-2f64.powi(60))), too_large);
+        assert_eq!(
+            operate("|", &f(2f64.powi(52)), &f(1.)),
+            Ok(f(2f64.powi(52) + 1.))
+        );
+        let n = |n: u64| Value::from(BigInt::from(n));
+        assert_eq!(operate("&", &n(u64::MAX), &n(1)), Ok(n(1)));
+    }
+}
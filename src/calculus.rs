@@ -0,0 +1,329 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Numerical derivatives and integrals of expressions in a bound variable,
+//! e.g. `integrate(x^2, x, 0, 1)`. Both estimate their own error and report
+//! a result they cannot vouch for as a convergence error.
+
+use std::collections::BinaryHeap;
+
+use crate::ast::Expr;
+use crate::env::Environment;
+use crate::error::{CalcError, MathError};
+use crate::lex::FunctionContext;
+use crate::value::Value;
+
+/// Relative accuracy asked of derivatives and integrals.
+const TOLERANCE: f64 = 1e-10;
+
+/// Most subintervals an integral is split into.
+const MAX_INTERVALS: usize = 1000;
+
+/// Gauss-Kronrod nodes on [0, 1), the odd ones are the Gauss nodes.
+const KRONROD_NODES: [f64; 8] = [
+    0.991_455_371_120_812_6,
+    0.949_107_912_342_758_5,
+    0.864_864_423_359_769_1,
+    0.741_531_185_599_394_4,
+    0.586_087_235_467_691_1,
+    0.405_845_151_377_397_2,
+    0.207_784_955_007_898_5,
+    0.,
+];
+
+const KRONROD_WEIGHTS: [f64; 8] = [
+    0.022_935_322_010_529_22,
+    0.063_092_092_629_978_55,
+    0.104_790_010_322_250_2,
+    0.140_653_259_715_525_9,
+    0.169_004_726_639_267_9,
+    0.190_350_578_064_785_4,
+    0.204_432_940_075_298_9,
+    0.209_482_141_084_727_8,
+];
+
+/// Weights of the 7 point Gauss rule on the odd Kronrod nodes.
+const GAUSS_WEIGHTS: [f64; 4] = [
+    0.129_484_966_168_869_7,
+    0.279_705_391_489_276_7,
+    0.381_830_050_505_118_9,
+    0.417_959_183_673_469_4,
+];
+
+/// `deriv(expr, x, at)`, by Ridders' extrapolation of central differences.
+pub fn deriv(ctx: &FunctionContext, env: &Environment, args: &[Expr]) -> Result<Value, CalcError> {
+    let mut f = Bound::new("deriv", ctx, env, &args[0], &args[1])?;
+    let x = real("deriv", &args[2], ctx, env)?;
+    // the scale of `x`, shrunk while the function is undefined that far away
+    let mut h = 0.1 * (x.abs() / 1000.).max(1.);
+    let mut attempts = 0;
+    let (derivative, error) = loop {
+        match ridders(&mut f, x, h) {
+            Err(_) if attempts < 6 => {
+                h /= 10.;
+                attempts += 1;
+            }
+            result => break result?,
+        }
+    };
+    if error.is_nan() || error > 1e3 * TOLERANCE * derivative.abs().max(1.) {
+        return Err(not_converged("derivative", error));
+    }
+    Ok(Value::Float(derivative))
+}
+
+/// `integrate(expr, x, a, b)`, by adaptive Gauss-Kronrod quadrature.
+pub fn integrate(
+    ctx: &FunctionContext,
+    env: &Environment,
+    args: &[Expr],
+) -> Result<Value, CalcError> {
+    let mut f = Bound::new("integrate", ctx, env, &args[0], &args[1])?;
+    let a = real("integrate", &args[2], ctx, env)?;
+    let b = real("integrate", &args[3], ctx, env)?;
+    if !a.is_finite() || !b.is_finite() {
+        return Err(CalcError::Math(MathError::OutOfBounds));
+    }
+    if a == b {
+        return Ok(Value::Float(0.));
+    }
+    // the interval with the largest error is split until the sum is accurate
+    let mut intervals = BinaryHeap::new();
+    intervals.push(kronrod(&mut f, a, b)?);
+    loop {
+        let total: f64 = intervals.iter().map(|i: &Interval| i.integral).sum();
+        let error: f64 = intervals.iter().map(|i| i.error).sum();
+        if error <= TOLERANCE * total.abs().max(1.) {
+            return Ok(Value::Float(total));
+        }
+        let worst = intervals.pop().unwrap();
+        let mid = (worst.a + worst.b) / 2.;
+        if intervals.len() + 2 > MAX_INTERVALS || mid == worst.a || mid == worst.b {
+            return Err(not_converged("integral", error));
+        }
+        intervals.push(kronrod(&mut f, worst.a, mid)?);
+        intervals.push(kronrod(&mut f, mid, worst.b)?);
+    }
+}
+
+/// An expression as a function of one of its variables.
+pub(crate) struct Bound<'a> {
+    function: &'static str,
+    ctx: &'a FunctionContext,
+    scope: Environment,
+    name: &'a str,
+    body: &'a Expr,
+}
+
+impl<'a> Bound<'a> {
+    pub(crate) fn new(
+        function: &'static str,
+        ctx: &'a FunctionContext,
+        env: &Environment,
+        body: &'a Expr,
+        variable: &'a Expr,
+    ) -> Result<Bound<'a>, CalcError> {
+        let name = match variable {
+            Expr::Ident { name, .. } => name,
+            other => {
+                return Err(CalcError::Syntax(format!(
+                    "Function '{}' expected a variable name, got '{}'",
+                    function, other
+                ))
+                .at(other.span()))
+            }
+        };
+        let mut scope = env.clone();
+        scope
+            .bind(name, Value::Float(0.))
+            .map_err(|e| e.at(variable.span()))?;
+        Ok(Bound {
+            function,
+            ctx,
+            scope,
+            name,
+            body,
+        })
+    }
+
+    pub(crate) fn at(&mut self, x: f64) -> Result<f64, CalcError> {
+        self.scope.bind(self.name, Value::Float(x))?;
+        let y = self.body.eval(self.ctx, &self.scope)?;
+        as_real(self.function, &y).map_err(|e| e.at(self.body.span()))
+    }
+}
+
+/// Central differences at `h`, `h / 1.4`, ... extrapolated to a step of 0,
+/// and an estimate of the error.
+fn ridders(f: &mut Bound, x: f64, h: f64) -> Result<(f64, f64), CalcError> {
+    const SHRINK: f64 = 1.4;
+    const STEPS: usize = 12;
+    let mut table = [[0.; STEPS]; STEPS];
+    let mut h = h;
+    let (mut best, mut error) = (f64::NAN, f64::INFINITY);
+    for i in 0..STEPS {
+        table[0][i] = (f.at(x + h)? - f.at(x - h)?) / (2. * h);
+        let mut factor = SHRINK * SHRINK;
+        for j in 1..=i {
+            table[j][i] = (table[j - 1][i] * factor - table[j - 1][i - 1]) / (factor - 1.);
+            factor *= SHRINK * SHRINK;
+            let change = (table[j][i] - table[j - 1][i])
+                .abs()
+                .max((table[j][i] - table[j - 1][i - 1]).abs());
+            if change <= error {
+                error = change;
+                best = table[j][i];
+            }
+        }
+        // higher orders only made it worse
+        if i > 0 && (table[i][i] - table[i - 1][i - 1]).abs() >= 2. * error {
+            break;
+        }
+        h /= SHRINK;
+    }
+    Ok((best, error))
+}
+
+/// Piece of an integral, ordered by its error estimate.
+struct Interval {
+    a: f64,
+    b: f64,
+    integral: f64,
+    error: f64,
+}
+
+impl PartialEq for Interval {
+    fn eq(&self, other: &Self) -> bool {
+        self.error == other.error
+    }
+}
+
+impl Eq for Interval {}
+
+impl PartialOrd for Interval {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Interval {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.error.total_cmp(&other.error)
+    }
+}
+
+/// 15 point Kronrod rule over `[a, b]`, its difference to the embedded
+/// Gauss rule estimates the error as in QUADPACK.
+fn kronrod(f: &mut Bound, a: f64, b: f64) -> Result<Interval, CalcError> {
+    let center = (a + b) / 2.;
+    let half = (b - a) / 2.;
+    let y0 = f.at(center)?;
+    let mut kronrod = y0 * KRONROD_WEIGHTS[7];
+    let mut gauss = y0 * GAUSS_WEIGHTS[3];
+    let mut absolute = kronrod.abs();
+    let mut values = vec![(y0, y0)];
+    for (i, (node, weight)) in KRONROD_NODES
+        .iter()
+        .zip(KRONROD_WEIGHTS)
+        .take(7)
+        .enumerate()
+    {
+        let (left, right) = (f.at(center - half * node)?, f.at(center + half * node)?);
+        kronrod += weight * (left + right);
+        absolute += weight * (left.abs() + right.abs());
+        if i % 2 == 1 {
+            gauss += GAUSS_WEIGHTS[i / 2] * (left + right);
+        }
+        values.push((left, right));
+    }
+    let mean = kronrod / 2.;
+    let mut deviation = KRONROD_WEIGHTS[7] * (y0 - mean).abs();
+    for ((left, right), weight) in values[1..].iter().zip(KRONROD_WEIGHTS) {
+        deviation += weight * ((left - mean).abs() + (right - mean).abs());
+    }
+    let (integral, absolute, deviation) = (
+        kronrod * half,
+        absolute * half.abs(),
+        deviation * half.abs(),
+    );
+    let mut error = ((kronrod - gauss) * half).abs();
+    if deviation != 0. && error != 0. {
+        error = deviation * (200. * error / deviation).powf(1.5).min(1.);
+    }
+    // no better than the rounding of the sum
+    error = error.max(50. * f64::EPSILON * absolute);
+    Ok(Interval {
+        a,
+        b,
+        integral,
+        error,
+    })
+}
+
+/// The real number `arg` evaluates to.
+pub(crate) fn real(
+    function: &str,
+    arg: &Expr,
+    ctx: &FunctionContext,
+    env: &Environment,
+) -> Result<f64, CalcError> {
+    let x = arg.eval(ctx, env)?;
+    as_real(function, &x).map_err(|e| e.at(arg.span()))
+}
+
+fn as_real(function: &str, x: &Value) -> Result<f64, CalcError> {
+    match x {
+        Value::Float(_) | Value::Decimal(_) | Value::Rational(_) | Value::Factors(_) => {
+            Ok(x.to_f64())
+        }
+        x => Err(CalcError::Math(MathError::Type(format!(
+            "Function '{}' needs real numbers, got {}",
+            function,
+            x.kind()
+        )))),
+    }
+}
+
+fn not_converged(what: &str, error: f64) -> CalcError {
+    CalcError::Math(MathError::Convergence(format!(
+        "The {} did not converge, estimated error {:.1e}",
+        what, error
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+
+    fn call(
+        f: fn(&FunctionContext, &Environment, &[Expr]) -> Result<Value, CalcError>,
+        args: &[&str],
+    ) -> Result<f64, CalcError> {
+        let ctx = FunctionContext::default();
+        let args: Vec<Expr> = args
+            .iter()
+            .map(|a| parse_expr(&ctx, a, None).unwrap())
+            .collect();
+        f(&ctx, &Environment::new(), &args).map(|x| x.to_f64())
+    }
+
+    #[test]
+    fn smooth_and_singular() {
+        let close = |x: f64, y: f64| (x - y).abs() <= 1e-9 * y.abs().max(1.);
+        assert!(close(call(deriv, &["x^3", "x", "2"]).unwrap(), 12.));
+        assert!(close(call(deriv, &["ln(x)", "x", "0.001"]).unwrap(), 1000.));
+        assert!(close(call(integrate, &["x^2", "x", "0", "3"]).unwrap(), 9.));
+        // integrable singularity at an end point
+        assert!(close(
+            call(integrate, &["1/sqrt(x)", "x", "0", "1"]).unwrap(),
+            2.
+        ));
+        assert!(close(call(integrate, &["x", "x", "1", "-1"]).unwrap(), 0.));
+        assert!(matches!(
+            call(integrate, &["1/x", "x", "0", "1"]),
+            Err(CalcError::Math(MathError::Convergence(_)))
+        ));
+    }
+}
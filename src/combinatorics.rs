@@ -0,0 +1,147 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Counting functions on exact integers. Products are built up one factor
+//! at a time and divided as they go, so that no intermediate grows much past
+//! the result the way `n! / (r! (n - r)!)` does.
+
+use num::{BigInt, One, Signed, ToPrimitive, Zero};
+
+use crate::error::{CalcError, MathError};
+
+/// Largest number of factors a product is built from.
+const MAX_FACTORS: u64 = 100_000;
+
+/// Largest `n` whose triangle of Stirling or Bell numbers is tabulated.
+const MAX_TRIANGLE: u64 = 1_000;
+
+/// `n choose r`, 0 when `r > n`.
+pub fn ncr(n: &BigInt, r: &BigInt) -> Result<BigInt, CalcError> {
+    natural(n)?;
+    natural(r)?;
+    if r > n {
+        return Ok(BigInt::zero());
+    }
+    let r = std::cmp::min(r.clone(), n - r);
+    let steps = factors(&r)?;
+    let base = n - &r;
+    // every prefix is itself a binomial coefficient, so the division is exact
+    Ok((1..=steps).fold(BigInt::one(), |c, i| c * (&base + i) / i))
+}
+
+/// Ordered selections of `r` out of `n`, `n! / (n - r)!`.
+pub fn npr(n: &BigInt, r: &BigInt) -> Result<BigInt, CalcError> {
+    natural(n)?;
+    let steps = factors(r)?;
+    if r > n {
+        return Ok(BigInt::zero());
+    }
+    Ok((0..steps).fold(BigInt::one(), |p, i| p * (n - i)))
+}
+
+/// `(k1 + k2 + ...)! / (k1! k2! ...)`, as a product of binomials.
+pub fn multinomial(ks: &[BigInt]) -> Result<BigInt, CalcError> {
+    let mut total = BigInt::zero();
+    let mut result = BigInt::one();
+    for k in ks {
+        natural(k)?;
+        total += k;
+        result *= ncr(&total, k)?;
+    }
+    Ok(result)
+}
+
+/// The `n`th Catalan number, `(2n choose n) / (n + 1)`.
+pub fn catalan(n: &BigInt) -> Result<BigInt, CalcError> {
+    natural(n)?;
+    Ok(ncr(&(n * 2), n)? / (n + 1))
+}
+
+/// Ways to partition `n` elements into `k` non-empty sets.
+pub fn stirling2(n: &BigInt, k: &BigInt) -> Result<BigInt, CalcError> {
+    let n = triangle_size(n)?;
+    natural(k)?;
+    if *k > BigInt::from(n) {
+        return Ok(BigInt::zero());
+    }
+    let k = k.to_usize().unwrap_or_default();
+    // row i holds S(i, 0..=k), S(i, j) = j S(i - 1, j) + S(i - 1, j - 1)
+    let mut row = vec![BigInt::zero(); k + 1];
+    row[0] = BigInt::one();
+    for _ in 0..n {
+        for j in (1..=k).rev() {
+            row[j] = &row[j] * j + &row[j - 1];
+        }
+        row[0] = BigInt::zero();
+    }
+    Ok(row.swap_remove(k))
+}
+
+/// Ways to partition `n` elements into any number of non-empty sets.
+pub fn bell(n: &BigInt) -> Result<BigInt, CalcError> {
+    let n = triangle_size(n)?;
+    // Bell's triangle, each row starts with the last entry of the previous
+    let mut row = vec![BigInt::one()];
+    for _ in 0..n {
+        let mut next = Vec::with_capacity(row.len() + 1);
+        next.push(row[row.len() - 1].clone());
+        for x in &row {
+            let entry = &next[next.len() - 1] + x;
+            next.push(entry);
+        }
+        row = next;
+    }
+    Ok(row.swap_remove(0))
+}
+
+fn natural(n: &BigInt) -> Result<(), CalcError> {
+    if n.is_negative() {
+        Err(CalcError::Math(MathError::OutOfBounds))
+    } else {
+        Ok(())
+    }
+}
+
+/// `n` as a number of factors, if it is not too many.
+fn factors(n: &BigInt) -> Result<u64, CalcError> {
+    natural(n)?;
+    n.to_u64()
+        .filter(|&n| n <= MAX_FACTORS)
+        .ok_or(CalcError::Math(MathError::ArgumentTooLarge(MAX_FACTORS)))
+}
+
+fn triangle_size(n: &BigInt) -> Result<usize, CalcError> {
+    natural(n)?;
+    n.to_u64()
+        .filter(|&n| n <= MAX_TRIANGLE)
+        .map(|n| n as usize)
+        .ok_or(CalcError::Math(MathError::ArgumentTooLarge(MAX_TRIANGLE)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn n(n: i64) -> BigInt {
+        BigInt::from(n)
+    }
+
+    #[test]
+    fn small_values() {
+        assert_eq!(ncr(&n(5), &n(2)), Ok(n(10)));
+        assert_eq!(ncr(&n(3), &n(5)), Ok(n(0)));
+        assert_eq!(npr(&n(5), &n(2)), Ok(n(20)));
+        assert_eq!(multinomial(&[n(2), n(1), n(1)]), Ok(n(12)));
+        let catalans: Vec<_> = (0..7).map(|i| catalan(&n(i)).unwrap()).collect();
+        assert_eq!(catalans, [1, 1, 2, 5, 14, 42, 132].map(n));
+        assert_eq!(stirling2(&n(5), &n(2)), Ok(n(15)));
+        assert_eq!(stirling2(&n(0), &n(0)), Ok(n(1)));
+        let bells: Vec<_> = (0..7).map(|i| bell(&n(i)).unwrap()).collect();
+        assert_eq!(bells, [1, 1, 2, 5, 15, 52, 203].map(n));
+        assert_eq!(
+            ncr(&n(-1), &n(0)),
+            Err(CalcError::Math(MathError::OutOfBounds))
+        );
+    }
+}
@@ -0,0 +1,131 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Complex counterparts of the built-in operators and functions, used when
+//! an argument is complex or a real result would leave the reals.
+
+use num::complex::Complex64;
+use num::Zero;
+
+use crate::error::{CalcError, MathError};
+use crate::lex::{self, FunctionContext};
+
+/// Largest exponent `^` evaluates by repeated multiplication.
+const MAX_EXPONENT: f64 = 1024.;
+
+fn rad(ctx: &FunctionContext, z: Complex64) -> Complex64 {
+    z * lex::rad(ctx, 1.)
+}
+
+fn pow(x: Complex64, y: Complex64) -> Result<Complex64, CalcError> {
+    if y.im == 0. && y.re.fract() == 0. && y.re.abs() <= MAX_EXPONENT {
+        // keeps `i^2` exactly real
+        if x.is_zero() && y.re < 0. {
+            return Err(CalcError::Math(MathError::TooLarge));
+        }
+        return Ok(x.powi(y.re as i32));
+    }
+    if x.is_zero() {
+        return match y.re > 0. {
+            true => Ok(Complex64::zero()),
+            false => Err(CalcError::Math(MathError::OutOfBounds)),
+        };
+    }
+    Ok(x.powc(y))
+}
+
+/// Complex `x op y`, `None` for operators without a complex meaning.
+pub fn operate(token: &str, x: Complex64, y: Complex64) -> Option<Result<Complex64, CalcError>> {
+    let result = match token {
+        "+" => Ok(x + y),
+        "-" => Ok(x - y),
+        "*" => Ok(x * y),
+        "/" if y.is_zero() => Err(CalcError::Math(MathError::DivideByZero)),
+        "/" => Ok(x / y),
+        "^" => pow(x, y),
+        _ => return None,
+    };
+    Some(result.and_then(|z| match z.is_finite() {
+        true => Ok(z),
+        false => Err(CalcError::Math(MathError::TooLarge)),
+    }))
+}
+
+/// Complex counterparts of `lex::FUNCTIONS`, `None` for functions that are
+/// only defined on the reals (`floor`, `ceil`, ...).
+pub fn apply(name: &str, ctx: &FunctionContext, args: &[Complex64]) -> Option<Complex64> {
+    let x = args[0];
+    let one = Complex64::new(1., 0.);
+    let result = match name {
+        "sin" => rad(ctx, x).sin(),
+        "cos" => rad(ctx, x).cos(),
+        "tan" => rad(ctx, x).tan(),
+        "csc" => rad(ctx, x).sin().inv(),
+        "sec" => rad(ctx, x).cos().inv(),
+        "cot" => rad(ctx, x).tan().inv(),
+        "sinh" => x.sinh(),
+        "cosh" => x.cosh(),
+        "tanh" => x.tanh(),
+        "ln" => x.ln(),
+        "log2" => x.log2(),
+        "log10" => x.log10(),
+        "sqrt" => x.sqrt(),
+        "rad" => x * std::f64::consts::PI / 180.,
+        "deg" => x * 180. / std::f64::consts::PI,
+        "abs" => Complex64::from(x.norm()),
+        "asin" => x.asin(),
+        "acos" => x.acos(),
+        "atan" => x.atan(),
+        "acsc" => x.inv().asin(),
+        "asec" => x.inv().acos(),
+        "acot" => x.inv().atan(),
+        "exp" => x.exp(),
+        "exp2" => x.exp2(),
+        "log" => x.ln() / args.get(1).map_or(Complex64::from(10.), |base| *base).ln(),
+        "nroot" => x.powc(one / args[1]),
+        "re" => Complex64::from(x.re),
+        "im" => Complex64::from(x.im),
+        "arg" => Complex64::from(lex::from_rad(ctx, x.arg())),
+        "conj" => x.conj(),
+        _ => return None,
+    };
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex::AngleUnit;
+
+    fn c(re: f64, im: f64) -> Complex64 {
+        Complex64::new(re, im)
+    }
+
+    #[test]
+    fn arithmetic() {
+        let i = c(0., 1.);
+        assert_eq!(operate("*", i, i), Some(Ok(c(-1., 0.))));
+        assert_eq!(operate("^", i, c(2., 0.)), Some(Ok(c(-1., 0.))));
+        assert_eq!(operate("/", c(1., 1.), c(1., -1.)), Some(Ok(i)));
+        assert_eq!(
+            operate("/", i, c(0., 0.)),
+            Some(Err(CalcError::Math(MathError::DivideByZero)))
+        );
+        assert_eq!(operate("%", i, i), None);
+    }
+
+    #[test]
+    fn functions() {
+        let ctx = FunctionContext {
+            angle_unit: AngleUnit::Radian,
+            ..Default::default()
+        };
+        assert_eq!(apply("sqrt", &ctx, &[c(-4., 0.)]), Some(c(0., 2.)));
+        assert_eq!(apply("abs", &ctx, &[c(3., 4.)]), Some(c(5., 0.)));
+        assert_eq!(apply("conj", &ctx, &[c(3., 4.)]), Some(c(3., -4.)));
+        let ln = apply("ln", &ctx, &[c(-1., 0.)]).unwrap();
+        assert_eq!(ln, c(0., std::f64::consts::PI));
+        assert_eq!(apply("floor", &ctx, &[c(1., 1.)]), None);
+    }
+}
@@ -0,0 +1,1126 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Arbitrary precision decimal arithmetic.
+//!
+//! Transcendental functions are evaluated on fixed point integers (a `BigInt`
+//! scaled by `10^w`) with a few guard digits and rounded afterwards.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use num::{BigInt, BigRational, Integer, One, Signed, ToPrimitive, Zero};
+
+use crate::error::{CalcError, MathError};
+use crate::lex::{AngleUnit, FunctionContext};
+use crate::special;
+use crate::stats;
+
+/// Extra digits carried by every value and every intermediate result.
+pub const GUARD: usize = 10;
+
+/// `mantissa * 10^exponent`, rounded to `precision` significant digits.
+#[derive(Debug, Clone)]
+pub struct Decimal {
+    mantissa: BigInt,
+    exponent: i64,
+    precision: usize,
+}
+
+fn pow10(n: usize) -> BigInt {
+    num::pow(BigInt::from(10), n)
+}
+
+fn digits(m: &BigInt) -> usize {
+    m.magnitude().to_string().len()
+}
+
+/// Integer division rounding half away from zero.
+fn div_round(a: &BigInt, b: &BigInt) -> BigInt {
+    let (q, r) = a.div_rem(b);
+    if r.abs() * 2 >= b.abs() {
+        q + a.signum() * b.signum()
+    } else {
+        q
+    }
+}
+
+fn out_of_bounds() -> CalcError {
+    CalcError::Math(MathError::OutOfBounds)
+}
+
+impl Decimal {
+    pub fn new(mut mantissa: BigInt, mut exponent: i64, precision: usize) -> Self {
+        let len = digits(&mantissa);
+        if len > precision {
+            let drop = len - precision;
+            mantissa = div_round(&mantissa, &pow10(drop));
+            exponent += drop as i64;
+        }
+        if mantissa.is_zero() {
+            exponent = 0;
+        } else {
+            let ten = BigInt::from(10);
+            while (&mantissa % &ten).is_zero() {
+                mantissa /= &ten;
+                exponent += 1;
+            }
+        }
+        Decimal {
+            mantissa,
+            exponent,
+            precision,
+        }
+    }
+
+    pub fn from_int(n: impl Into<BigInt>, precision: usize) -> Self {
+        Decimal::new(n.into(), 0, precision)
+    }
+
+    /// Parse a plain (`12.5`) or scientific (`1.25e1`) decimal literal.
+    pub fn parse(literal: &str, precision: usize) -> Option<Self> {
+        let (number, exponent) = match literal.split_once(['e', 'E']) {
+            Some((number, exponent)) => (number, exponent.parse::<i64>().ok()?),
+            None => (literal, 0),
+        };
+        let (sign, number) = match number.strip_prefix('-') {
+            Some(number) => (-1, number),
+            None => (1, number),
+        };
+        let (integral, fract) = number.split_once('.').unwrap_or((number, ""));
+        let all_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+        if integral.len() + fract.len() == 0 || !all_digits(integral) || !all_digits(fract) {
+            return None;
+        }
+        let mantissa: BigInt = format!("{}{}", integral, fract).parse().ok()?;
+        Some(Decimal::new(
+            mantissa * sign,
+            exponent - fract.len() as i64,
+            precision,
+        ))
+    }
+
+    pub fn from_f64(x: f64, precision: usize) -> Option<Self> {
+        if !x.is_finite() {
+            return None;
+        }
+        Decimal::parse(&format!("{:e}", x), precision)
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        format!("{}e{}", self.mantissa, self.exponent)
+            .parse()
+            .unwrap_or(f64::NAN)
+    }
+
+    /// The exact value as a fraction.
+    pub fn to_rational(&self) -> BigRational {
+        let ten = BigRational::from_integer(BigInt::from(10));
+        BigRational::from_integer(self.mantissa.clone()) * ten.pow(self.exponent as i32)
+    }
+
+    /// Nearest decimal to `r`, `None` if the denominator is zero.
+    pub fn from_rational(r: &BigRational, precision: usize) -> Option<Self> {
+        Decimal::from_int(r.numer().clone(), precision)
+            .div(&Decimal::from_int(r.denom().clone(), precision))
+            .ok()
+    }
+
+    pub fn precision(&self) -> usize {
+        self.precision
+    }
+
+    pub fn with_precision(&self, precision: usize) -> Self {
+        Decimal::new(self.mantissa.clone(), self.exponent, precision)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mantissa.is_zero()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.mantissa.is_negative()
+    }
+
+    pub fn is_integer(&self) -> bool {
+        self.exponent >= 0
+    }
+
+    /// `floor(log10(|self|))`, zero has magnitude 0.
+    pub fn magnitude(&self) -> i64 {
+        if self.is_zero() {
+            0
+        } else {
+            self.exponent + digits(&self.mantissa) as i64 - 1
+        }
+    }
+
+    pub fn neg(&self) -> Self {
+        Decimal {
+            mantissa: -&self.mantissa,
+            ..self.clone()
+        }
+    }
+
+    pub fn abs(&self) -> Self {
+        Decimal {
+            mantissa: self.mantissa.abs(),
+            ..self.clone()
+        }
+    }
+
+    pub fn add(&self, other: &Self) -> Self {
+        let precision = self.precision.max(other.precision);
+        if self.is_zero() {
+            return other.with_precision(precision);
+        } else if other.is_zero() {
+            return self.with_precision(precision);
+        }
+        // the smaller operand would be rounded away entirely
+        let gap = self.magnitude() - other.magnitude();
+        if gap > precision as i64 + 2 {
+            return self.with_precision(precision);
+        } else if -gap > precision as i64 + 2 {
+            return other.with_precision(precision);
+        }
+        let exponent = self.exponent.min(other.exponent);
+        let a = &self.mantissa * pow10((self.exponent - exponent) as usize);
+        let b = &other.mantissa * pow10((other.exponent - exponent) as usize);
+        Decimal::new(a + b, exponent, precision)
+    }
+
+    pub fn sub(&self, other: &Self) -> Self {
+        self.add(&other.neg())
+    }
+
+    pub fn mul(&self, other: &Self) -> Self {
+        Decimal::new(
+            &self.mantissa * &other.mantissa,
+            self.exponent + other.exponent,
+            self.precision.max(other.precision),
+        )
+    }
+
+    pub fn div(&self, other: &Self) -> Result<Self, CalcError> {
+        if other.is_zero() {
+            return Err(CalcError::Math(MathError::DivideByZero));
+        }
+        let precision = self.precision.max(other.precision);
+        let shift =
+            (precision + GUARD + digits(&other.mantissa)).saturating_sub(digits(&self.mantissa));
+        let q = div_round(&(&self.mantissa * pow10(shift)), &other.mantissa);
+        Ok(Decimal::new(
+            q,
+            self.exponent - other.exponent - shift as i64,
+            precision,
+        ))
+    }
+
+    /// Remainder with the sign of the dividend, like `f64::rem`.
+    pub fn rem(&self, other: &Self) -> Result<Self, CalcError> {
+        if other.is_zero() {
+            return Err(CalcError::Math(MathError::DivideByZero));
+        }
+        let exponent = self.exponent.min(other.exponent);
+        let a = &self.mantissa * pow10((self.exponent - exponent) as usize);
+        let b = &other.mantissa * pow10((other.exponent - exponent) as usize);
+        Ok(Decimal::new(
+            a % b,
+            exponent,
+            self.precision.max(other.precision),
+        ))
+    }
+
+    /// Integral part, rounded towards zero.
+    pub fn trunc(&self) -> BigInt {
+        if self.exponent >= 0 {
+            &self.mantissa * pow10(self.exponent as usize)
+        } else if -self.exponent as usize > digits(&self.mantissa) {
+            BigInt::zero()
+        } else {
+            &self.mantissa / pow10(-self.exponent as usize)
+        }
+    }
+
+    pub fn floor(&self) -> Self {
+        let t = self.trunc();
+        let t = if self.is_negative() && !self.is_integer() {
+            t - 1
+        } else {
+            t
+        };
+        Decimal::from_int(t, self.precision)
+    }
+
+    pub fn ceil(&self) -> Self {
+        let t = self.trunc();
+        let t = if !self.is_negative() && !self.is_integer() {
+            t + 1
+        } else {
+            t
+        };
+        Decimal::from_int(t, self.precision)
+    }
+
+    /// Round half away from zero, like `f64::round`.
+    pub fn round(&self) -> Self {
+        if self.is_integer() {
+            return self.clone();
+        }
+        let m = div_round(&self.mantissa, &pow10(-self.exponent as usize));
+        Decimal::from_int(m, self.precision)
+    }
+
+    /// Exact decimal representation rounded to `fix` decimal places.
+    pub fn to_fixed_string(&self, fix: usize) -> String {
+        let scaled = self.to_fixed(fix);
+        let sign = if scaled.is_negative() { "-" } else { "" };
+        let s = format!("{:0>width$}", scaled.magnitude(), width = fix + 1);
+        let (integral, fract) = s.split_at(s.len() - fix);
+        if fix == 0 {
+            format!("{}{}", sign, integral)
+        } else {
+            format!("{}{}.{}", sign, integral, fract)
+        }
+    }
+
+    /// The fractional part of `|self|` as `numerator / denominator`.
+    pub fn fract_parts(&self) -> (BigInt, BigInt) {
+        if self.exponent >= 0 {
+            return (BigInt::zero(), BigInt::one());
+        }
+        let denominator = pow10(-self.exponent as usize);
+        (self.mantissa.abs() % &denominator, denominator)
+    }
+
+    /// `self * 10^w` rounded to an integer.
+    fn to_fixed(&self, w: usize) -> BigInt {
+        let shift = self.exponent + w as i64;
+        if shift >= 0 {
+            &self.mantissa * pow10(shift as usize)
+        } else {
+            div_round(&self.mantissa, &pow10(-shift as usize))
+        }
+    }
+
+    fn from_fixed(x: BigInt, w: usize, precision: usize) -> Self {
+        Decimal::new(x, -(w as i64), precision)
+    }
+
+    /// Working digits for a result of `precision` digits.
+    fn work(&self) -> usize {
+        2 * (self.precision + GUARD)
+    }
+
+    pub fn pi(precision: usize) -> Self {
+        let w = precision + GUARD;
+        Decimal::from_fixed(pi_fixed(w), w, precision)
+    }
+
+    pub fn e(precision: usize) -> Self {
+        let w = precision + GUARD;
+        let s = pow10(w);
+        Decimal::from_fixed(exp_fixed(&s, &s), w, precision)
+    }
+
+    pub fn sqrt(&self) -> Result<Self, CalcError> {
+        if self.is_negative() {
+            return Err(out_of_bounds());
+        } else if self.is_zero() {
+            return Ok(self.clone());
+        }
+        let mut shift = self.work().saturating_sub(digits(&self.mantissa)) as i64;
+        if (self.exponent - shift) % 2 != 0 {
+            shift += 1;
+        }
+        let root = (&self.mantissa * pow10(shift as usize)).sqrt();
+        Ok(Decimal::new(
+            root,
+            (self.exponent - shift) / 2,
+            self.precision,
+        ))
+    }
+
+    pub fn exp(&self) -> Result<Self, CalcError> {
+        let precision = self.precision;
+        if self.is_zero() {
+            return Ok(Decimal::from_int(1, precision));
+        }
+        if self.magnitude() > 15 {
+            return if self.is_negative() {
+                Ok(Decimal::from_int(0, precision))
+            } else {
+                Err(CalcError::Math(MathError::TooLarge))
+            };
+        }
+        // exp(x) = 10^k * exp(x - k ln(10)), keeping the series argument small
+        let k = (self.to_f64() / std::f64::consts::LN_10).round() as i64;
+        let w = precision + GUARD + k.unsigned_abs().to_string().len();
+        let s = pow10(w);
+        let r = self.to_fixed(w) - ln10_fixed(w) * k;
+        Ok(Decimal::new(exp_fixed(&r, &s), k - w as i64, precision))
+    }
+
+    pub fn ln(&self) -> Result<Self, CalcError> {
+        if self.is_negative() || self.is_zero() {
+            return Err(out_of_bounds());
+        }
+        let magnitude = self.magnitude();
+        let w = self.work() + magnitude.unsigned_abs().to_string().len();
+        let s = pow10(w);
+        // scale into [1, 10) and then halve into [1, 1.5]
+        let len = digits(&self.mantissa);
+        let mut y = &self.mantissa * pow10(w + 1 - len);
+        let mut halvings = 0;
+        while &y * 2 > &s * 3 {
+            y /= 2;
+            halvings += 1;
+        }
+        let z = (&y - &s) * &s / (&y + &s);
+        let ln = atanh_fixed(&z, &s) * 2 + ln2_fixed(w) * halvings + ln10_fixed(w) * magnitude;
+        Ok(Decimal::from_fixed(ln, w, self.precision))
+    }
+
+    pub fn pow(&self, other: &Self) -> Result<Self, CalcError> {
+        let precision = self.precision.max(other.precision);
+        if other.is_integer() && other.magnitude() < 7 {
+            let n = other.trunc().to_i64().unwrap();
+            if self.is_zero() && n < 0 {
+                return Err(CalcError::Math(MathError::TooLarge));
+            }
+            let work = precision + GUARD + n.unsigned_abs().to_string().len();
+            let mut base = self.with_precision(work);
+            let mut result = Decimal::from_int(1, work);
+            let mut e = n.unsigned_abs();
+            while e > 0 {
+                if e & 1 == 1 {
+                    result = result.mul(&base);
+                }
+                base = base.mul(&base);
+                e >>= 1;
+            }
+            if n < 0 {
+                result = Decimal::from_int(1, work).div(&result)?;
+            }
+            return Ok(result.with_precision(precision));
+        }
+        if self.is_negative() {
+            return Err(out_of_bounds());
+        } else if self.is_zero() {
+            return if other.is_negative() {
+                Err(CalcError::Math(MathError::TooLarge))
+            } else {
+                Ok(self.clone())
+            };
+        }
+        // the exponent's magnitude is lost to the integral part of the product
+        let ln = self.ln()?;
+        let work = precision + GUARD + (other.magnitude() + ln.magnitude()).max(0) as usize;
+        let y = other
+            .with_precision(work)
+            .mul(&self.with_precision(work).ln()?);
+        Ok(y.exp()?.with_precision(precision))
+    }
+
+    /// `self!`, `Γ(self + 1)` for fractions.
+    pub fn factorial(&self) -> Result<Self, CalcError> {
+        if self.is_negative() {
+            return Err(out_of_bounds());
+        } else if !self.is_integer() {
+            return self.add(&Decimal::from_int(1, self.precision)).gamma();
+        }
+        let n = match self.trunc().to_u64() {
+            Some(n) if n <= 100_000 => n,
+            _ => return Err(CalcError::Math(MathError::TooLarge)),
+        };
+        let work = self.precision + GUARD;
+        let mut result = Decimal::from_int(1, work);
+        for i in 2..=n {
+            result = result.mul(&Decimal::from_int(i, work));
+        }
+        Ok(result.with_precision(self.precision))
+    }
+
+    /// Whether `self` is 0 or a negative integer, where gamma has its poles.
+    fn is_pole(&self) -> bool {
+        self.is_integer() && !self.is_positive()
+    }
+
+    fn is_positive(&self) -> bool {
+        self.mantissa.is_positive()
+    }
+
+    /// `Γ(self)`, exact for small positive integers.
+    pub fn gamma(&self) -> Result<Self, CalcError> {
+        if self.is_pole() {
+            return Err(out_of_bounds());
+        } else if self.is_integer() && self.magnitude() < 5 {
+            return self.sub(&Decimal::from_int(1, self.precision)).factorial();
+        }
+        let w = self.precision + GUARD;
+        let x = self.with_precision(w);
+        let gamma = if self.is_negative() {
+            // reflection, Γ(x) Γ(1 - x) = π / sin(πx)
+            let reflected = Decimal::from_int(1, w).sub(&x).gamma()?;
+            Decimal::pi(w).div(&sin_pi(&x)?.mul(&reflected))?
+        } else {
+            // the integral digits of the logarithm are lost to the exponent
+            let digits = log_digits(special::lgamma(self.to_f64()));
+            x.with_precision(w + digits).ln_gamma()?.exp()?
+        };
+        Ok(gamma.with_precision(self.precision))
+    }
+
+    /// `ln |Γ(self)|`.
+    pub fn lgamma(&self) -> Result<Self, CalcError> {
+        if self.is_pole() {
+            return Err(out_of_bounds());
+        } else if *self == Decimal::from_int(1, 1) || *self == Decimal::from_int(2, 1) {
+            return Ok(Decimal::from_int(0, self.precision));
+        }
+        self.near_roots(|x| {
+            if !x.is_negative() {
+                return x.ln_gamma();
+            }
+            // reflection, ln |Γ(x)| = ln π - ln |sin(πx)| - ln Γ(1 - x)
+            let w = x.precision;
+            let reflected = Decimal::from_int(1, w).sub(x).ln_gamma()?;
+            let ln_pi = Decimal::pi(w).ln()?;
+            Ok(ln_pi.sub(&sin_pi(x)?.abs().ln()?).sub(&reflected))
+        })
+    }
+
+    /// `ψ(self)`, the logarithmic derivative of `Γ(self)`.
+    pub fn digamma(&self) -> Result<Self, CalcError> {
+        if self.is_pole() {
+            return Err(out_of_bounds());
+        }
+        self.near_roots(|x| {
+            if !x.is_negative() {
+                return x.digamma_positive();
+            }
+            // reflection, ψ(1 - x) - ψ(x) = π cot(πx)
+            let w = x.precision;
+            let reflected = Decimal::from_int(1, w).sub(x).digamma_positive()?;
+            let (sin, cos, w_fixed) = x.sub(&x.round()).mul(&Decimal::pi(w)).sin_cos()?;
+            let cot =
+                Decimal::from_fixed(cos, w_fixed, w).div(&Decimal::from_fixed(sin, w_fixed, w))?;
+            Ok(reflected.sub(&Decimal::pi(w).mul(&cot)))
+        })
+    }
+
+    /// `B(self, other) = Γ(self) Γ(other) / Γ(self + other)`.
+    pub fn beta(&self, other: &Self) -> Result<Self, CalcError> {
+        if self.is_pole() || other.is_pole() {
+            return Err(out_of_bounds());
+        }
+        let precision = self.precision.max(other.precision);
+        let w = precision + GUARD;
+        let (a, b) = (self.with_precision(w), other.with_precision(w));
+        let sum = a.add(&b);
+        if sum.is_pole() {
+            return Ok(Decimal::from_int(0, precision));
+        }
+        let beta = if a.is_positive() && b.is_positive() {
+            // as logarithms, which cancel in all their integral digits
+            let w = w + log_digits(special::lgamma(sum.to_f64()));
+            let ln_gamma = |x: &Decimal| x.with_precision(w).ln_gamma();
+            ln_gamma(&a)?
+                .add(&ln_gamma(&b)?)
+                .sub(&ln_gamma(&sum)?)
+                .exp()?
+        } else {
+            a.gamma()?.mul(&b.gamma()?).div(&sum.gamma()?)?
+        };
+        Ok(beta.with_precision(precision))
+    }
+
+    /// `f(self)` rounded to `self.precision`, evaluated with more digits when
+    /// the result is small, as it is near the roots of `lgamma` and `digamma`.
+    fn near_roots(&self, f: impl Fn(&Self) -> Result<Self, CalcError>) -> Result<Self, CalcError> {
+        let w = self.precision + GUARD;
+        let mut result = f(&self.with_precision(w))?;
+        if result.magnitude() < 0 {
+            let leading_zeros = (-result.magnitude()) as usize;
+            result = f(&self.with_precision(w + leading_zeros))?;
+        }
+        Ok(result.with_precision(self.precision))
+    }
+
+    /// `self + n` for the smallest `n` that makes it at least the working
+    /// precision, where Stirling's series converges quickly enough.
+    fn shifted(&self) -> (Self, u64) {
+        let target = self.precision as f64;
+        let n = (target - self.to_f64()).ceil().max(0.) as u64;
+        (self.add(&Decimal::from_int(n, self.precision)), n)
+    }
+
+    /// `ln Γ(self)` for positive `self` to `self.precision` digits after the
+    /// point, by Stirling's series.
+    fn ln_gamma(&self) -> Result<Self, CalcError> {
+        let w = self.precision;
+        let (z, n) = self.shifted();
+        // ln Γ(x) = ln Γ(x + n) - ln(x (x + 1) ... (x + n - 1))
+        let mut product = Decimal::from_int(1, w);
+        for k in 0..n {
+            product = product.mul(&self.add(&Decimal::from_int(k, w)));
+        }
+        let half = Decimal::new(BigInt::from(5), -1, w);
+        let ln_2pi = Decimal::pi(w).mul(&Decimal::from_int(2, w)).ln()?;
+        let mut sum = z.sub(&half).mul(&z.ln()?).sub(&z).add(&ln_2pi.mul(&half));
+        let z2 = z.mul(&z);
+        let mut power = z.clone();
+        for (k, t) in tangent_numbers(stirling_terms(&z)).into_iter().enumerate() {
+            // B_2k / (2k (2k - 1)) = (-1)^(k-1) T_k / ((2k - 1) 4^k (4^k - 1))
+            let k = k + 1;
+            let four_k = num::pow(BigInt::from(4), k);
+            let denominator = (&four_k - 1) * four_k * (2 * k - 1);
+            let term =
+                Decimal::from_int(t, w).div(&Decimal::from_int(denominator, w).mul(&power))?;
+            sum = if k % 2 == 1 {
+                sum.add(&term)
+            } else {
+                sum.sub(&term)
+            };
+            power = power.mul(&z2);
+        }
+        Ok(sum.sub(&product.ln()?))
+    }
+
+    /// `ψ(self)` for positive `self`, by the asymptotic series.
+    fn digamma_positive(&self) -> Result<Self, CalcError> {
+        let w = self.precision;
+        let (z, n) = self.shifted();
+        // ψ(x) = ψ(x + n) - 1/x - 1/(x + 1) - ... - 1/(x + n - 1)
+        let mut sum = z.ln()?.sub(&z.add(&z).recip()?);
+        for k in 0..n {
+            sum = sum.sub(&self.add(&Decimal::from_int(k, w)).recip()?);
+        }
+        let z2 = z.mul(&z);
+        let mut power = z2.clone();
+        for (k, t) in tangent_numbers(stirling_terms(&z)).into_iter().enumerate() {
+            // B_2k / 2k = (-1)^(k-1) T_k / (4^k (4^k - 1))
+            let k = k + 1;
+            let four_k = num::pow(BigInt::from(4), k);
+            let denominator = (&four_k - 1) * four_k;
+            let term =
+                Decimal::from_int(t, w).div(&Decimal::from_int(denominator, w).mul(&power))?;
+            sum = if k % 2 == 1 {
+                sum.sub(&term)
+            } else {
+                sum.add(&term)
+            };
+            power = power.mul(&z2);
+        }
+        Ok(sum)
+    }
+
+    /// `(sin, cos, w)` as fixed point numbers scaled by `10^w`.
+    fn sin_cos(&self) -> Result<(BigInt, BigInt, usize), CalcError> {
+        let magnitude = self.magnitude();
+        if magnitude > 10_000 {
+            return Err(out_of_bounds());
+        }
+        let w = self.work() + magnitude.unsigned_abs() as usize;
+        let s = pow10(w);
+        let x = self.to_fixed(w);
+        let two_pi = pi_fixed(w) * 2;
+        let r = &x - div_round(&x, &two_pi) * &two_pi;
+        let r2 = &r * &r / &s;
+        let (mut sin, mut cos) = (r.clone(), s.clone());
+        let (mut sin_term, mut cos_term) = (r, s.clone());
+        let mut k = 1u64;
+        while !sin_term.is_zero() || !cos_term.is_zero() {
+            cos_term = -cos_term * &r2 / (&s * (2 * k - 1) * (2 * k));
+            sin_term = -sin_term * &r2 / (&s * (2 * k) * (2 * k + 1));
+            cos += &cos_term;
+            sin += &sin_term;
+            k += 1;
+        }
+        Ok((sin, cos, w))
+    }
+
+    pub fn sin(&self) -> Result<Self, CalcError> {
+        let (sin, _, w) = self.sin_cos()?;
+        Ok(Decimal::from_fixed(sin, w, self.precision))
+    }
+
+    pub fn cos(&self) -> Result<Self, CalcError> {
+        let (_, cos, w) = self.sin_cos()?;
+        Ok(Decimal::from_fixed(cos, w, self.precision))
+    }
+
+    pub fn tan(&self) -> Result<Self, CalcError> {
+        let (sin, cos, w) = self.sin_cos()?;
+        if cos.is_zero() {
+            return Err(out_of_bounds());
+        }
+        Ok(Decimal::from_fixed(sin * pow10(w) / cos, w, self.precision))
+    }
+
+    pub fn atan(&self) -> Result<Self, CalcError> {
+        if self.is_zero() {
+            return Ok(self.clone());
+        }
+        let w = self.work() + (-self.magnitude()).max(0) as usize;
+        let s = pow10(w);
+        let mut x = self.to_fixed(w).abs();
+        let inverted = x > s;
+        if inverted {
+            x = &s * &s / x;
+        }
+        // atan(x) = 2 atan(x / (1 + sqrt(1 + x^2))), three times
+        for _ in 0..3 {
+            let hyp = (&s * &s + &x * &x).sqrt();
+            x = &x * &s / (&s + hyp);
+        }
+        let mut atan: BigInt = atan_fixed(&x, &s) * 8;
+        if inverted {
+            atan = pi_fixed(w) / 2 - atan;
+        }
+        if self.is_negative() {
+            atan = -atan;
+        }
+        Ok(Decimal::from_fixed(atan, w, self.precision))
+    }
+
+    pub fn asin(&self) -> Result<Self, CalcError> {
+        // asin(x) = atan(x / sqrt((1 - x)(1 + x)))
+        let one = Decimal::from_int(1, self.work());
+        let x = self.with_precision(self.work());
+        let (below, above) = (one.sub(&x), one.add(&x));
+        if below.is_negative() || above.is_negative() {
+            return Err(out_of_bounds());
+        } else if below.is_zero() || above.is_zero() {
+            let half_pi = Decimal::pi(self.precision).div(&Decimal::from_int(2, 1))?;
+            return Ok(if self.is_negative() {
+                half_pi.neg()
+            } else {
+                half_pi
+            });
+        }
+        let atan = x.div(&below.mul(&above).sqrt()?)?.atan()?;
+        Ok(atan.with_precision(self.precision))
+    }
+
+    pub fn acos(&self) -> Result<Self, CalcError> {
+        // acos(x) = 2 atan(sqrt((1 - x) / (1 + x)))
+        let one = Decimal::from_int(1, self.work());
+        let x = self.with_precision(self.work());
+        let (below, above) = (one.sub(&x), one.add(&x));
+        if below.is_negative() || above.is_negative() {
+            return Err(out_of_bounds());
+        } else if above.is_zero() {
+            return Ok(Decimal::pi(self.precision));
+        }
+        let atan = below.div(&above)?.sqrt()?.atan()?;
+        Ok(atan
+            .mul(&Decimal::from_int(2, 1))
+            .with_precision(self.precision))
+    }
+
+    /// Precision needed to survive cancellation around zero.
+    fn cancellation(&self) -> usize {
+        self.precision + GUARD + (-self.magnitude()).max(0) as usize
+    }
+
+    pub fn sinh(&self) -> Result<Self, CalcError> {
+        let e = self.with_precision(self.cancellation()).exp()?;
+        let sinh = e.sub(&Decimal::from_int(1, 1).div(&e)?);
+        Ok(sinh
+            .div(&Decimal::from_int(2, 1))?
+            .with_precision(self.precision))
+    }
+
+    pub fn cosh(&self) -> Result<Self, CalcError> {
+        let e = self.with_precision(self.precision + GUARD).exp()?;
+        let cosh = e.add(&Decimal::from_int(1, 1).div(&e)?);
+        Ok(cosh
+            .div(&Decimal::from_int(2, 1))?
+            .with_precision(self.precision))
+    }
+
+    pub fn tanh(&self) -> Result<Self, CalcError> {
+        if self.magnitude() > 15 {
+            let sign = if self.is_negative() { -1 } else { 1 };
+            return Ok(Decimal::from_int(sign, self.precision));
+        }
+        // (e^2x - 1) / (e^2x + 1)
+        let x = self.with_precision(self.cancellation());
+        let e = x.add(&x).exp()?;
+        let one = Decimal::from_int(1, 1);
+        let tanh = e.sub(&one).div(&e.add(&one))?;
+        Ok(tanh.with_precision(self.precision))
+    }
+
+    pub fn recip(&self) -> Result<Self, CalcError> {
+        Decimal::from_int(1, self.precision).div(self)
+    }
+}
+
+impl PartialEq for Decimal {
+    fn eq(&self, other: &Self) -> bool {
+        self.mantissa == other.mantissa && self.exponent == other.exponent
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(
+            self.sub(other)
+                .mantissa
+                .sign()
+                .cmp(&num::bigint::Sign::NoSign),
+        )
+    }
+}
+
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fix = (-self.exponent).max(0) as usize;
+        write!(f, "{}", self.to_fixed_string(fix))
+    }
+}
+
+/// `sin(πx)`, reduced by the nearest integer first so that it keeps its
+/// relative accuracy near the integers.
+fn sin_pi(x: &Decimal) -> Result<Decimal, CalcError> {
+    let n = x.round();
+    let sin = x.sub(&n).mul(&Decimal::pi(x.precision)).sin()?;
+    Ok(if n.trunc().is_odd() { sin.neg() } else { sin })
+}
+
+/// Digits before the point of `x`, at least one.
+fn log_digits(x: f64) -> usize {
+    x.abs().log10().clamp(0., 1e4) as usize + 1
+}
+
+/// Terms of Stirling's series in `1/z` that reach `10^-w` for `z` at least
+/// the working precision `w`, the kth term is about `2z (2k)! / (2πz)^2k`.
+fn stirling_terms(z: &Decimal) -> usize {
+    let w = z.precision as f64;
+    let step = (2. * std::f64::consts::PI * z.to_f64()).log10();
+    let mut size = (2. * z.to_f64()).log10();
+    (1..)
+        .find(|&k: &usize| {
+            size += ((2 * k - 1) as f64 * (2 * k) as f64).log10() - 2. * step;
+            size < -w
+        })
+        .unwrap()
+}
+
+/// The tangent numbers `T_1` to `T_n`, `B_2k = (-1)^(k-1) 2k T_k / (4^k (4^k - 1))`,
+/// by Brent and Harvey's recurrence on integers.
+fn tangent_numbers(n: usize) -> Vec<BigInt> {
+    let mut t = vec![BigInt::one(); n + 1];
+    for k in 2..=n {
+        t[k] = &t[k - 1] * (k - 1);
+    }
+    for k in 2..=n {
+        for j in k..=n {
+            t[j] = &t[j - 1] * (j - k) + &t[j] * (j - k + 2);
+        }
+    }
+    t.split_off(1)
+}
+
+/// `sum x^(2k+1) / (2k+1)`, converges for `|x| < 1`.
+fn atanh_fixed(x: &BigInt, s: &BigInt) -> BigInt {
+    let x2 = x * x / s;
+    let mut term = x.clone();
+    let mut sum = x.clone();
+    let mut k = 1u64;
+    while !term.is_zero() {
+        term = term * &x2 / s;
+        sum += &term / (2 * k + 1);
+        k += 1;
+    }
+    sum
+}
+
+/// `sum (-1)^k x^(2k+1) / (2k+1)`, converges for `|x| <= 1`.
+fn atan_fixed(x: &BigInt, s: &BigInt) -> BigInt {
+    let x2 = x * x / s;
+    let mut term = x.clone();
+    let mut sum = x.clone();
+    let mut k = 1u64;
+    while !term.is_zero() {
+        term = -term * &x2 / s;
+        sum += &term / (2 * k + 1);
+        k += 1;
+    }
+    sum
+}
+
+/// Taylor series of `exp`, meant for small `|x|`.
+fn exp_fixed(x: &BigInt, s: &BigInt) -> BigInt {
+    let mut term = s.clone();
+    let mut sum = s.clone();
+    let mut k = 1u64;
+    while !term.is_zero() {
+        term = term * x / (s * k);
+        sum += &term;
+        k += 1;
+    }
+    sum
+}
+
+/// Machin's formula, `pi = 16 atan(1/5) - 4 atan(1/239)`.
+fn pi_fixed(w: usize) -> BigInt {
+    let s = pow10(w + GUARD);
+    let pi = atan_fixed(&(&s / 5), &s) * 16 - atan_fixed(&(&s / 239), &s) * 4;
+    pi / pow10(GUARD)
+}
+
+fn ln2_fixed(w: usize) -> BigInt {
+    let s = pow10(w + GUARD);
+    atanh_fixed(&(&s / 3), &s) * 2 / pow10(GUARD)
+}
+
+/// `ln(10) = 3 ln(2) + ln(1.25)`
+fn ln10_fixed(w: usize) -> BigInt {
+    let s = pow10(w + GUARD);
+    let ln1_25 = atanh_fixed(&(&s / 9), &s) * 2;
+    (ln2_fixed(w + GUARD) * 3 + ln1_25) / pow10(GUARD)
+}
+
+/// Named constants at the given precision.
+pub fn constant(name: &str, precision: usize) -> Option<Decimal> {
+    match name {
+        "pi" => Some(Decimal::pi(precision)),
+        "e" => Some(Decimal::e(precision)),
+        _ => None,
+    }
+}
+
+/// An angle argument of a trigonometric function in radians, whatever the
+/// session's angle unit.
+fn rad(ctx: &FunctionContext, x: &Decimal) -> Result<Decimal, CalcError> {
+    match half_turn(ctx, x.precision()) {
+        Some(half_turn) => x.mul(&Decimal::pi(x.precision() + GUARD)).div(&half_turn),
+        None => Ok(x.clone()),
+    }
+}
+
+/// A half turn in the session's angle unit, `None` for radians.
+fn half_turn(ctx: &FunctionContext, precision: usize) -> Option<Decimal> {
+    match ctx.angle_unit {
+        AngleUnit::Radian => None,
+        AngleUnit::Degree => Some(Decimal::from_int(180, precision)),
+        AngleUnit::Gradian => Some(Decimal::from_int(200, precision)),
+    }
+}
+
+fn to_radians(x: &Decimal) -> Result<Decimal, CalcError> {
+    let pi = Decimal::pi(x.precision() + GUARD);
+    x.mul(&pi).div(&Decimal::from_int(180, x.precision()))
+}
+
+/// Decimal counterparts of everything in `lex::FUNCTIONS`.
+pub fn apply(name: &str, ctx: &FunctionContext, args: &[Decimal]) -> Result<Decimal, CalcError> {
+    let x = &args[0];
+    let precision = x.precision();
+    let one = Decimal::from_int(1, precision);
+    let result = match name {
+        "sin" => rad(ctx, x)?.sin()?,
+        "cos" => rad(ctx, x)?.cos()?,
+        "tan" => rad(ctx, x)?.tan()?,
+        "csc" => rad(ctx, x)?.sin()?.recip()?,
+        "sec" => rad(ctx, x)?.cos()?.recip()?,
+        "cot" => rad(ctx, x)?.tan()?.recip()?,
+        "sinh" => x.sinh()?,
+        "cosh" => x.cosh()?,
+        "tanh" => x.tanh()?,
+        "ln" => x.ln()?,
+        "log2" => x.ln()?.div(&Decimal::from_int(2, precision).ln()?)?,
+        "log10" => x.ln()?.div(&Decimal::from_int(10, precision).ln()?)?,
+        "sqrt" => x.sqrt()?,
+        "ceil" => x.ceil(),
+        "floor" => x.floor(),
+        "rad" => to_radians(x)?,
+        "deg" => x
+            .mul(&Decimal::from_int(180, x.precision()))
+            .div(&Decimal::pi(precision + GUARD))?,
+        "abs" => x.abs(),
+        "asin" => x.asin()?,
+        "acos" => x.acos()?,
+        "atan" => x.atan()?,
+        "acsc" => x.recip()?.asin()?,
+        "asec" => x.recip()?.acos()?,
+        "acot" if x.is_zero() => Decimal::pi(precision).div(&Decimal::from_int(2, 1))?,
+        "acot" => x.recip()?.atan()?,
+        "exp" => x.exp()?,
+        "exp2" => Decimal::from_int(2, precision).pow(x)?,
+        "round" if args.len() == 2 => {
+            let digits = args[1].to_rational();
+            if !digits.is_integer() {
+                return Err(out_of_bounds());
+            }
+            let scale = Decimal::from_int(10, precision).pow(&args[1].abs())?;
+            match digits.is_negative() {
+                true => x.div(&scale)?.round().mul(&scale),
+                false => x.mul(&scale).round().div(&scale)?,
+            }
+        }
+        "round" => x.round(),
+        "log" if args.len() == 1 => x.ln()?.div(&Decimal::from_int(10, precision).ln()?)?,
+        "log" => x.ln()?.div(&args[1].ln()?)?,
+        "nroot" => x.pow(&one.div(&args[1])?)?,
+        "re" | "conj" => x.clone(),
+        "im" => Decimal::from_int(0, precision),
+        "gamma" => x.gamma()?,
+        "lgamma" => x.lgamma()?,
+        "digamma" => x.digamma()?,
+        "beta" => x.beta(&args[1])?,
+        "arg" if x.is_negative() => {
+            half_turn(ctx, precision).unwrap_or_else(|| Decimal::pi(precision))
+        }
+        "arg" => Decimal::from_int(0, precision),
+        "stddev" => exact_stats("var", args, precision)?.sqrt()?,
+        "pstddev" => exact_stats("pvar", args, precision)?.sqrt()?,
+        _ => exact_stats(name, args, precision)?,
+    };
+    Ok(result.with_precision(precision))
+}
+
+/// Statistics of `args`, computed exactly and rounded once.
+fn exact_stats(name: &str, args: &[Decimal], precision: usize) -> Result<Decimal, CalcError> {
+    let xs: Vec<BigRational> = args.iter().map(Decimal::to_rational).collect();
+    stats::exact(name, &xs)
+        .and_then(|r| Decimal::from_rational(&r, precision))
+        .ok_or_else(out_of_bounds)
+}
+
+/// Decimal counterparts of everything in `lex::OPERATORS`.
+pub fn operate(token: &str, x: &Decimal, y: &Decimal) -> Result<Decimal, CalcError> {
+    match token {
+        "+" => Ok(x.add(y)),
+        "-" => Ok(x.sub(y)),
+        "*" => Ok(x.mul(y)),
+        "/" => x.div(y),
+        "//" => x.div(y).map(|q| q.floor()),
+        "%" => x.rem(y),
+        "^" => x.pow(y),
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(s: &str) -> Decimal {
+        Decimal::parse(s, 50).unwrap()
+    }
+
+    fn fixed(x: Result<Decimal, CalcError>, fix: usize) -> String {
+        x.unwrap().to_fixed_string(fix)
+    }
+
+    #[test]
+    fn parse_and_print() {
+        assert_eq!(d("12.50").to_string(), "12.5");
+        assert_eq!(d(".5").to_fixed_string(3), "0.500");
+        assert_eq!(d("-0.125").to_fixed_string(2), "-0.13");
+        assert_eq!(d("1.5e3").to_string(), "1500");
+        assert_eq!(Decimal::parse("1.2.3", 10), None);
+        assert_eq!(Decimal::parse(".", 10), None);
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(d("0.1").add(&d("0.2")), d("0.3"));
+        assert_eq!(
+            d("1").sub(&d("1e-40")).to_string(),
+            format!("0.{}", "9".repeat(40))
+        );
+        assert_eq!(
+            fixed(d("1").div(&d("3")), 45),
+            format!("0.{}", "3".repeat(45))
+        );
+        assert_eq!(d("-7").rem(&d("3")), Ok(d("-1")));
+        assert_eq!(
+            d("2").pow(&d("100")),
+            Ok(d("1267650600228229401496703205376"))
+        );
+        assert_eq!(
+            d("30").factorial(),
+            Ok(d("265252859812191058636308480000000"))
+        );
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(
+            Decimal::pi(50).to_fixed_string(48),
+            "3.141592653589793238462643383279502884197169399375"
+        );
+        assert_eq!(
+            Decimal::e(50).to_fixed_string(48),
+            "2.718281828459045235360287471352662497757247093700"
+        );
+    }
+
+    #[test]
+    fn transcendental() {
+        assert_eq!(
+            fixed(d("2").sqrt(), 45),
+            "1.414213562373095048801688724209698078569671875"
+        );
+        assert_eq!(
+            fixed(d("10").ln(), 45),
+            "2.302585092994045684017991454684364207601101489"
+        );
+        assert_eq!(
+            fixed(d("1e-30").exp(), 45),
+            "1.000000000000000000000000000001000000000000000"
+        );
+        assert_eq!(
+            fixed(d("1").sin(), 45),
+            "0.841470984807896506652502321630298999622563061"
+        );
+        assert_eq!(
+            fixed(d("1").atan(), 45),
+            "0.785398163397448309615660845819875721049292350"
+        );
+        assert_eq!(
+            fixed(d("0.5").asin(), 45),
+            "0.523598775598298873077107230546583814032861567"
+        );
+        assert_eq!(d("-1").ln(), Err(out_of_bounds()));
+        assert_eq!(d("2").asin(), Err(out_of_bounds()));
+    }
+
+    #[test]
+    fn gamma_family() {
+        assert_eq!(
+            fixed(d("0.5").gamma(), 45),
+            "1.772453850905516027298167483341145182797549456"
+        );
+        assert_eq!(
+            fixed(d("-2.5").gamma(), 45),
+            "-0.945308720482941881225689324448610764158693043"
+        );
+        assert_eq!(
+            fixed(d("10").lgamma(), 45),
+            "12.801827480081469611207717874566706164281149256"
+        );
+        assert_eq!(
+            fixed(d("1").digamma(), 45),
+            "-0.577215664901532860606512090082402431042159336"
+        );
+        // near a root, every digit is still significant
+        assert!(d("1.000001")
+            .lgamma()
+            .unwrap()
+            .to_string()
+            .starts_with("-0.000000577214842434900121857099784260882611178771915"));
+        assert_eq!(
+            fixed(d("2.5").beta(&d("1.5")), 45),
+            "0.196349540849362077403915211454968930262323087"
+        );
+        assert_eq!(d("0").gamma(), Err(out_of_bounds()));
+        assert_eq!(d("-3").digamma(), Err(out_of_bounds()));
+    }
+}
@@ -0,0 +1,170 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ast::Expr;
+use crate::error::CalcError;
+use crate::lex::{FunctionContext, CONSTANTS, FUNCTIONS, KEYWORDS};
+use crate::units;
+use crate::value::Value;
+
+/// How deep user functions may call each other before evaluation is aborted.
+pub const MAX_CALL_DEPTH: usize = 128;
+
+/// Session state that outlives a single expression, such as variables and
+/// user defined functions.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Environment {
+    variables: HashMap<String, Value>,
+    /// Parameters of the function being called and variables bound by
+    /// `deriv` and the like, which shadow the session's variables.
+    locals: HashMap<String, Value>,
+    functions: HashMap<String, UserFunction>,
+    depth: usize,
+}
+
+/// A function defined at runtime, e.g. `hyp(a, b) = sqrt(a^2 + b^2)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserFunction {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Expr,
+    /// The body as it was typed.
+    pub source: String,
+}
+
+impl UserFunction {
+    /// Evaluate the body with parameters bound to `args`. The body sees the
+    /// session's variables, not those of its caller.
+    pub(crate) fn call(
+        &self,
+        ctx: &FunctionContext,
+        env: &Environment,
+        args: &[Value],
+    ) -> Result<Value, CalcError> {
+        if env.depth >= MAX_CALL_DEPTH {
+            return Err(CalcError::RecursionLimit(MAX_CALL_DEPTH));
+        }
+        let mut scope = env.clone();
+        scope.depth += 1;
+        scope.locals = self
+            .params
+            .iter()
+            .cloned()
+            .zip(args.iter().cloned())
+            .collect();
+        self.body.eval(ctx, &scope)
+    }
+}
+
+impl fmt::Display for UserFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}({}) = {}",
+            self.name,
+            self.params.join(", "),
+            self.source
+        )
+    }
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.locals
+            .get(name)
+            .or_else(|| self.variables.get(name))
+            .cloned()
+    }
+
+    /// Bind `name` to `value`, rejecting names that clash with built-ins or
+    /// units, which `5 km to m` must still find.
+    pub fn set(&mut self, name: &str, value: Value) -> Result<(), CalcError> {
+        check_name(name)?;
+        if units::lookup(name).is_some() {
+            return Err(CalcError::Syntax(format!(
+                "Cannot assign to unit '{}'",
+                name
+            )));
+        }
+        self.variables.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Bind `name` for the expression being evaluated only, as `deriv` does
+    /// with its variable.
+    pub(crate) fn bind(&mut self, name: &str, value: Value) -> Result<(), CalcError> {
+        check_name(name)?;
+        self.locals.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    pub fn function(&self, name: &str) -> Option<&UserFunction> {
+        self.functions.get(name)
+    }
+
+    /// Define (or redefine) the function `name`, syntax errors in `body` are
+    /// reported right away with spans into `body`.
+    pub fn define(
+        &mut self,
+        ctx: &FunctionContext,
+        name: &str,
+        params: &[&str],
+        body: &str,
+    ) -> Result<(), CalcError> {
+        check_name(name)?;
+        for (i, param) in params.iter().enumerate() {
+            check_name(param)?;
+            if params[..i].contains(param) {
+                return Err(CalcError::Syntax(format!(
+                    "Duplicate parameter '{}'",
+                    param
+                )));
+            }
+        }
+        if body.is_empty() {
+            return Err(CalcError::Syntax(format!(
+                "Function '{}' has an empty body",
+                name
+            )));
+        }
+        let func = UserFunction {
+            name: name.to_string(),
+            params: params.iter().map(|p| p.to_string()).collect(),
+            body: crate::parse_expr(ctx, body, None)?,
+            source: body.to_string(),
+        };
+        self.functions.insert(name.to_string(), func);
+        Ok(())
+    }
+
+    /// User defined functions, sorted by name.
+    pub fn functions(&self) -> Vec<&UserFunction> {
+        let mut functions: Vec<_> = self.functions.values().collect();
+        functions.sort_by(|a, b| a.name.cmp(&b.name));
+        functions
+    }
+}
+
+fn check_name(name: &str) -> Result<(), CalcError> {
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphabetic()) {
+        return Err(CalcError::Syntax(format!("Invalid name '{}'", name)));
+    }
+    if KEYWORDS.contains(&name) {
+        return Err(CalcError::Syntax(format!("'{}' is an operator", name)));
+    }
+    if CONSTANTS.contains_key(name) || FUNCTIONS.contains_key(name) {
+        return Err(CalcError::Syntax(format!(
+            "Cannot assign to built-in '{}'",
+            name
+        )));
+    }
+    Ok(())
+}
@@ -3,9 +3,8 @@
  * */
 
 use std::fmt;
-use std::iter::ExactSizeIterator;
 
-use crate::lex;
+use crate::lex::{self, Span};
 
 /// Math related errors.
 #[derive(Debug, PartialEq, Eq)]
@@ -14,6 +13,18 @@ pub enum MathError {
     OutOfBounds,
     UnknownBase,
     TooLarge,
+    /// An integer argument past the largest a function takes, which is given.
+    ArgumentTooLarge(u64),
+    /// Integer only operation on a fractional or complex operand.
+    NotAnInteger,
+    /// Mismatched or misused physical units.
+    Units(String),
+    /// Shape mismatch or singular matrix.
+    Matrix(String),
+    /// A boolean where a number is expected, or the other way round.
+    Type(String),
+    /// An iterative method that could not reach the accuracy asked of it.
+    Convergence(String),
 }
 
 /// Generic calculation errors.
@@ -22,7 +33,51 @@ pub enum CalcError {
     Math(MathError),
     Syntax(String),
     Parser(String),
-    Help,
+    RecursionLimit(usize),
+    /// A script's `assert` whose condition was false, carries the condition.
+    Assertion(String),
+    /// Request for help, carries the user defined functions to list.
+    Help(Vec<String>),
+    /// An error located at a byte range of the input.
+    At(Box<CalcError>, Span),
+}
+
+impl CalcError {
+    /// Locate the error at `span`, unless it already points somewhere more
+    /// precise.
+    pub fn at(self, span: Span) -> CalcError {
+        match self {
+            CalcError::At(..) | CalcError::Help(_) => self,
+            error => CalcError::At(Box::new(error), span),
+        }
+    }
+
+    /// Byte range of the input the error refers to, if known.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CalcError::At(_, span) => Some(span.clone()),
+            _ => None,
+        }
+    }
+
+    /// The error without its location.
+    pub fn without_span(self) -> CalcError {
+        match self {
+            CalcError::At(error, _) => *error,
+            error => error,
+        }
+    }
+
+    /// Move the location `offset` bytes to the right, for errors found in a
+    /// slice of the input.
+    pub(crate) fn shift(self, offset: usize) -> CalcError {
+        match self {
+            CalcError::At(error, span) => {
+                CalcError::At(error, span.start + offset..span.end + offset)
+            }
+            error => error,
+        }
+    }
 }
 
 impl fmt::Display for CalcError {
@@ -35,10 +90,26 @@ impl fmt::Display for CalcError {
                 MathError::TooLarge => {
                     write!(f, "Error: to large to process! Max value: {}", f64::MAX)
                 }
+                MathError::ArgumentTooLarge(max) => {
+                    write!(
+                        f,
+                        "Error: argument too large to process! Max value: {}",
+                        max
+                    )
+                }
+                MathError::NotAnInteger => write!(f, "Domain Error: Expected an integer!"),
+                MathError::Units(details) => write!(f, "Unit Error: {}", details),
+                MathError::Matrix(details) => write!(f, "Matrix Error: {}", details),
+                MathError::Type(details) => write!(f, "Type Error: {}", details),
+                MathError::Convergence(details) => write!(f, "Convergence Error: {}", details),
             },
             CalcError::Syntax(details) => write!(f, "Syntax Error: {}", details),
             CalcError::Parser(details) => write!(f, "Parser Error: {}", details),
-            CalcError::Help => {
+            CalcError::RecursionLimit(depth) => {
+                write!(f, "Error: recursion deeper than {} calls!", depth)
+            }
+            CalcError::Assertion(condition) => write!(f, "Assertion Failed: {}", condition),
+            CalcError::Help(user_functions) => {
                 // calculate max width but ideally this should be calculated once
                 // TODO remove terminal_size from lib dependency
                 let mut max_width = 79; // capped at 79
@@ -47,36 +118,49 @@ impl fmt::Display for CalcError {
                         max_width = w as usize;
                     }
                 }
-                let operators: Vec<_> = lex::OPERATORS.keys().map(|c| c.to_string()).collect();
+                let mut operators: Vec<_> = lex::OPERATORS.keys().map(|c| c.to_string()).collect();
+                operators.push("!".to_string());
+                operators.push("~".to_string());
+                operators.sort();
+                let constants = lex::CONSTANTS.keys().map(|c| c.to_string()).collect();
+                let functions = lex::FUNCTIONS
+                    .values()
+                    .flat_map(|f| f.signatures())
+                    .collect();
                 write!(
                     f,
                     "Constants\n{}\nFunctions\n{}\nOperators\n{}\n",
-                    blocks(max_width, lex::CONSTANTS.keys().cloned()),
-                    blocks(max_width, lex::FUNCTIONS.keys().cloned()),
+                    blocks(max_width, constants),
+                    blocks(max_width, functions),
                     operators.join(" ")
-                )
+                )?;
+                if !user_functions.is_empty() {
+                    write!(f, "\nUser functions\n{}\n", user_functions.join("\n"))?;
+                }
+                Ok(())
             }
+            CalcError::At(error, _) => write!(f, "{}", error),
         }
     }
 }
 
-/// Convert iterator into strings of chunks of 8 right padded with space.
-fn blocks(
-    max_width: usize,
-    mut iter: impl Iterator<Item = &'static str> + ExactSizeIterator,
-) -> String {
-    // multiply by eight since we are formatting it into chunks of 8
-    let items_per_line = max_width / 8;
-    let full_bytes = (iter.len() - iter.len() % items_per_line) * 8;
-    let part_bytes = iter.len() % items_per_line * 8; // leftovers
-    let n_newlines = iter.len() / items_per_line + if part_bytes > 0 { 1 } else { 0 };
-    let mut s = String::with_capacity(full_bytes + part_bytes + n_newlines);
-    for _ in 0..n_newlines {
-        for item in iter.by_ref().take(items_per_line) {
-            s.extend(format!("{:>8}", item).chars());
-        }
+/// Sorted `items` in left aligned columns, at least 8 wide and as many per
+/// line as fit in `max_width`.
+fn blocks(max_width: usize, mut items: Vec<String>) -> String {
+    items.sort();
+    let width = items
+        .iter()
+        .map(|item| item.chars().count() + 1)
+        .fold(8, usize::max);
+    let items_per_line = (max_width / width).max(1);
+    let mut s = String::new();
+    for line in items.chunks(items_per_line) {
+        let line: Vec<String> = line
+            .iter()
+            .map(|item| format!("{:<1$}", item, width))
+            .collect();
+        s.push_str(line.concat().trim_end());
         s.push('\n');
     }
-    debug_assert_eq!(s.capacity(), s.len()); // check capacity calculation
     s
 }
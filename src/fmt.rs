@@ -1,8 +1,16 @@
-use num::{BigInt, FromPrimitive, ToPrimitive};
+use std::ops::Range;
+
+use eva::decimal::{Decimal, GUARD};
+use eva::lex::AngleUnit;
+use eva::matrix::Matrix;
+use eva::primes::Factorization;
+use eva::Value;
+use num::complex::Complex64;
+use num::{BigInt, BigRational, FromPrimitive, Signed, ToPrimitive, Zero};
 
 fn thousand_sep(mut s: String) -> String {
     let inc = 3;
-    let mut end = s.find('.').unwrap();
+    let mut end = s.find('.').unwrap_or(s.len());
     let sign = if s.starts_with('-') { 1 } else { 0 };
     for _ in 0..(end - sign - 1) / inc {
         end -= inc;
@@ -11,49 +19,476 @@ fn thousand_sep(mut s: String) -> String {
     s
 }
 
-pub fn pprint(base: u8, fix: usize, mut ans: f64) {
+/// Marker like `   ^~~` under the part of `input` that `span` covers.
+pub fn caret(input: &str, span: Range<usize>) -> String {
+    let start = span.start.min(input.len());
+    let end = span.end.clamp(start, input.len());
+    let indent = input[..start].chars().count();
+    let width = input[start..end].chars().count().max(1);
+    format!("{}^{}", " ".repeat(indent), "~".repeat(width - 1))
+}
+
+/// How numbers are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Notation {
+    /// Without an exponent, `1,234.5`.
+    Fixed,
+    /// One digit before the point, `1.2345e3`.
+    Scientific,
+    /// Exponents that are multiples of 3, `12.345e-6`.
+    Engineering,
+    /// Fixed while the exponent is inside `Style::window`, scientific outside.
+    Auto,
+}
+
+impl Notation {
+    pub fn parse(name: &str) -> Option<Notation> {
+        match name {
+            "fix" => Some(Notation::Fixed),
+            "sci" => Some(Notation::Scientific),
+            "eng" => Some(Notation::Engineering),
+            "auto" => Some(Notation::Auto),
+            _ => None,
+        }
+    }
+}
+
+/// Decimal places results are rounded to when digits are not counted from
+/// the first significant one, enough for the smallest float.
+pub const ALL_DECIMALS: usize = 1100;
+
+/// How results are printed, changed at runtime with `:notation` and the like.
+#[derive(Clone, Copy, Debug)]
+pub struct Style {
+    /// Radix of the output, exponents are only written in base 10.
+    pub base: u8,
+    pub fix: usize,
+    /// Show this many significant digits instead of `fix` decimal places.
+    pub significant: Option<usize>,
+    pub notation: Notation,
+    /// Decimal exponents `Notation::Auto` writes without an exponent.
+    pub window: (i64, i64),
+}
+
+impl Style {
+    /// Decimal places `eval_expr` rounds floats to, only fixed notation with
+    /// `fix` places can do without the rest.
+    pub fn rounding(&self) -> usize {
+        match (self.notation, self.significant) {
+            (Notation::Fixed, None) => self.fix,
+            _ => ALL_DECIMALS,
+        }
+    }
+
+    /// Apply a REPL switch like `:notation sci`, `:sig 4`, `:sig off`,
+    /// `:fix 3` or `:window -3:6`.
+    pub fn set(&mut self, switch: &str) -> Result<(), String> {
+        let mut words = switch.trim().trim_start_matches(':').split_whitespace();
+        let (name, value) = (words.next().unwrap_or(""), words.next().unwrap_or(""));
+        let count = |value: &str| {
+            value
+                .parse::<usize>()
+                .ok()
+                .filter(|n| (1..=1000).contains(n))
+        };
+        match name {
+            // digits are only counted in base 10
+            "notation" | "sig" if self.base != 10 && !matches!(value, "fix" | "off") => {
+                return Err(format!(
+                    "':{}' only applies to base 10 output, not base {}",
+                    name, self.base
+                ))
+            }
+            "notation" => {
+                self.notation = Notation::parse(value)
+                    .ok_or_else(|| format!("Expected fix, sci, eng or auto, got '{}'", value))?
+            }
+            "sig" if value == "off" => self.significant = None,
+            "sig" => {
+                self.significant = Some(
+                    count(value)
+                        .ok_or_else(|| format!("Expected 1 - 1000 or off, got '{}'", value))?,
+                )
+            }
+            "fix" => {
+                self.fix = count(value)
+                    .filter(|&n| n <= 64)
+                    .ok_or_else(|| format!("Expected 1 - 64, got '{}'", value))?;
+                self.significant = None;
+            }
+            "window" => {
+                self.window = parse_window(value)
+                    .ok_or_else(|| format!("Expected MIN:MAX, as in -5:12, got '{}'", value))?
+            }
+            name => {
+                return Err(format!(
+                    "Unknown switch ':{}', try :notation, :sig, :fix or :window",
+                    name
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Digits after the point of a number of `magnitude`, written without
+    /// an exponent.
+    fn decimals(&self, magnitude: i64) -> usize {
+        match self.significant {
+            Some(digits) => (digits as i64 - 1 - magnitude).max(0) as usize,
+            None => self.fix,
+        }
+    }
+
+    /// The exponent to write a number of `magnitude` with, `None` for none.
+    fn exponent(&self, magnitude: i64, zero: bool) -> Option<i64> {
+        let (min, max) = self.window;
+        match self.notation {
+            _ if self.base != 10 => None,
+            // too few significant digits to reach the units
+            Notation::Fixed | Notation::Auto
+                if !zero
+                    && self
+                        .significant
+                        .is_some_and(|digits| magnitude >= digits as i64) =>
+            {
+                Some(magnitude)
+            }
+            Notation::Fixed => None,
+            Notation::Auto if zero || (min..=max).contains(&magnitude) => None,
+            Notation::Scientific | Notation::Auto => Some(magnitude),
+            Notation::Engineering => Some(magnitude.div_euclid(3) * 3),
+        }
+    }
+
+    /// A number written as `mantissa e exponent`, where `mantissa(e, places)`
+    /// is the number over `10^e` rounded to `places` decimals.
+    fn with_exponent(&self, magnitude: i64, mantissa: impl Fn(i64, usize) -> String) -> String {
+        let mut magnitude = magnitude;
+        loop {
+            let exponent = self.exponent(magnitude, false).unwrap_or(magnitude);
+            let integral = (magnitude - exponent) as usize + 1;
+            let places = match self.significant {
+                Some(digits) => digits.saturating_sub(integral),
+                None => self.fix,
+            };
+            let m = mantissa(exponent, places);
+            // rounding can carry into another digit, as in 9.99 to 10.0
+            let digits = m.trim_start_matches('-').split('.').next().unwrap().len();
+            if digits <= integral {
+                return format!("{}e{}", m, exponent);
+            }
+            magnitude += 1;
+        }
+    }
+}
+
+/// `MIN:MAX` for `Style::window`.
+pub fn parse_window(window: &str) -> Option<(i64, i64)> {
+    let (min, max) = window.split_once(':')?;
+    let (min, max) = (min.trim().parse().ok()?, max.trim().parse().ok()?);
+    (min <= max).then_some((min, max))
+}
+
+pub fn format(style: &Style, ans: &Value) -> String {
+    match ans {
+        Value::Float(x) => format_float(style, *x),
+        Value::Decimal(d) => format_decimal(style, d),
+        Value::Rational(r) => format_rational(style, r),
+        Value::Complex(z) => format_complex(style, *z),
+        Value::Quantity(q) => format!("{} {}", format_float(style, q.value), q.unit),
+        Value::Matrix(m) => format_matrix(style, m),
+        Value::Bool(b) => b.to_string(),
+        Value::Factors(f) => format_factors(style.base, f),
+        Value::Symbolic(s) => s.to_string(),
+    }
+}
+
+/// Prime factors as `2^3 * 3 * 5`, exponents in decimal.
+fn format_factors(base: u8, f: &Factorization) -> String {
+    let prime = |p: u64| format_integer(base, &p.into());
+    let factors: Vec<String> = f
+        .primes
+        .iter()
+        .map(|&(p, k)| match k {
+            1 => prime(p),
+            k => format!("{}^{}", prime(p), k),
+        })
+        .collect();
+    let factors = match factors.is_empty() {
+        true => "1".to_string(),
+        false => factors.join(" * "),
+    };
+    format!("{}{}", if f.negative { "-" } else { "" }, factors)
+}
+
+/// One line per row, entries right aligned in their column.
+fn format_matrix(style: &Style, m: &Matrix) -> String {
+    let cells: Vec<Vec<String>> = (0..m.rows())
+        .map(|i| {
+            (0..m.cols())
+                .map(|j| format_float(style, m.get(i, j)))
+                .collect()
+        })
+        .collect();
+    let widths: Vec<usize> = (0..m.cols())
+        .map(|j| {
+            cells
+                .iter()
+                .map(|row| row[j].chars().count())
+                .max()
+                .unwrap()
+        })
+        .collect();
+    let rows: Vec<String> = cells
+        .iter()
+        .map(|row| {
+            let row: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:>1$}", cell, width))
+                .collect();
+            format!("[{}]", row.join("  "))
+        })
+        .collect();
+    rows.join("\n")
+}
+
+/// Complex numbers as `a + bi`, leaving out a zero real part.
+fn format_complex(style: &Style, z: Complex64) -> String {
+    let im = format!("{}i", format_float(style, z.im.abs()).trim_start());
+    match (z.re == 0., z.im < 0.) {
+        (true, true) => format!("-{}", im),
+        (true, false) => im,
+        (false, negative) => format!(
+            "{} {} {}",
+            format_float(style, z.re),
+            if negative { '-' } else { '+' },
+            im
+        ),
+    }
+}
+
+/// Complex numbers as `r ∠ θ`, with `θ` in `unit`.
+pub fn format_polar(style: &Style, z: Complex64, unit: AngleUnit) -> String {
+    let (r, theta) = z.to_polar();
+    let (theta, suffix) = match unit {
+        AngleUnit::Degree => (theta.to_degrees(), "°"),
+        AngleUnit::Radian => (theta, ""),
+        AngleUnit::Gradian => (theta.to_degrees() * 10. / 9., "ᵍ"),
+    };
+    format!(
+        "{} ∠ {}{}",
+        format_float(style, r),
+        format_float(style, theta).trim_start(),
+        suffix
+    )
+}
+
+fn format_float(style: &Style, mut ans: f64) -> String {
+    // digits past those a f64 holds are noise, even when more are asked for
+    let style = &Style {
+        significant: style
+            .significant
+            .map(|digits| digits.min(f64::DIGITS as usize)),
+        ..*style
+    };
+    let base = style.base;
+    let magnitude = match (ans, style.significant) {
+        (0., _) => 0,
+        // of the rounded number, 999.99 to 3 digits is 1.00e3
+        (ans, Some(digits)) if ans.is_finite() => {
+            let rounded = format!("{:.*e}", digits - 1, ans);
+            rounded.split_once('e').unwrap().1.parse().unwrap()
+        }
+        (ans, _) => ans.abs().log10().floor() as i64,
+    };
+    let fix = style.decimals(magnitude);
     if ans.is_infinite() {
-        println!("{}inf", if ans.is_sign_positive() { "" } else { "-" });
+        format!("{}inf", if ans.is_sign_positive() { "" } else { "-" })
     } else if ans.is_nan() {
-        println!("nan");
+        "nan".to_string()
+    } else if style.exponent(magnitude, ans == 0.).is_some() {
+        style.with_exponent(magnitude, |exponent, places| {
+            // in two steps, 10^exponent alone may not be a finite float
+            let half = exponent / 2;
+            let scaled = ans / 10f64.powi(half as i32) / 10f64.powi((exponent - half) as i32);
+            format!("{:.*}", places, scaled)
+        })
     } else if base == 10 {
         // use standard library formatter since it handle printing pretty well
         let ans = format!("{:.*}", fix, ans);
-        println!("{}", thousand_sep(ans));
+        thousand_sep(ans)
     } else {
         ans = format!("{:.*}", fix, ans).parse().unwrap();
-        let table: &[u8] = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ".as_bytes();
-
-        // format integral part of float
-        let mut integral = BigInt::from_f64(ans.abs().trunc()).unwrap();
-        let mut obase_int = String::new();
-        let obaseb = BigInt::from_usize(base as usize).unwrap();
-
-        while integral >= obaseb {
-            obase_int.push(table[(&integral % &obaseb).to_usize().unwrap()] as char);
-            integral /= &obaseb;
-        }
-        obase_int.push(table[integral.to_usize().unwrap()] as char);
-        if ans.is_sign_negative() {
-            obase_int.push('-');
-        }
-        let obase_int = obase_int.chars().rev().collect::<String>();
+        let integral = BigInt::from_f64(ans.abs().trunc()).unwrap();
 
         // format fractional part of float
         // TODO: fix conversion with fractional part
         let mut fract = ans.abs().fract();
-        let mut obase_fract = String::new();
-        let mut i = 0;
-        loop {
+        let next_digit = || {
             fract *= base as f64;
-            obase_fract.push(table[fract.trunc() as usize] as char);
-            i += 1;
-            if fract.fract() == 0. || i >= fix {
-                break;
-            }
+            let digit = fract.trunc() as usize;
             fract = fract.fract();
+            (digit, fract == 0.)
+        };
+        let ans = radix(base, fix, ans.is_sign_negative(), integral, next_digit);
+        thousand_sep(ans)
+    }
+}
+
+fn format_decimal(style: &Style, ans: &Decimal) -> String {
+    // digits past the evaluated precision would only be padding
+    let style = &Style {
+        significant: style
+            .significant
+            .map(|digits| digits.min(ans.precision().saturating_sub(GUARD).max(1))),
+        ..*style
+    };
+    let magnitude = match style.significant {
+        // of the rounded number, as for floats
+        Some(digits) => ans.with_precision(digits).magnitude(),
+        None => ans.magnitude(),
+    };
+    let (base, fix) = (style.base, style.decimals(magnitude));
+    if style.exponent(magnitude, ans.is_zero()).is_some() {
+        style.with_exponent(magnitude, |exponent, places| {
+            let scale = Decimal::parse(&format!("1e{}", -exponent), ans.precision()).unwrap();
+            ans.mul(&scale).to_fixed_string(places)
+        })
+    } else if base == 10 {
+        thousand_sep(ans.to_fixed_string(fix))
+    } else {
+        let ans = Decimal::parse(&ans.to_fixed_string(fix), ans.precision()).unwrap();
+        let (mut numerator, denominator) = ans.fract_parts();
+        let next_digit = || {
+            numerator *= base;
+            let digit = (&numerator / &denominator).to_usize().unwrap();
+            numerator %= &denominator;
+            (digit, numerator.is_zero())
+        };
+        let ans = radix(base, fix, ans.is_negative(), ans.trunc().abs(), next_digit);
+        thousand_sep(ans)
+    }
+}
+
+/// Exact fractions print as `numerator/denominator`, integers on their own
+/// or with an exponent if the notation asks for one.
+fn format_rational(style: &Style, ans: &BigRational) -> String {
+    if !ans.is_integer() {
+        let (numer, denom) = (ans.numer(), ans.denom());
+        return format!(
+            "{}/{}",
+            format_integer(style.base, numer),
+            format_integer(style.base, denom)
+        );
+    }
+    let n = ans.numer();
+    let digits = n.abs().to_string().len();
+    match style.exponent(digits as i64 - 1, n.is_zero()) {
+        // every digit of the integer, and the guard digits decimals carry
+        Some(_) => format_decimal(style, &Decimal::from_int(n.clone(), digits + GUARD)),
+        None => format_integer(style.base, n),
+    }
+}
+
+fn format_integer(base: u8, n: &BigInt) -> String {
+    match base {
+        10 => thousand_sep(n.to_string()),
+        _ => n.to_str_radix(base as u32).to_uppercase(),
+    }
+}
+
+/// Format in radix `base`, `next_digit` yields fractional digits and whether
+/// they have run out.
+fn radix(
+    base: u8,
+    fix: usize,
+    negative: bool,
+    mut integral: BigInt,
+    mut next_digit: impl FnMut() -> (usize, bool),
+) -> String {
+    let table: &[u8] = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ".as_bytes();
+
+    // format integral part
+    let mut obase_int = String::new();
+    let obaseb = BigInt::from_usize(base as usize).unwrap();
+
+    while integral >= obaseb {
+        obase_int.push(table[(&integral % &obaseb).to_usize().unwrap()] as char);
+        integral /= &obaseb;
+    }
+    obase_int.push(table[integral.to_usize().unwrap()] as char);
+    if negative {
+        obase_int.push('-');
+    }
+    let obase_int = obase_int.chars().rev().collect::<String>();
+
+    // format fractional part
+    let mut obase_fract = String::new();
+    let mut i = 0;
+    loop {
+        let (digit, exhausted) = next_digit();
+        obase_fract.push(table[digit] as char);
+        i += 1;
+        if exhausted || i >= fix {
+            break;
         }
-        let ans = format!("{:>10}.{}", obase_int, obase_fract);
-        println!("{}", thousand_sep(ans));
+    }
+    format!("{}.{}", obase_int, obase_fract)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notations() {
+        let mut style = Style {
+            base: 10,
+            fix: 2,
+            significant: None,
+            notation: Notation::Scientific,
+            window: (-5, 12),
+        };
+        let show = |style: &Style, x: f64| format(style, &Value::Float(x));
+        assert_eq!(show(&style, 6.02e23), "6.02e23");
+        assert_eq!(show(&style, -0.000123), "-1.23e-4");
+        style.set(":notation eng").unwrap();
+        assert_eq!(show(&style, 0.000123), "123.00e-6");
+        style.set(":sig 3").unwrap();
+        assert_eq!(show(&style, 999.99), "1.00e3");
+        style.set(":notation auto").unwrap();
+        assert_eq!(show(&style, 1e-15), "1.00e-15");
+        assert_eq!(show(&style, 0.0123), "0.0123");
+        assert_eq!(show(&style, 0.), "0.00");
+        style.set(":sig 50").unwrap();
+        assert_eq!(show(&style, 2f64.sqrt()), "1.41421356237310");
+        // fewer significant digits than integer ones
+        style.set(":notation fix").unwrap();
+        style.set(":sig 3").unwrap();
+        assert_eq!(show(&style, 123456.), "1.23e5");
+        assert_eq!(show(&style, 999.99), "1.00e3");
+        let integer = Value::from(BigInt::from(2).pow(100));
+        assert_eq!(format(&style, &integer), "1.27e30");
+        style.set(":sig off").unwrap();
+        style.set(":notation sci").unwrap();
+        assert_eq!(format(&style, &integer), "1.27e30");
+        // no more digits than were evaluated
+        let pi = Decimal::parse("3.14159265358979", 5 + GUARD).unwrap();
+        style.set(":sig 10").unwrap();
+        style.set(":notation fix").unwrap();
+        assert_eq!(format(&style, &Value::Decimal(pi)), "3.1416");
+        assert!(style.set(":window 3:-3").is_err());
+        assert!(style.set(":sig many").is_err());
+        // digits are counted in base 10 only
+        style.set(":sig off").unwrap();
+        style.base = 16;
+        assert_eq!(
+            style.set(":sig 3"),
+            Err("':sig' only applies to base 10 output, not base 16".into())
+        );
+        assert!(style.set(":notation sci").is_err());
+        style.set(":notation fix").unwrap();
+        assert_eq!(show(&style, 255.5), "FF.8");
     }
 }
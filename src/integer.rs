@@ -0,0 +1,244 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Number theory on arbitrary size integers, evaluated exactly whatever the
+//! number mode and returned in the representation of the arguments.
+
+use num::{BigInt, FromPrimitive, Integer, Signed, ToPrimitive, Zero};
+
+use crate::combinatorics;
+use crate::decimal::Decimal;
+use crate::error::{CalcError, MathError};
+use crate::matrix::Matrix;
+use crate::primes::{self, Factorization};
+use crate::value::Value;
+
+/// `gcd(a, b, ...)`, 0 only when every argument is.
+pub fn gcd(args: &[Value]) -> Result<Value, CalcError> {
+    exact(args, |n| Ok(n.iter().fold(BigInt::zero(), |a, b| a.gcd(b))))
+}
+
+/// `lcm(a, b, ...)`, never negative.
+pub fn lcm(args: &[Value]) -> Result<Value, CalcError> {
+    exact(args, |n| {
+        Ok(n[1..].iter().fold(n[0].abs(), |a, b| a.lcm(b)))
+    })
+}
+
+/// `modpow(base, exponent, modulus)`, with the sign of `modulus`. Negative
+/// exponents are powers of the inverse of `base`.
+pub fn modpow(args: &[Value]) -> Result<Value, CalcError> {
+    exact(args, |n| {
+        let (base, exponent, modulus) = (&n[0], &n[1], &n[2]);
+        if modulus.is_zero() {
+            return Err(CalcError::Math(MathError::DivideByZero));
+        }
+        if exponent.is_negative() {
+            let inverse = primes::mod_inv(base, modulus)?;
+            return Ok(inverse.modpow(&-exponent, modulus));
+        }
+        Ok(base.modpow(exponent, modulus))
+    })
+}
+
+/// `modinv(a, m)`, with the sign of `m`.
+pub fn modinv(args: &[Value]) -> Result<Value, CalcError> {
+    exact(args, |n| primes::mod_inv(&n[0], &n[1]))
+}
+
+pub fn ncr(args: &[Value]) -> Result<Value, CalcError> {
+    exact(args, |n| combinatorics::ncr(&n[0], &n[1]))
+}
+
+pub fn npr(args: &[Value]) -> Result<Value, CalcError> {
+    exact(args, |n| combinatorics::npr(&n[0], &n[1]))
+}
+
+pub fn multinomial(args: &[Value]) -> Result<Value, CalcError> {
+    exact(args, combinatorics::multinomial)
+}
+
+pub fn catalan(args: &[Value]) -> Result<Value, CalcError> {
+    exact(args, |n| combinatorics::catalan(&n[0]))
+}
+
+pub fn stirling2(args: &[Value]) -> Result<Value, CalcError> {
+    exact(args, |n| combinatorics::stirling2(&n[0], &n[1]))
+}
+
+pub fn bell(args: &[Value]) -> Result<Value, CalcError> {
+    exact(args, |n| combinatorics::bell(&n[0]))
+}
+
+/// `f` of the integers `args` stand for, in the representation of `args`.
+fn exact(
+    args: &[Value],
+    f: impl FnOnce(&[BigInt]) -> Result<BigInt, CalcError>,
+) -> Result<Value, CalcError> {
+    let n = args.iter().map(from_value).collect::<Result<Vec<_>, _>>()?;
+    // results take the representation of the numbers factorizations stand for
+    let numbers: Vec<Value> = args.iter().cloned().map(Value::number).collect();
+    like(&numbers.iter().collect::<Vec<_>>(), f(&n)?)
+}
+
+/// Largest integer whose divisors all fit in the floats of a matrix.
+const MAX_DIVISORS: u64 = 1 << f64::MANTISSA_DIGITS;
+
+/// `isprime(n)`, false for negative numbers.
+pub fn isprime(args: &[Value]) -> Result<Value, CalcError> {
+    let n = from_value(&args[0])?;
+    if n.is_negative() {
+        return Ok(Value::Bool(false));
+    }
+    primes::to_u64(&n).map(|n| Value::Bool(primes::is_prime(n)))
+}
+
+/// `nextprime(n)`, 2 for negative numbers.
+pub fn nextprime(args: &[Value]) -> Result<Value, CalcError> {
+    exact(args, |n| {
+        if n[0].is_negative() {
+            return Ok(BigInt::from(2));
+        }
+        primes::to_u64(&n[0]).and_then(|n| {
+            primes::next_prime(n)
+                .map(BigInt::from)
+                .ok_or(CalcError::Math(MathError::ArgumentTooLarge(
+                    primes::LARGEST_PRIME - 1,
+                )))
+        })
+    })
+}
+
+pub fn prevprime(args: &[Value]) -> Result<Value, CalcError> {
+    exact(args, |n| {
+        if n[0].is_negative() {
+            return Err(CalcError::Math(MathError::OutOfBounds));
+        }
+        primes::to_u64(&n[0]).and_then(|n| {
+            primes::prev_prime(n)
+                .map(BigInt::from)
+                .ok_or(CalcError::Math(MathError::OutOfBounds))
+        })
+    })
+}
+
+pub fn totient(args: &[Value]) -> Result<Value, CalcError> {
+    exact(args, |n| {
+        positive(&n[0]).map(|n| primes::totient(&primes::factor(n)))
+    })
+}
+
+/// Factorization of a nonzero integer, which still stands for it.
+pub fn factor(args: &[Value]) -> Result<Value, CalcError> {
+    let n = from_value(&args[0])?;
+    if n.is_zero() {
+        return Err(CalcError::Math(MathError::OutOfBounds));
+    }
+    let primes = primes::factor(primes::to_u64(&n)?);
+    Ok(Value::Factors(Box::new(Factorization {
+        number: args[0].clone(),
+        negative: n.is_negative(),
+        primes,
+    })))
+}
+
+/// Divisors of a positive integer in a row vector, the list type of
+/// functions, as long as the floats of the vector hold them exactly.
+pub fn divisors(args: &[Value]) -> Result<Value, CalcError> {
+    let n = positive(&from_value(&args[0])?)?;
+    if n > MAX_DIVISORS {
+        return Err(CalcError::Math(MathError::ArgumentTooLarge(MAX_DIVISORS)));
+    }
+    let divisors = primes::divisors(&primes::factor(n));
+    let row = divisors.into_iter().map(|d| d as f64).collect();
+    Ok(Value::Matrix(Matrix::new(vec![row]).unwrap()))
+}
+
+/// `n` as an argument of functions only defined for positive integers.
+fn positive(n: &BigInt) -> Result<u64, CalcError> {
+    if !n.is_positive() {
+        return Err(CalcError::Math(MathError::OutOfBounds));
+    }
+    primes::to_u64(n)
+}
+
+/// Largest integer a float is sure to stand for, past it neighbouring
+/// integers round to the same float.
+const MAX_EXACT_FLOAT: u64 = (1 << f64::MANTISSA_DIGITS) - 1;
+
+/// The integer `x` stands for, whatever its representation.
+pub fn from_value(x: &Value) -> Result<BigInt, CalcError> {
+    let not_an_integer = CalcError::Math(MathError::NotAnInteger);
+    match x {
+        Value::Float(f) if f.abs() > MAX_EXACT_FLOAT as f64 => Err(CalcError::Math(
+            MathError::ArgumentTooLarge(MAX_EXACT_FLOAT),
+        )),
+        Value::Float(f) if f.fract() == 0. => BigInt::from_f64(*f).ok_or(not_an_integer),
+        Value::Rational(r) if r.is_integer() => Ok(r.to_integer()),
+        Value::Decimal(d) => {
+            let r = d.to_rational();
+            if r.is_integer() {
+                Ok(r.to_integer())
+            } else {
+                Err(not_an_integer)
+            }
+        }
+        Value::Factors(f) => from_value(&f.number),
+        _ => Err(not_an_integer),
+    }
+}
+
+/// `n` in the representation of `operands`, decimals win over floats.
+pub fn like(operands: &[&Value], n: BigInt) -> Result<Value, CalcError> {
+    let precision = operands.iter().find_map(|x| match x {
+        Value::Decimal(d) => Some(d.precision()),
+        _ => None,
+    });
+    if let Some(precision) = precision {
+        return Ok(Value::Decimal(Decimal::from_int(n, precision)));
+    }
+    if operands.iter().all(|x| matches!(x, Value::Rational(_))) {
+        return Ok(Value::from(n));
+    }
+    match n.to_f64() {
+        Some(f) if f.is_finite() => Ok(Value::Float(f)),
+        _ => Err(CalcError::Math(MathError::TooLarge)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_in_every_representation() {
+        let f = Value::Float;
+        let n = |n: i64| Value::from(BigInt::from(n));
+        assert_eq!(gcd(&[f(12.), f(-18.), f(8.)]), Ok(f(2.)));
+        assert_eq!(lcm(&[n(4), n(-6)]), Ok(n(12)));
+        // 2^100 is far beyond the integers a f64 holds exactly
+        assert_eq!(modpow(&[f(2.), f(100.), f(7.)]), Ok(f(2.)));
+        assert_eq!(
+            gcd(&[f(1.5), f(3.)]),
+            Err(CalcError::Math(MathError::NotAnInteger))
+        );
+        assert_eq!(
+            divisors(&[n((1 << 61) - 1)]),
+            Err(CalcError::Math(MathError::ArgumentTooLarge(1 << 53)))
+        );
+    }
+    #[test]
+    fn floats_past_exact_integers() {
+        let f = Value::Float;
+        let too_large = Err(CalcError::Math(MathError::ArgumentTooLarge(
+            MAX_EXACT_FLOAT,
+        )));
+        // 2^53 + 1 reads as 2^53, it could stand for either
+        assert_eq!(isprime(&[f(9007199254740993.)]), too_large);
+        assert_eq!(factor(&[f(9007199254740993.)]), too_large);
+        assert_eq!(isprime(&[f(18446744073709551557.)]), too_large);
+        assert_eq!(nextprime(&[f(-2f64.powi(60))]), too_large);
+        assert_eq!(isprime(&[f(9007199254740881.)]), Ok(Value::Bool(true)));
+    }
+}
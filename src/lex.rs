@@ -2,29 +2,98 @@
  * Refer to LICENCE for more information.
  * */
 
+use num::complex::Complex64;
+use num::Signed;
 use once_cell::sync::Lazy;
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::fmt;
 
+use crate::ast::Expr;
+use crate::bitwise;
+use crate::calculus;
+use crate::complex;
+use crate::decimal;
+use crate::env::{Environment, UserFunction};
 use crate::error::{CalcError, MathError};
+use crate::integer;
+use crate::logic;
+use crate::matrix;
+use crate::rational;
+use crate::roots;
+use crate::special;
+use crate::stats;
+use crate::symbolic;
+use crate::units;
+use crate::value::{self, Value};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone)]
 pub struct Operator {
-    token: char,
+    token: &'static str,
     pub operation: fn(f64, f64) -> f64,
     pub precedence: u8,
     pub is_left_associative: bool,
 }
 
+impl PartialEq for Operator {
+    fn eq(&self, other: &Self) -> bool {
+        self.token == other.token
+            && self.precedence == other.precedence
+            && self.is_left_associative == other.is_left_associative
+    }
+}
+
 impl Operator {
-    pub fn operate(self, x: f64, y: f64) -> Result<f64, CalcError> {
-        if self.token == '/' && y == 0. {
+    pub fn token(&self) -> &'static str {
+        self.token
+    }
+
+    pub fn operate(self, x: Value, y: Value) -> Result<Value, CalcError> {
+        let (x, y) = (x.number(), y.number());
+        match (&x, &y) {
+            _ if logic::is_logical(self.token) => logic::operate(self.token, &x, &y),
+            (Value::Bool(_), _) | (_, Value::Bool(_)) => Err(CalcError::Math(MathError::Type(
+                format!("Operator '{}' needs numbers, got a boolean", self.token),
+            ))),
+            (Value::Symbolic(_), _) | (_, Value::Symbolic(_)) => {
+                symbolic::operate(self.token, &x, &y)
+            }
+            _ if bitwise::is_bitwise(self.token) => bitwise::operate(self.token, &x, &y),
+            (Value::Matrix(_), _) | (_, Value::Matrix(_)) => matrix::operate(self.token, &x, &y),
+            (Value::Quantity(_), _) | (_, Value::Quantity(_)) => units::operate(self.token, &x, &y),
+            _ if self.token == "to" => units::operate(self.token, &x, &y),
+            (Value::Complex(_), _) | (_, Value::Complex(_)) => self.operate_complex(&x, &y),
+            // negative base with a fractional exponent has a complex result
+            _ if self.token == "^" && x.to_f64() < 0. && y.to_f64().fract() != 0. => {
+                self.operate_complex(&x, &y)
+            }
+            (Value::Rational(a), Value::Rational(b)) => match rational::operate(self.token, a, b) {
+                Some(result) => result.map(Value::Rational),
+                None => self.operate_f64(x.to_f64(), y.to_f64()).map(Value::Float),
+            },
+            // a float in decimal mode was computed in f64, a decimal result
+            // would claim digits it does not have
+            (Value::Decimal(_), Value::Float(_)) | (Value::Float(_), Value::Decimal(_)) => {
+                self.operate_f64(x.to_f64(), y.to_f64()).map(Value::Float)
+            }
+            (Value::Decimal(_), _) | (_, Value::Decimal(_)) => {
+                let args = value::to_decimals(&[x, y])?;
+                decimal::operate(self.token, &args[0], &args[1]).map(Value::Decimal)
+            }
+            _ => self.operate_f64(x.to_f64(), y.to_f64()).map(Value::Float),
+        }
+    }
+
+    fn operate_complex(self, x: &Value, y: &Value) -> Result<Value, CalcError> {
+        match complex::operate(self.token, x.to_complex(), y.to_complex()) {
+            Some(result) => result.map(Value::from),
+            None => Err(CalcError::Math(MathError::OutOfBounds)),
+        }
+    }
+
+    fn operate_f64(self, x: f64, y: f64) -> Result<f64, CalcError> {
+        if matches!(self.token, "/" | "//") && y == 0. {
             return Err(CalcError::Math(MathError::DivideByZero));
-        } else if self.token == '!' && (x < 0.0 || x.fract() != 0.0) {
-            return Err(CalcError::Math(MathError::OutOfBounds));
-        } else if self.token == '!' && x == 0.0 {
-            // Must return 1 manually as 0..=n where n is 0.0 doesn't work AFAIK.
-            return Ok(1.0);
         }
         let result = (self.operation)(x, y);
         if !result.is_finite() {
@@ -39,12 +108,63 @@ impl Operator {
 pub enum Relation {
     N1(fn(&FunctionContext, f64) -> f64),
     N2(fn(&FunctionContext, f64, f64) -> f64),
+    N3(fn(&FunctionContext, f64, f64, f64) -> f64),
+    /// Any number of arguments, at least the given count.
+    Variadic(usize, fn(&FunctionContext, &[f64]) -> f64),
+    /// Between the given counts of arguments, or at least as many with no
+    /// most, taken as they are rather than as floats. For functions only
+    /// defined exactly, e.g. on integers, which reject other arguments.
+    Exact(
+        usize,
+        Option<usize>,
+        fn(&[Value]) -> Result<Value, CalcError>,
+    ),
+    /// Alternatives told apart by their number of arguments.
+    Overloaded(Vec<Relation>),
+    /// Exactly the given count of arguments, passed unevaluated so that
+    /// e.g. `if` only evaluates the branch it takes.
+    Special(
+        usize,
+        fn(&FunctionContext, &Environment, &[Expr]) -> Result<Value, CalcError>,
+    ),
+    User(UserFunction),
+}
+
+impl Relation {
+    /// Fewest arguments taken, and the most unless variadic.
+    fn arity(&self) -> Vec<(usize, Option<usize>)> {
+        match self {
+            Relation::N1(_) => vec![(1, Some(1))],
+            Relation::N2(_) => vec![(2, Some(2))],
+            Relation::N3(_) => vec![(3, Some(3))],
+            Relation::Variadic(min, _) => vec![(*min, None)],
+            Relation::Exact(min, max, _) => vec![(*min, *max)],
+            Relation::Overloaded(overloads) => overloads.iter().flat_map(Relation::arity).collect(),
+            Relation::Special(n, _) => vec![(*n, Some(*n))],
+            Relation::User(func) => vec![(func.params.len(), Some(func.params.len()))],
+        }
+    }
+
+    /// The alternative taking `n` arguments.
+    pub(crate) fn select(&self, n: usize) -> &Relation {
+        match self {
+            Relation::Overloaded(overloads) => overloads
+                .iter()
+                .find(|r| {
+                    r.arity()
+                        .iter()
+                        .any(|&(min, max)| n >= min && max.is_none_or(|max| n <= max))
+                })
+                .unwrap_or(self),
+            relation => relation,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct Function {
-    token: &'static str,
-    relation: Relation,
+    pub(crate) token: Cow<'static, str>,
+    pub(crate) relation: Relation,
 }
 
 impl fmt::Debug for Function {
@@ -59,9 +179,36 @@ impl PartialEq for Function {
     }
 }
 
-#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FunctionContext {
     pub angle_unit: AngleUnit,
+    pub mode: NumberMode,
+    /// Radix of literals without a `0x`, `0o` or `0b` prefix, from 2 to 36.
+    pub ibase: u32,
+}
+
+impl Default for FunctionContext {
+    fn default() -> Self {
+        FunctionContext {
+            angle_unit: AngleUnit::default(),
+            mode: NumberMode::default(),
+            ibase: 10,
+        }
+    }
+}
+
+/// Numeric representation used for evaluation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumberMode {
+    #[default]
+    Float,
+    /// Arbitrary precision decimals, rounded to the given significant digits.
+    Decimal(usize),
+    /// Exact fractions, falling back to floats for irrational results.
+    Rational,
+    /// Exact integers of any size, falling back to floats for fractional
+    /// results.
+    Integer,
 }
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
@@ -73,62 +220,246 @@ pub enum AngleUnit {
 }
 
 impl Function {
-    pub fn apply(self, ctx: &FunctionContext, args: &[f64]) -> Result<f64, CalcError> {
-        let result = match self.relation {
-            Relation::N1(func) => (func)(ctx, args[0]),
-            Relation::N2(func) => (func)(ctx, args[0], args[1]),
+    pub fn apply(
+        self,
+        ctx: &FunctionContext,
+        env: &Environment,
+        args: &[Value],
+    ) -> Result<Value, CalcError> {
+        if let Relation::User(func) = &self.relation {
+            return func.call(ctx, env, args);
+        }
+        if args.iter().any(|a| matches!(a, Value::Bool(_))) {
+            return Err(CalcError::Math(MathError::Type(format!(
+                "Function '{}' needs numbers, got a boolean",
+                self.token
+            ))));
+        }
+        if args.iter().any(|a| matches!(a, Value::Symbolic(_))) {
+            return symbolic::apply(ctx, &self.token, args);
+        }
+        if let (Relation::Variadic(..) | Relation::Exact(_, None, _), true) = (
+            &self.relation,
+            args.iter().any(|a| matches!(a, Value::Matrix(_))),
+        ) {
+            // a matrix stands for the list of its entries
+            let args: Vec<Value> = args
+                .iter()
+                .flat_map(|a| match a {
+                    Value::Matrix(m) => m.entries().iter().copied().map(Value::Float).collect(),
+                    a => vec![a.clone()],
+                })
+                .collect();
+            return self.apply(ctx, env, &args);
+        }
+        if args.iter().any(|a| matches!(a, Value::Matrix(_))) {
+            return match matrix::apply(&self.token, args) {
+                Some(result) => result,
+                None => self.apply_entries(ctx, env, args),
+            };
+        }
+        if args.iter().any(|a| matches!(a, Value::Quantity(_))) {
+            return units::apply(&self.token, args);
+        }
+        if let Relation::Exact(_, _, func) = &self.relation {
+            return func(args);
+        }
+        let complex_args = || args.iter().map(Value::to_complex).collect::<Vec<_>>();
+        if args.iter().any(|a| matches!(a, Value::Complex(_))) {
+            return complex::apply(&self.token, ctx, &complex_args())
+                .filter(|z| z.is_finite())
+                .map(Value::from)
+                .ok_or(CalcError::Math(MathError::OutOfBounds));
+        }
+        match self.apply_real(ctx, args) {
+            // e.g. `sqrt(-1)`, retry off the real axis
+            Err(CalcError::Math(MathError::OutOfBounds)) => {
+                complex::apply(&self.token, ctx, &complex_args())
+                    .filter(|z| z.is_finite() && z.im != 0.)
+                    .map(Value::Complex)
+                    .ok_or(CalcError::Math(MathError::OutOfBounds))
+            }
+            result => result,
+        }
+    }
+
+    /// Apply a function of one argument to every entry of a matrix.
+    fn apply_entries(
+        &self,
+        ctx: &FunctionContext,
+        env: &Environment,
+        args: &[Value],
+    ) -> Result<Value, CalcError> {
+        let m = match args {
+            [Value::Matrix(m)] => m,
+            _ => {
+                return Err(CalcError::Math(MathError::Matrix(format!(
+                    "Function '{}' needs scalar arguments",
+                    self.token
+                ))))
+            }
+        };
+        let mut error = None;
+        let result = m.map(|x| {
+            match self
+                .clone()
+                .apply(ctx, env, &[Value::Float(x)])
+                .and_then(|y| matrix::scalar(&y))
+            {
+                Ok(y) => y,
+                Err(e) => {
+                    error.get_or_insert(e);
+                    f64::NAN
+                }
+            }
+        });
+        match error {
+            Some(e) => Err(e),
+            None => Ok(Value::Matrix(result)),
+        }
+    }
+
+    fn apply_real(&self, ctx: &FunctionContext, args: &[Value]) -> Result<Value, CalcError> {
+        let is_float = |a: &Value| matches!(a, Value::Float(_));
+        if args.iter().any(|a| matches!(a, Value::Decimal(_))) && !args.iter().any(is_float) {
+            let args = value::to_decimals(args)?;
+            return decimal::apply(&self.token, ctx, &args).map(Value::Decimal);
+        }
+        if let Some(result) =
+            value::to_rationals(args).and_then(|args| rational::apply(&self.token, &args))
+        {
+            return Ok(Value::Rational(result));
+        }
+        let x = |i: usize| args[i].to_f64();
+        let result = match self.relation.select(args.len()) {
+            Relation::N1(func) => (func)(ctx, x(0)),
+            Relation::N2(func) => (func)(ctx, x(0), x(1)),
+            Relation::N3(func) => (func)(ctx, x(0), x(1), x(2)),
+            Relation::Variadic(_, func) => {
+                (func)(ctx, &args.iter().map(Value::to_f64).collect::<Vec<_>>())
+            }
+            Relation::Exact(..)
+            | Relation::Overloaded(_)
+            | Relation::Special(..)
+            | Relation::User(_) => unreachable!(),
         };
         if result.is_finite() {
-            Ok(result)
+            Ok(Value::Float(result))
         } else {
             Err(CalcError::Math(MathError::OutOfBounds))
         }
     }
-    pub fn arity(&self) -> usize {
-        match self.relation {
-            Relation::N1(_) => 1,
-            Relation::N2(_) => 2,
+    pub fn accepts(&self, n: usize) -> bool {
+        self.relation
+            .arity()
+            .iter()
+            .any(|&(min, max)| n >= min && max.is_none_or(|max| n <= max))
+    }
+
+    /// Argument counts taken, e.g. `1 or 2 arguments` or `at least 2 arguments`.
+    pub fn describe_arity(&self) -> String {
+        let mut arity = self.relation.arity();
+        arity.sort();
+        let counts: Vec<String> = arity
+            .iter()
+            .map(|&(min, max)| match max {
+                Some(_) => min.to_string(),
+                None => format!("at least {}", min),
+            })
+            .collect();
+        let counts = match counts.split_last() {
+            Some((last, [])) => last.clone(),
+            Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+            None => unreachable!(),
+        };
+        match arity[..] {
+            [(1, _)] => format!("{} argument", counts),
+            _ => format!("{} arguments", counts),
         }
     }
+
+    /// How the function can be called, one line per overload, e.g.
+    /// `log(x, y)`.
+    pub fn signatures(&self) -> Vec<String> {
+        let mut arity = self.relation.arity();
+        arity.sort();
+        arity
+            .iter()
+            .map(|&(min, max)| {
+                let mut params: Vec<String> = match min {
+                    1 if max.is_some() => vec!["x".into()],
+                    2 if max.is_some() => vec!["x".into(), "y".into()],
+                    3 if max.is_some() => vec!["x".into(), "y".into(), "z".into()],
+                    n => (1..=n).map(|i| format!("x{}", i)).collect(),
+                };
+                if max.is_none() {
+                    params.push("...".into());
+                }
+                format!("{}({})", self.token, params.join(", "))
+            })
+            .collect()
+    }
 }
 
+/// Lexical unit of an expression, names are resolved during evaluation.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Operator(Operator),
-    Num(f64),
-    Function(Function),
+    Num(Value),
+    Ident(String),
+    /// `!` after an operand, factorial.
+    Bang,
+    /// `!` before an operand, logical not.
+    Not,
+    /// `~`, prefix bitwise complement.
+    Tilde,
+    LBracket,
+    RBracket,
+    /// Separates the rows of a matrix.
+    Semicolon,
     LParen,
     RParen,
     Comma,
+    /// `=` inside the arguments of a call, as in `solve(x^2 = 2, x)`.
+    Equals,
 }
 
-impl Token {
-    fn from_op(
-        token: char,
-        operation: fn(f64, f64) -> f64,
-        precedence: u8,
-        is_left_associative: bool,
-    ) -> Token {
-        Token::Operator(Operator {
-            token,
-            operation,
-            precedence,
-            is_left_associative,
-        })
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Operator(op) => write!(f, "{}", op.token),
+            Token::Num(x) => write!(f, "{}", x),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Bang | Token::Not => write!(f, "!"),
+            Token::Tilde => write!(f, "~"),
+            Token::LBracket => write!(f, "["),
+            Token::RBracket => write!(f, "]"),
+            Token::Semicolon => write!(f, ";"),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+            Token::Equals => write!(f, "="),
+        }
     }
 }
 
-pub static CONSTANTS: Lazy<HashMap<&str, Token>> = Lazy::new(|| {
+pub static CONSTANTS: Lazy<HashMap<&str, Value>> = Lazy::new(|| {
     let mut m = HashMap::new();
-    m.insert("e", Token::Num(std::f64::consts::E));
-    m.insert("pi", Token::Num(std::f64::consts::PI));
+    m.insert("e", Value::Float(std::f64::consts::E));
+    m.insert("pi", Value::Float(std::f64::consts::PI));
+    m.insert("i", Value::Complex(Complex64::i()));
+    m.insert("true", Value::Bool(true));
+    m.insert("false", Value::Bool(false));
     m
 });
 
-pub static FUNCTIONS: Lazy<HashMap<&str, Token>> = Lazy::new(|| {
+pub static FUNCTIONS: Lazy<HashMap<&str, Function>> = Lazy::new(|| {
     use Relation::*;
-    fn add_fn(map: &mut HashMap<&str, Token>, token: &'static str, relation: Relation) {
-        let func = Token::Function(Function { token, relation });
+    fn add_fn(map: &mut HashMap<&str, Function>, token: &'static str, relation: Relation) {
+        let func = Function {
+            token: Cow::Borrowed(token),
+            relation,
+        };
         map.insert(token, func);
     }
     let mut m = HashMap::new();
@@ -158,218 +489,469 @@ pub static FUNCTIONS: Lazy<HashMap<&str, Token>> = Lazy::new(|| {
     add_fn(&mut m, "acot", N1(|_ctx, x| (1. / x).atan()));
     add_fn(&mut m, "exp", N1(|_ctx, x| x.exp()));
     add_fn(&mut m, "exp2", N1(|_ctx, x| x.exp2()));
-    add_fn(&mut m, "round", N1(|_ctx, x| x.round()));
-    add_fn(&mut m, "log", N2(|_ctx, x, y| x.log(y)));
+    add_fn(
+        &mut m,
+        "round",
+        Overloaded(vec![
+            N1(|_ctx, x| x.round()),
+            N2(|_ctx, x, digits| round_to(x, digits)),
+        ]),
+    );
+    add_fn(
+        &mut m,
+        "log",
+        Overloaded(vec![N1(|_ctx, x| x.log10()), N2(|_ctx, x, y| x.log(y))]),
+    );
     add_fn(&mut m, "nroot", N2(|_ctx, x, y| x.powf(1. / y)));
+    add_fn(&mut m, "gamma", N1(|_ctx, x| special::gamma(x)));
+    add_fn(&mut m, "lgamma", N1(|_ctx, x| special::lgamma(x)));
+    add_fn(&mut m, "digamma", N1(|_ctx, x| special::digamma(x)));
+    add_fn(&mut m, "beta", N2(|_ctx, a, b| special::beta(a, b)));
+    add_fn(&mut m, "re", N1(|_ctx, x| x));
+    add_fn(&mut m, "im", N1(|_ctx, _| 0.));
+    add_fn(
+        &mut m,
+        "arg",
+        N1(|ctx, x| match x < 0. {
+            true => from_rad(ctx, std::f64::consts::PI),
+            false => 0.,
+        }),
+    );
+    add_fn(&mut m, "conj", N1(|_ctx, x| x));
+    add_fn(
+        &mut m,
+        "if",
+        Special(3, |ctx, env, args| {
+            let condition = args[0].eval(ctx, env)?;
+            match logic::truth(&condition).map_err(|e| e.at(args[0].span()))? {
+                true => args[1].eval(ctx, env),
+                false => args[2].eval(ctx, env),
+            }
+        }),
+    );
+    add_fn(
+        &mut m,
+        "approx",
+        Special(3, |ctx, env, args| {
+            let args = args
+                .iter()
+                .map(|arg| arg.eval(ctx, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            logic::approx(&args[0], &args[1], &args[2])
+        }),
+    );
+    // the expression is evaluated with the variable bound to many values
+    add_fn(&mut m, "deriv", Special(3, calculus::deriv));
+    add_fn(&mut m, "integrate", Special(4, calculus::integrate));
+    // roots of an expression, or `solve(A, b)` for linear systems
+    add_fn(
+        &mut m,
+        "solve",
+        Overloaded(vec![
+            Special(2, roots::solve),
+            Special(3, roots::solve),
+            Special(4, roots::solve),
+        ]),
+    );
+    // symbolic, variables without a value stay in the result
+    add_fn(
+        &mut m,
+        "diff",
+        Overloaded(vec![Special(2, symbolic::diff), Special(3, symbolic::diff)]),
+    );
+    add_fn(&mut m, "simplify", Special(1, symbolic::simplify));
+    // linear algebra, numbers act as 1x1 matrices
+    add_fn(&mut m, "det", N1(|_ctx, x| x));
+    add_fn(&mut m, "inv", N1(|_ctx, x| 1. / x));
+    add_fn(&mut m, "transpose", N1(|_ctx, x| x));
+    add_fn(&mut m, "rank", N1(|_ctx, x| if x == 0. { 0. } else { 1. }));
+    add_fn(&mut m, "eig", N1(|_ctx, x| x));
+    add_fn(&mut m, "dot", N2(|_ctx, x, y| x * y));
+    add_fn(&mut m, "cross", Exact(2, Some(2), matrix::cross));
+    // integers only, evaluated exactly whatever the number mode
+    add_fn(&mut m, "gcd", Exact(2, None, integer::gcd));
+    add_fn(&mut m, "lcm", Exact(2, None, integer::lcm));
+    add_fn(&mut m, "modpow", Exact(3, Some(3), integer::modpow));
+    add_fn(&mut m, "modinv", Exact(2, Some(2), integer::modinv));
+    add_fn(&mut m, "ncr", Exact(2, Some(2), integer::ncr));
+    add_fn(&mut m, "npr", Exact(2, Some(2), integer::npr));
+    add_fn(&mut m, "multinomial", Exact(1, None, integer::multinomial));
+    add_fn(&mut m, "catalan", Exact(1, Some(1), integer::catalan));
+    add_fn(&mut m, "stirling2", Exact(2, Some(2), integer::stirling2));
+    add_fn(&mut m, "bell", Exact(1, Some(1), integer::bell));
+    add_fn(&mut m, "isprime", Exact(1, Some(1), integer::isprime));
+    add_fn(&mut m, "nextprime", Exact(1, Some(1), integer::nextprime));
+    add_fn(&mut m, "prevprime", Exact(1, Some(1), integer::prevprime));
+    add_fn(&mut m, "factor", Exact(1, Some(1), integer::factor));
+    add_fn(&mut m, "totient", Exact(1, Some(1), integer::totient));
+    add_fn(&mut m, "divisors", Exact(1, Some(1), integer::divisors));
+    // statistics over any number of arguments
+    add_fn(&mut m, "sum", Variadic(1, |_ctx, xs| stats::sum(xs)));
+    add_fn(&mut m, "mean", Variadic(1, |_ctx, xs| stats::mean(xs)));
+    add_fn(&mut m, "median", Variadic(1, |_ctx, xs| stats::median(xs)));
+    add_fn(&mut m, "mode", Variadic(1, |_ctx, xs| stats::mode(xs)));
+    add_fn(
+        &mut m,
+        "var",
+        Variadic(2, |_ctx, xs| stats::variance(xs, true)),
+    );
+    add_fn(
+        &mut m,
+        "pvar",
+        Variadic(1, |_ctx, xs| stats::variance(xs, false)),
+    );
+    add_fn(
+        &mut m,
+        "stddev",
+        Variadic(2, |_ctx, xs| stats::variance(xs, true).sqrt()),
+    );
+    add_fn(
+        &mut m,
+        "pstddev",
+        Variadic(1, |_ctx, xs| stats::variance(xs, false).sqrt()),
+    );
+    add_fn(
+        &mut m,
+        "percentile",
+        Variadic(2, |_ctx, xs| stats::percentile(xs[0], &xs[1..])),
+    );
+    add_fn(
+        &mut m,
+        "min",
+        Variadic(1, |_ctx, xs| {
+            xs.iter().copied().fold(f64::INFINITY, f64::min)
+        }),
+    );
+    add_fn(
+        &mut m,
+        "max",
+        Variadic(1, |_ctx, xs| {
+            xs.iter().copied().fold(f64::NEG_INFINITY, f64::max)
+        }),
+    );
     m
 });
 
-pub static OPERATORS: Lazy<HashMap<char, Token>> = Lazy::new(|| {
+pub static OPERATORS: Lazy<HashMap<&str, Operator>> = Lazy::new(|| {
     fn add_op(
-        map: &mut HashMap<char, Token>,
-        token: char,
+        map: &mut HashMap<&str, Operator>,
+        token: &'static str,
         operation: fn(f64, f64) -> f64,
         precedence: u8,
         is_left_associative: bool,
     ) {
-        let op = Token::from_op(token, operation, precedence, is_left_associative);
+        let op = Operator {
+            token,
+            operation,
+            precedence,
+            is_left_associative,
+        };
         map.insert(token, op);
     }
     let mut m = HashMap::new();
-    add_op(&mut m, '+', |x, y| x + y, 2, true);
-    add_op(&mut m, '-', |x, y| x - y, 2, true);
-    add_op(&mut m, '*', |x, y| x * y, 3, true);
-    add_op(&mut m, '/', |x, y| x / y, 3, true);
-    add_op(&mut m, '%', |x, y| x % y, 3, true);
-    add_op(&mut m, '^', |x, y| x.powf(y), 4, false);
-    add_op(&mut m, '!', |x, _| factorial(x), 4, true);
+    // C-like precedence
+    add_op(&mut m, "to", |x, _| x, 0, true);
+    add_op(&mut m, "||", |x, y| bool_f64(x != 0. || y != 0.), 1, true);
+    add_op(&mut m, "&&", |x, y| bool_f64(x != 0. && y != 0.), 2, true);
+    add_op(
+        &mut m,
+        "|",
+        |x, y| ((x as i64) | (y as i64)) as f64,
+        3,
+        true,
+    );
+    add_op(
+        &mut m,
+        "xor",
+        |x, y| ((x as i64) ^ (y as i64)) as f64,
+        4,
+        true,
+    );
+    add_op(
+        &mut m,
+        "&",
+        |x, y| ((x as i64) & (y as i64)) as f64,
+        5,
+        true,
+    );
+    add_op(&mut m, "==", |x, y| bool_f64(x == y), 6, true);
+    add_op(&mut m, "!=", |x, y| bool_f64(x != y), 6, true);
+    add_op(&mut m, "<", |x, y| bool_f64(x < y), 7, true);
+    add_op(&mut m, "<=", |x, y| bool_f64(x <= y), 7, true);
+    add_op(&mut m, ">", |x, y| bool_f64(x > y), 7, true);
+    add_op(&mut m, ">=", |x, y| bool_f64(x >= y), 7, true);
+    add_op(&mut m, "<<", |x, y| x * y.exp2(), 8, true);
+    add_op(&mut m, ">>", |x, y| (x / y.exp2()).floor(), 8, true);
+    add_op(&mut m, "+", |x, y| x + y, 9, true);
+    add_op(&mut m, "-", |x, y| x - y, 9, true);
+    add_op(&mut m, "*", |x, y| x * y, 10, true);
+    add_op(&mut m, "/", |x, y| x / y, 10, true);
+    add_op(&mut m, "//", |x, y| (x / y).floor(), 10, true);
+    add_op(&mut m, "%", |x, y| x % y, 10, true);
+    // 11 is the product of a number and its unit, see `UNIT_PRODUCT`
+    add_op(&mut m, "^", |x, y| x.powf(y), 12, false);
     m
 });
 
-fn factorial(n: f64) -> f64 {
-    let answer = (1..=n.round() as u128).map(|u| u as f64).product::<f64>() as f64;
-    if answer == 0.0 {
-        return f64::INFINITY;
+/// Juxtaposition of a number and a unit, a product binding tighter than `/`
+/// so that `100 km / 2 h` is a speed.
+static UNIT_PRODUCT: Lazy<Operator> = Lazy::new(|| Operator {
+    precedence: OPERATORS["^"].precedence - 1,
+    ..OPERATORS["*"]
+});
+
+/// Names the lexer reads as operators, they cannot name anything else.
+pub(crate) const KEYWORDS: [&str; 3] = ["xor", "to", "in"];
+
+fn bool_f64(b: bool) -> f64 {
+    f64::from(u8::from(b))
+}
+
+/// `x!`, defined as `gamma(x + 1)` for non-negative numbers.
+pub(crate) fn factorial(x: Value) -> Result<Value, CalcError> {
+    match x {
+        Value::Rational(r) if !r.is_integer() && !r.is_negative() => {
+            factorial(Value::Float(Value::Rational(r).to_f64()))
+        }
+        Value::Rational(r) => rational::factorial(&r).map(Value::Rational),
+        Value::Factors(f) => factorial(f.number),
+        Value::Decimal(d) => d.factorial().map(Value::Decimal),
+        Value::Complex(_) | Value::Quantity(_) | Value::Matrix(_) => {
+            Err(CalcError::Math(MathError::OutOfBounds))
+        }
+        Value::Bool(_) | Value::Symbolic(_) => Err(CalcError::Math(MathError::Type(format!(
+            "Operator '!' needs a number, got {}",
+            x.kind()
+        )))),
+        Value::Float(n) if n.is_nan() || n < 0.0 => Err(CalcError::Math(MathError::OutOfBounds)),
+        Value::Float(n) => {
+            // past 171.62 gamma no longer fits in a f64
+            let answer = special::gamma(n + 1.);
+            if answer.is_finite() {
+                Ok(Value::Float(answer))
+            } else {
+                Err(CalcError::Math(MathError::TooLarge))
+            }
+        }
     }
-    answer
 }
 
-pub(crate) fn lexer(input: &str, prev_ans: Option<f64>) -> Result<Vec<Token>, CalcError> {
-    let mut num_vec: String = String::new();
-    let mut char_vec: String = String::new();
-    let mut result: Vec<Token> = vec![];
-    let mut last_char_is_op = true;
+/// Byte range of the input a token or error refers to.
+pub type Span = std::ops::Range<usize>;
 
-    let mut chars = input.chars().peekable();
-    while let Some(mut letter) = chars.next() {
-        match letter {
+pub(crate) fn lexer(
+    input: &str,
+    ctx: &FunctionContext,
+    prev_ans: Option<&Value>,
+) -> Result<Vec<(Token, Span)>, CalcError> {
+    let mut result: Vec<(Token, Span)> = vec![];
+    let mut chars = input.char_indices().peekable();
+    while let Some((start, letter)) = chars.next() {
+        let mut end = start + letter.len_utf8();
+        let token = match letter {
             '0'..='9' | '.' => {
-                if !char_vec.is_empty() {
-                    if FUNCTIONS.get(&char_vec[..]).is_some() {
-                        char_vec.push(letter);
-                        if FUNCTIONS.get(&char_vec[..]).is_none()
-                            && !FUNCTIONS.keys().any(|k| k.starts_with(&char_vec))
-                        {
-                            return Err(CalcError::Syntax(format!(
-                                "Function '{}' expected parentheses",
-                                &char_vec[..char_vec.chars().count() - 1]
-                            )));
-                        }
-                    } else if CONSTANTS.get(&char_vec[..]).is_some() {
-                        result.push(CONSTANTS.get(&char_vec[..]).unwrap().clone());
-                        result.push(OPERATORS.get(&'*').unwrap().clone());
-                        char_vec.clear();
-                        num_vec.push(letter);
-                        last_char_is_op = false;
-                    } else {
-                        char_vec.push(letter);
-                        if FUNCTIONS.get(&char_vec[..]).is_none() {
-                            return Err(CalcError::Syntax(format!(
-                                "Unexpected character '{}'",
-                                char_vec
-                            )));
-                        }
+                let prefix = match (letter, chars.peek()) {
+                    ('0', Some((_, 'x'))) => Some(16),
+                    ('0', Some((_, 'o'))) => Some(8),
+                    ('0', Some((_, 'b'))) => Some(2),
+                    _ => None,
+                };
+                let radix = match prefix {
+                    Some(radix) => {
+                        chars.next();
+                        end += 1;
+                        radix
                     }
-                } else {
-                    num_vec.push(letter);
-                    last_char_is_op = false;
-                }
-            }
-            '_' => {
-                if prev_ans.is_none() {
-                    return Err(CalcError::Syntax("No previous answer!".into()));
+                    None => ctx.ibase,
+                };
+                let mut last = letter;
+                let mut exponent = false;
+                while let Some(&(i, c)) = chars.peek() {
+                    // `1.5e-3` has a decimal exponent, while `2e` and `2e-x` are
+                    // still products with `e`
+                    let rest = &input[i + c.len_utf8()..];
+                    let rest = rest.strip_prefix(['+', '-']).unwrap_or(rest);
+                    let starts_exponent = radix == 10
+                        && !exponent
+                        && matches!(c, 'e' | 'E')
+                        && rest.starts_with(|c: char| c.is_ascii_digit());
+                    // letters are only digits after a prefix or in upper case, bc-style,
+                    // so `2e` is still `2 * e` in base 16
+                    let continues = c == '.'
+                        || c.is_ascii_digit()
+                        || starts_exponent
+                        || (c.is_digit(radix) && (prefix.is_some() || c.is_ascii_uppercase()))
+                        || (prefix == Some(16) && matches!(c, 'p' | 'P'))
+                        || (matches!(last, 'p' | 'P') && matches!(c, '+' | '-'))
+                        || (exponent && matches!(last, 'e' | 'E') && matches!(c, '+' | '-'));
+                    if !continues {
+                        break;
+                    }
+                    exponent |= starts_exponent;
+                    last = c;
+                    end = i + c.len_utf8();
+                    chars.next();
                 }
-                if !char_vec.is_empty() {
-                    if FUNCTIONS.get(&char_vec[..]).is_some() {
-                        return Err(CalcError::Syntax(format!(
-                            "Function '{}' expected parentheses",
-                            char_vec
-                        )));
-                    } else {
+                let parsed = match prefix {
+                    Some(radix) => Value::parse_radix(ctx, &input[start + 2..end], radix),
+                    None => Value::parse(ctx, &input[start..end]),
+                };
+                match parsed {
+                    Some(x) => Token::Num(x),
+                    None => {
                         return Err(CalcError::Syntax(format!(
-                            "Unexpected character '{}'",
-                            char_vec
-                        )));
+                            "Invalid number '{}'",
+                            &input[start..end]
+                        ))
+                        .at(start..end))
                     }
                 }
-                let parse_num = num_vec.parse::<f64>().ok();
-                if let Some(x) = parse_num {
-                    result.push(Token::Num(x));
-                    result.push(OPERATORS.get(&'*').unwrap().clone());
-                    num_vec.clear();
-                }
-                last_char_is_op = false;
-                result.push(Token::Num(prev_ans.unwrap()));
-            }
-            'a'..='z' | 'A'..='Z' => {
-                let parse_num = num_vec.parse::<f64>().ok();
-                if let Some(x) = parse_num {
-                    result.push(Token::Num(x));
-                    result.push(OPERATORS.get(&'*').unwrap().clone());
-                    num_vec.clear();
-                }
-                char_vec.push(letter);
-                last_char_is_op = false;
             }
-            '+' | '-' => {
-                let op_token = OPERATORS.get(&letter).unwrap().clone();
-                let parse_num = num_vec.parse::<f64>().ok();
-                if !last_char_is_op {
-                    if let Some(x) = parse_num {
-                        result.push(Token::Num(x));
-                        num_vec.clear();
-                        last_char_is_op = true;
-                    } else if let Some(token) = CONSTANTS.get(&char_vec[..]) {
-                        result.push(token.clone());
-                        char_vec.clear();
-                        last_char_is_op = true;
+            // `°` starts temperature units like `°C`
+            c if c.is_alphabetic() || c == '°' => {
+                while let Some(&(i, c)) = chars.peek() {
+                    // digits only continue a name if they spell a function, as in `log2`
+                    let continued = &input[start..i + c.len_utf8()];
+                    let continues = c.is_alphabetic()
+                        || (c.is_ascii_digit()
+                            && FUNCTIONS.keys().any(|k| k.starts_with(continued)));
+                    if !continues {
+                        break;
                     }
-                    result.push(op_token);
-                } else if last_char_is_op {
-                    result.push(Token::LParen);
-                    result.push(Token::Num(
-                        (letter.to_string() + "1").parse::<f64>().unwrap(),
-                    ));
-                    result.push(Token::RParen);
-                    result.push(Token::from_op('*', |x, y| x * y, 10, true));
-                }
-            }
-            '/' | '*' | '%' | '^' | '!' => {
-                drain_stack(&mut num_vec, &mut char_vec, &mut result);
-                if letter == '*' && chars.peek() == Some(&'*') {
-                    // Accept `**` operator as meaning `^` (exponentation).
-                    let _ = chars.next();
-                    letter = '^';
+                    end = continued.len() + start;
+                    chars.next();
                 }
-                let operator_token: Token = OPERATORS.get(&letter).unwrap().clone();
-                result.push(operator_token);
-                last_char_is_op = true;
-                if letter == '!' {
-                    result.push(Token::Num(1.));
-                    last_char_is_op = false;
+                match &input[start..end] {
+                    "xor" => Token::Operator(OPERATORS["xor"]),
+                    "to" | "in" => Token::Operator(OPERATORS["to"]),
+                    name => Token::Ident(name.to_string()),
                 }
             }
-            '(' => {
-                if !char_vec.is_empty() {
-                    if let Some(res) = FUNCTIONS.get(&char_vec[..]) {
-                        result.push(res.clone());
-                    } else {
-                        return Err(CalcError::Syntax(format!(
-                            "Unknown function '{}'",
-                            char_vec
-                        )));
-                    }
-                    char_vec.clear();
-                } else {
-                    let parse_num = num_vec.parse::<f64>().ok();
-                    if let Some(x) = parse_num {
-                        result.push(Token::Num(x));
-                        result.push(OPERATORS.get(&'*').unwrap().clone());
-                        num_vec.clear();
-                    }
-                }
-
-                if let Some(Token::RParen) = result.last() {
-                    result.push(OPERATORS.get(&'*').unwrap().clone());
-                }
-                result.push(Token::LParen);
-                last_char_is_op = true;
+            '_' => match prev_ans {
+                Some(ans) => Token::Num(ans.clone()),
+                None => return Err(CalcError::Syntax("No previous answer!".into()).at(start..end)),
+            },
+            '*' if matches!(chars.peek(), Some((_, '*'))) => {
+                // Accept `**` operator as meaning `^` (exponentation).
+                let _ = chars.next();
+                end += 1;
+                Token::Operator(OPERATORS["^"])
+            }
+            '/' if matches!(chars.peek(), Some((_, '/'))) => {
+                let _ = chars.next();
+                end += 1;
+                Token::Operator(OPERATORS["//"])
+            }
+            // `<<`, `&&` and the like, or `<=`, `==` and `!=`
+            '<' | '>' | '&' | '|' | '=' | '!'
+                if chars.peek().is_some_and(|&(_, c)| match letter {
+                    '<' | '>' => c == letter || c == '=',
+                    '&' | '|' => c == letter,
+                    _ => c == '=',
+                }) =>
+            {
+                let _ = chars.next();
+                end += 1;
+                Token::Operator(OPERATORS[&input[start..end]])
             }
-            ',' => {
-                drain_stack(&mut num_vec, &mut char_vec, &mut result);
-                result.push(Token::Comma);
+            '+' | '-' | '*' | '/' | '%' | '^' | '&' | '|' | '<' | '>' => {
+                Token::Operator(OPERATORS[&input[start..end]])
             }
-            ')' => {
-                drain_stack(&mut num_vec, &mut char_vec, &mut result);
-                result.push(Token::RParen);
-                last_char_is_op = false;
+            '=' => Token::Equals,
+            '~' => Token::Tilde,
+            '!' if ends_operand(result.last().map(|(token, _)| token)) => Token::Bang,
+            '!' => Token::Not,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            ',' => Token::Comma,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            ';' => Token::Semicolon,
+            c if c.is_whitespace() => continue,
+            _ => {
+                return Err(
+                    CalcError::Syntax(format!("Unexpected token: '{}'", letter)).at(start..end)
+                )
+            }
+        };
+        // juxtaposition multiplies, as in `2pi` or `(1 + 2)(3 + 4)`
+        let last = result.last().map(|(token, _)| token);
+        let starts_operand = matches!(
+            token,
+            Token::Num(_) | Token::Ident(_) | Token::LParen | Token::LBracket
+        );
+        let is_call = matches!(last, Some(Token::Ident(_))) && token == Token::LParen;
+        if ends_operand(last) && starts_operand && !is_call {
+            let op = match (last, &token) {
+                (Some(Token::Num(_)), Token::Ident(name)) if is_unit(name) => *UNIT_PRODUCT,
+                _ => OPERATORS["*"],
+            };
+            result.push((Token::Operator(op), start..start));
+        }
+        // `2 min(a, b)` calls a function rather than naming a unit
+        if is_call && result.len() >= 2 {
+            let i = result.len() - 2;
+            if result[i].0 == Token::Operator(*UNIT_PRODUCT) {
+                result[i].0 = Token::Operator(OPERATORS["*"]);
             }
-            ' ' => {}
-            _ => return Err(CalcError::Syntax(format!("Unexpected token: '{}'", letter))),
         }
+        result.push((token, start..end));
     }
-    // println!("{:?}", result);
-    drain_stack(&mut num_vec, &mut char_vec, &mut result);
     Ok(result)
 }
 
-fn drain_stack(num_vec: &mut String, char_vec: &mut String, result: &mut Vec<Token>) {
-    let parse_num = num_vec.parse::<f64>().ok();
-    if let Some(x) = parse_num {
-        result.push(Token::Num(x));
-        num_vec.clear();
-    } else if let Some(token) = CONSTANTS.get(&char_vec[..]) {
-        result.push(token.clone());
-        char_vec.clear();
+/// Whether `token` can be the last one of an operand, so that a following
+/// `!` is a factorial rather than a not.
+fn ends_operand(token: Option<&Token>) -> bool {
+    matches!(
+        token,
+        Some(Token::Num(_) | Token::Ident(_) | Token::RParen | Token::RBracket | Token::Bang)
+    )
+}
+
+/// Whether `name` reads as a unit rather than a constant.
+fn is_unit(name: &str) -> bool {
+    !CONSTANTS.contains_key(name) && units::lookup(name).is_some()
+}
+
+/// Resolve a name to a constant or a session variable.
+pub(crate) fn lookup(name: &str, ctx: &FunctionContext, env: &Environment) -> Option<Value> {
+    Value::constant(ctx, name)
+        .or_else(|| CONSTANTS.get(name).cloned())
+        .or_else(|| env.get(name))
+        .or_else(|| units::lookup(name))
+}
+
+/// `x` rounded to `digits` decimal places, to tens or hundreds for negative
+/// `digits`.
+pub(crate) fn round_to(x: f64, digits: f64) -> f64 {
+    if digits.fract() != 0. {
+        return f64::NAN;
+    }
+    let scale = 10f64.powf(digits.abs());
+    match digits < 0. {
+        true => (x / scale).round() * scale,
+        false if scale.is_finite() => (x * scale).round() / scale,
+        // more digits than a f64 holds
+        false => x,
+    }
+}
+
+/// An angle in the session's angle unit in radians, for trigonometric
+/// functions.
+pub(crate) fn rad(ctx: &FunctionContext, x: f64) -> f64 {
+    match ctx.angle_unit {
+        AngleUnit::Radian => x,
+        AngleUnit::Degree => x.to_radians(),
+        AngleUnit::Gradian => x * std::f64::consts::PI / 200.,
     }
 }
 
-/// Convert to radian if radian_mode is enabled.
-fn rad(ctx: &FunctionContext, x: f64) -> f64 {
-    // TODO gradian
-    if ctx.angle_unit == AngleUnit::Radian {
-        x
-    } else {
-        x.to_radians()
+/// An angle in radians in the session's angle unit, as `arg` returns it.
+pub(crate) fn from_rad(ctx: &FunctionContext, x: f64) -> f64 {
+    match ctx.angle_unit {
+        AngleUnit::Radian => x,
+        AngleUnit::Degree => x.to_degrees(),
+        AngleUnit::Gradian => x * 200. / std::f64::consts::PI,
     }
 }
@@ -5,68 +5,199 @@
 //! ## Examples
 //!
 //! ```
-//! use eva::lex::FunctionContext;
+//! use eva::ast::Expr;
+//! use eva::env::Environment;
+//! use eva::lex::{FunctionContext, NumberMode};
+//! use eva::Value;
 //!
 //! let ctx = FunctionContext::default();
-//! assert_eq!(eva::eval_expr(&ctx, 10, "1 + 1", None), Ok(2.));
+//! let mut env = Environment::new();
+//! let ans = eva::eval_expr(&ctx, &mut env, 10, "1 + 1", None);
+//! assert_eq!(ans, Ok(Some(Value::Float(2.))));
+//!
+//! eva::eval_expr(&ctx, &mut env, 10, "rate = 0.5", None).unwrap();
+//! eva::eval_expr(&ctx, &mut env, 10, "hyp(a, b) = sqrt(a^2 + b^2)", None).unwrap();
+//! let ans = eva::eval_expr(&ctx, &mut env, 10, "hyp(3, 8rate)", None);
+//! assert_eq!(ans, Ok(Some(Value::Float(5.))));
+//!
+//! // arbitrary precision, to 50 significant digits
+//! let ctx = FunctionContext {
+//!     mode: NumberMode::Decimal(50),
+//!     ..Default::default()
+//! };
+//! let ans = eva::eval_expr(&ctx, &mut env, 10, "sqrt(2)", None).unwrap().unwrap();
+//! assert!(ans
+//!     .to_string()
+//!     .starts_with("1.4142135623730950488016887242096980785696718753769"));
+//!
+//! // expressions can be parsed into a tree, inspected and printed back
+//! let ctx = FunctionContext::default();
+//! let expr = eva::parse_expr(&ctx, "2(1+x)^2", None).unwrap();
+//! assert!(matches!(expr, Expr::Binary { op: "*", .. }));
+//! assert_eq!(expr.to_string(), "2 * (1 + x) ^ 2");
+//! env.set("x", Value::Float(2.)).unwrap();
+//! assert_eq!(expr.eval(&ctx, &env), Ok(Value::Float(18.)));
 //! ```
 /*
  *  eva - an easy to use calculator REPL similar to bc(1)
  *  Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
  */
-use std::cmp::Ordering;
+use num::complex::Complex64;
 
+pub mod ast;
+mod bitwise;
+mod calculus;
+mod combinatorics;
+mod complex;
+pub mod decimal;
+pub mod env;
 pub mod error;
+mod integer;
 pub mod lex;
+mod logic;
+pub mod matrix;
 mod parse;
+pub mod plot;
+pub mod primes;
+mod rational;
+mod roots;
+pub mod script;
+mod special;
+mod stats;
+pub mod symbolic;
+pub mod units;
+pub mod value;
 
+use crate::ast::Expr;
+use crate::env::Environment;
 pub use crate::error::CalcError;
 use crate::lex::*;
 use crate::parse::*;
+pub use crate::value::Value;
 
+/// Close parentheses left open at the end of `input`.
 fn autobalance_parens(input: &str) -> Result<String, CalcError> {
-    let mut balanced = String::from(input);
-    let mut left_parens = 0;
-    let mut right_parens = 0;
-    for letter in input.chars() {
+    let mut depth = 0usize;
+    for (i, letter) in input.char_indices() {
         if letter == '(' {
-            left_parens += 1;
+            depth += 1;
         } else if letter == ')' {
-            right_parens += 1;
+            depth = depth
+                .checked_sub(1)
+                .ok_or_else(|| CalcError::Syntax("Mismatched parentheses!".into()).at(i..i + 1))?;
         }
     }
-
-    match left_parens.cmp(&right_parens) {
-        Ordering::Greater => {
-            let extras = ")".repeat(left_parens - right_parens);
-            balanced.push_str(&extras[..]);
-            Ok(balanced)
-        }
-        Ordering::Equal => Ok(balanced),
-        Ordering::Less => Err(CalcError::Syntax("Mismatched parentheses!".into())),
-    }
+    Ok(format!("{}{}", input, ")".repeat(depth)))
 }
 
 /// Evaluate math expression. Main entry function for eva.
+///
+/// Input of the form `name = expr` assigns the result to a variable in `env`
+/// and returns it, `name(a, b) = expr` defines a function and returns nothing.
 pub fn eval_expr(
     ctx: &FunctionContext,
+    env: &mut Environment,
     fix: usize,
     input: &str,
-    prev_ans: Option<f64>,
-) -> Result<f64, CalcError> {
-    let input = input.trim().replace(' ', "");
-    if input == "help" {
-        return Err(CalcError::Help);
-    }
-    if input.is_empty() {
-        return Ok(0.);
-    }
-    let input = autobalance_parens(&input[..])?;
-    let lexed = lexer(&input[..], prev_ans)?;
-    let postfixed = to_postfix(lexed)?;
-    let evaled = eval_postfix(ctx, postfixed)?;
-    let evaled_fixed = format!("{:.*}", fix, evaled).parse::<f64>().unwrap();
-    Ok(evaled_fixed)
+    prev_ans: Option<&Value>,
+) -> Result<Option<Value>, CalcError> {
+    if input.replace(' ', "") == "help" {
+        let user_functions = env.functions().iter().map(|f| f.to_string()).collect();
+        return Err(CalcError::Help(user_functions));
+    }
+    let round = |x: f64| format!("{:.*}", fix, x).parse().unwrap();
+    let fixed = |value: Value| match value {
+        Value::Float(x) => Value::Float(round(x)),
+        Value::Complex(z) => Value::from(Complex64::new(round(z.re), round(z.im))),
+        Value::Quantity(mut q) => {
+            q.value = round(q.value);
+            Value::Quantity(q)
+        }
+        Value::Matrix(m) => Value::Matrix(m.map(round)),
+        value => value,
+    };
+    if let Some((lhs, rhs)) = split_assignment(input) {
+        let lhs_span = lhs.len() - lhs.trim_start().len()..lhs.trim_end().len();
+        let lhs = lhs.replace(' ', "");
+        if let Some((name, params)) = lhs.strip_suffix(')').and_then(|l| l.split_once('(')) {
+            let params: Vec<&str> = match params {
+                "" => vec![],
+                _ => params.split(',').collect(),
+            };
+            let body_offset = input.len() - rhs.trim_start().len();
+            env.define(ctx, name, &params, rhs.trim())
+                .map_err(|e| e.shift(body_offset).at(lhs_span))?;
+            return Ok(None);
+        }
+        let rhs_offset = input.len() - rhs.len();
+        let value = eval_body(ctx, env, rhs, prev_ans).map_err(|e| e.shift(rhs_offset))?;
+        let value = fixed(value);
+        env.set(&lhs, value.clone()).map_err(|e| e.at(lhs_span))?;
+        return Ok(Some(value));
+    }
+    Ok(Some(fixed(eval_body(ctx, env, input, prev_ans)?)))
+}
+
+/// Parse `input` as `eval_expr` reads it without evaluating anything, a cheap
+/// check while it is being typed.
+pub fn check_syntax(
+    ctx: &FunctionContext,
+    input: &str,
+    prev_ans: Option<&Value>,
+) -> Result<(), CalcError> {
+    if input.replace(' ', "") == "help" {
+        return Ok(());
+    }
+    let body = split_assignment(input).map_or(input, |(_, rhs)| rhs);
+    parse_expr(ctx, body.trim(), prev_ans).map(|_| ())
+}
+
+/// Split `name = expr` at its `=`, which is neither part of a comparison like
+/// `==` or `<=` nor inside parentheses.
+fn split_assignment(input: &str) -> Option<(&str, &str)> {
+    let mut depth = 0usize;
+    let mut prev = None;
+    let mut chars = input.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            '=' if depth == 0
+                && !matches!(prev, Some('=' | '!' | '<' | '>'))
+                && !matches!(chars.peek(), Some((_, '='))) =>
+            {
+                return Some((&input[..i], &input[i + 1..]));
+            }
+            _ => {}
+        }
+        prev = Some(c);
+    }
+    None
+}
+
+/// Evaluate an expression without assignments, also used for function bodies.
+pub(crate) fn eval_body(
+    ctx: &FunctionContext,
+    env: &Environment,
+    input: &str,
+    prev_ans: Option<&Value>,
+) -> Result<Value, CalcError> {
+    if input.trim().is_empty() {
+        return Ok(Value::Float(0.));
+    }
+    parse_expr(ctx, input, prev_ans)?.eval(ctx, env)
+}
+
+/// Parse `input` into an expression tree without evaluating it, `_` is
+/// replaced by `prev_ans`.
+pub fn parse_expr(
+    ctx: &FunctionContext,
+    input: &str,
+    prev_ans: Option<&Value>,
+) -> Result<Expr, CalcError> {
+    let balanced = autobalance_parens(input)?;
+    let lexed = lexer(&balanced, ctx, prev_ans)?;
+    parse(lexed, input.len())
 }
 
 #[cfg(test)]
@@ -76,9 +207,35 @@ mod tests {
 
     pub fn eval(input: &str, prev_ans: Option<f64>) -> Result<f64, CalcError> {
         let ctx = FunctionContext::default();
+        let mut env = Environment::new();
         let fix = 10;
-        let ans = eval_expr(&ctx, fix, input, prev_ans)?;
-        Ok(format!("{:.*}", fix, ans).parse().unwrap())
+        let prev_ans = prev_ans.map(Value::Float);
+        let ans = eval_expr(&ctx, &mut env, fix, input, prev_ans.as_ref())
+            .map_err(CalcError::without_span)?
+            .unwrap();
+        Ok(format!("{:.*}", fix, ans.to_f64()).parse().unwrap())
+    }
+
+    /// Evaluates inputs one after another in a session with `ctx`, errors
+    /// without their spans.
+    fn session(ctx: FunctionContext) -> impl FnMut(&str) -> Result<Option<Value>, CalcError> {
+        let mut env = Environment::new();
+        move |input| eval_expr(&ctx, &mut env, 10, input, None).map_err(CalcError::without_span)
+    }
+    fn rational() -> FunctionContext {
+        FunctionContext {
+            mode: NumberMode::Rational,
+            ..Default::default()
+        }
+    }
+    fn ratio(n: i64, d: i64) -> Result<Option<Value>, CalcError> {
+        Ok(Some(Value::Rational(num::BigRational::new(
+            n.into(),
+            d.into(),
+        ))))
+    }
+    fn float(x: f64) -> Result<Option<Value>, CalcError> {
+        Ok(Some(Value::Float(x)))
     }
 
     #[test]
@@ -174,28 +331,25 @@ mod tests {
     #[test]
     fn eval_mismatched_parens_in_multiarg_fn() {
         let evaled = eval("log(1+(2^16, 4)", None);
-        assert_eq!(
-            evaled,
-            Err(CalcError::Syntax("Mismatched parentheses!".to_string()))
-        );
+        assert_eq!(evaled, Err(CalcError::Syntax("Unexpected ','".to_string())));
     }
     #[test]
     fn eval_comma_without_multiarg_fn() {
         let evaled = eval("1+(2^16, 4)", None);
-        assert_eq!(
-            evaled,
-            Err(CalcError::Syntax("Mismatched parentheses!".to_string()))
-        );
+        assert_eq!(evaled, Err(CalcError::Syntax("Unexpected ','".to_string())));
+        let ctx = FunctionContext::default();
+        let error = eval_expr(&ctx, &mut Environment::new(), 10, "1 + (2, 3)", None);
+        assert_eq!(error.unwrap_err().span(), Some(6..7));
     }
     #[test]
     fn eval_unexpected_comma() {
         let evaled = eval("(1+1,2+2)", None);
-        assert_eq!(
-            evaled,
-            Err(CalcError::Parser(
-                "Too many operators, too few operands".to_string()
-            ))
-        );
+        assert_eq!(evaled, Err(CalcError::Syntax("Unexpected ','".to_string())));
+        let ctx = FunctionContext::default();
+        let error = eval_expr(&ctx, &mut Environment::new(), 10, "(1+1,2+2)", None);
+        assert_eq!(error.unwrap_err().span(), Some(4..5));
+        let evaled = eval("1+1,2", None);
+        assert_eq!(evaled, Err(CalcError::Syntax("Unexpected ','".to_string())));
     }
     #[test]
     fn eval_nroot_expr_on_both_sides() {
@@ -236,14 +390,21 @@ mod tests {
         assert_eq!(
             evaled,
             Err(CalcError::Parser(
-                "Too many operators, too few operands".to_string()
+                "Function 'nroot' takes 2 arguments, got 3".to_string()
             ))
         );
         let evaled = eval("nroot(23)", None);
         assert_eq!(
             evaled,
             Err(CalcError::Parser(
-                "To few arguments for function, need 2".to_string()
+                "Function 'nroot' takes 2 arguments, got 1".to_string()
+            ))
+        );
+        let evaled = eval("sqrt()", None);
+        assert_eq!(
+            evaled,
+            Err(CalcError::Parser(
+                "Function 'sqrt' takes 1 argument, got 0".to_string()
             ))
         );
     }
@@ -252,4 +413,874 @@ mod tests {
         let evaled = eval("-1!", None);
         assert_eq!(Err(CalcError::Math(MathError::OutOfBounds)), evaled);
     }
+    #[test]
+    fn eval_gamma() {
+        assert_eq!(eval("0.5! - sqrt(pi) / 2", None), Ok(0.));
+        assert_eq!(eval("2.5!", None), Ok(3.3233509704));
+        assert_eq!(eval("gamma(5) + gamma(1/3)", None), Ok(26.6789385347));
+        assert_eq!(eval("lgamma(1000)", None), Ok(5905.2204232092));
+        assert_eq!(eval("digamma(1) + 0.5772156649", None), Ok(0.));
+        assert_eq!(eval("beta(2.5, 1.5) - pi / 16", None), Ok(0.));
+        // poles
+        for input in ["(-1.5)!", "gamma(-2)", "lgamma(0)", "beta(1, -1)"] {
+            assert_eq!(
+                eval(input, None),
+                Err(CalcError::Math(MathError::OutOfBounds)),
+                "{}",
+                input
+            );
+        }
+        assert_eq!(
+            eval("171.7!", None),
+            Err(CalcError::Math(MathError::TooLarge))
+        );
+        let mut rational = session(rational());
+        assert_eq!(rational("gamma(6)"), ratio(120, 1));
+        assert_eq!(rational("beta(2, 3)"), ratio(1, 12));
+        assert_eq!(rational("(1/2)!"), float(0.8862269255));
+    }
+    #[test]
+    fn eval_variables() {
+        let mut eval = session(FunctionContext::default());
+        assert_eq!(eval("rate = 0.07"), float(0.07));
+        assert_eq!(eval("total = 1200 * (1 + rate)"), float(1284.));
+        assert_eq!(eval("total2 - 2rate"), float(2567.86));
+        assert_eq!(eval("rate = rate * 2"), float(0.14));
+    }
+    #[test]
+    fn eval_assign_builtin() {
+        let mut eval = session(FunctionContext::default());
+        assert_eq!(
+            eval("pi = 3"),
+            Err(CalcError::Syntax(
+                "Cannot assign to built-in 'pi'".to_string()
+            ))
+        );
+        assert_eq!(
+            eval("sin = 3"),
+            Err(CalcError::Syntax(
+                "Cannot assign to built-in 'sin'".to_string()
+            ))
+        );
+        assert_eq!(
+            eval("xor = 2"),
+            Err(CalcError::Syntax("'xor' is an operator".to_string()))
+        );
+    }
+    #[test]
+    fn eval_unknown_variable() {
+        let evaled = eval("2 + rate", None);
+        assert_eq!(
+            evaled,
+            Err(CalcError::Syntax("Unknown variable 'rate'".to_string()))
+        );
+    }
+    #[test]
+    fn eval_user_functions() {
+        let mut eval = session(FunctionContext::default());
+        assert_eq!(eval("hyp(a, b) = sqrt(a^2 + b^2)"), Ok(None));
+        assert_eq!(eval("hyp(3, 4)"), float(5.));
+        assert_eq!(eval("sq(x) = x * x"), Ok(None));
+        assert_eq!(eval("f(x, y, z) = sq(x) + 2hyp(y, z)"), Ok(None));
+        assert_eq!(eval("f(2, 6, 8) + 1"), float(25.));
+        assert_eq!(eval("two() = 2"), Ok(None));
+        assert_eq!(eval("two()^3"), float(8.));
+        assert_eq!(
+            eval("f(1, 2)"),
+            Err(CalcError::Parser(
+                "Function 'f' takes 3 arguments, got 2".to_string()
+            ))
+        );
+    }
+    #[test]
+    fn eval_user_function_scope() {
+        let ctx = FunctionContext::default();
+        let mut env = Environment::new();
+        let mut eval = |input| eval_expr(&ctx, &mut env, 10, input, None);
+        // bodies see the session's variables, not the parameters of callers
+        assert_eq!(eval("f(x) = x + a"), Ok(None));
+        assert_eq!(eval("a = 1"), Ok(Some(Value::Float(1.))));
+        assert_eq!(eval("g(a) = f(0)"), Ok(None));
+        assert_eq!(eval("g(100)"), Ok(Some(Value::Float(1.))));
+        // bodies are parsed when defined
+        let error = eval("h(x) = x +* 2").unwrap_err();
+        assert_eq!(error.span(), Some(10..11));
+        assert_eq!(
+            error.without_span(),
+            CalcError::Syntax("Unexpected '*'".into())
+        );
+    }
+    #[test]
+    fn eval_user_function_recursion() {
+        let mut eval = session(FunctionContext::default());
+        assert_eq!(eval("f(n) = n + f(n - 1)"), Ok(None));
+        assert_eq!(
+            eval("f(3)"),
+            Err(CalcError::RecursionLimit(crate::env::MAX_CALL_DEPTH))
+        );
+    }
+    #[test]
+    fn eval_define_builtin() {
+        let mut eval = session(FunctionContext::default());
+        assert_eq!(
+            eval("sin(x) = x"),
+            Err(CalcError::Syntax(
+                "Cannot assign to built-in 'sin'".to_string()
+            ))
+        );
+        assert_eq!(
+            eval("f(x, x) = x"),
+            Err(CalcError::Syntax("Duplicate parameter 'x'".to_string()))
+        );
+    }
+    fn decimal() -> FunctionContext {
+        FunctionContext {
+            mode: NumberMode::Decimal(60),
+            ..Default::default()
+        }
+    }
+    #[test]
+    fn eval_decimal_precision() {
+        let mut decimal = session(decimal());
+        let mut eval_decimal = |input, fix| {
+            decimal(input).map(|ans| match ans {
+                Some(Value::Decimal(d)) => d.to_fixed_string(fix),
+                ans => panic!("expected a decimal, got {:?}", ans),
+            })
+        };
+        assert_eq!(
+            eval_decimal("exp(3)", 50),
+            Ok("20.08553692318766774092852965458171789698790783855415".into())
+        );
+        assert_eq!(
+            eval_decimal("log(1+(2^16),4)", 50),
+            Ok("8.00001100680568017024824453644153487777856377469010".into())
+        );
+        assert_eq!(
+            eval_decimal("nroot(2+2,4+e^2)", 50),
+            Ok("1.12943964485144004694215966518491125553690514346079".into())
+        );
+        assert_eq!(
+            eval_decimal("1 / (1 + e^-7)", 50),
+            Ok("0.99908894880559935464213667622530585867585193973868".into())
+        );
+        assert_eq!(
+            eval_decimal("40!", 1),
+            Ok("815915283247897734345611269596115894272000000000.0".into())
+        );
+        assert_eq!(
+            eval_decimal("sin(30) + tan(45", 50),
+            Ok(format!("1.5{}", "0".repeat(49)))
+        );
+        assert_eq!(
+            eval_decimal("2.5! + beta(2, 3)", 50),
+            Ok("3.40668430378117588451739736459798055107873856356281".into())
+        );
+        assert_eq!(
+            eval_decimal("0.1 + 0.2 - 0.3", 60),
+            Ok(format!("0.{}", "0".repeat(60)))
+        );
+    }
+    #[test]
+    fn eval_decimal_inexact() {
+        let mut decimal = session(decimal());
+        let mut eval = |input| decimal(input).unwrap().unwrap();
+        // results computed in f64 stay floats, and so does arithmetic on them
+        assert!(matches!(eval("deriv(x^3, x, 2) + 1"), Value::Float(_)));
+        assert!(matches!(eval("1 mi to km"), Value::Quantity(_)));
+        assert!(matches!(eval("sqrt(2) + 1"), Value::Decimal(_)));
+    }
+    #[test]
+    fn eval_decimal_errors() {
+        let mut decimal = session(decimal());
+        assert_eq!(
+            decimal("ln(0)"),
+            Err(CalcError::Math(MathError::OutOfBounds))
+        );
+        assert_eq!(
+            decimal("1/(2-2)"),
+            Err(CalcError::Math(MathError::DivideByZero))
+        );
+        assert_eq!(
+            decimal("(0-2.5)!"),
+            Err(CalcError::Math(MathError::OutOfBounds))
+        );
+        assert_eq!(
+            decimal("lgamma(0-3)"),
+            Err(CalcError::Math(MathError::OutOfBounds))
+        );
+    }
+
+    #[test]
+    fn eval_rational_exact() {
+        let mut rational = session(rational());
+        assert_eq!(rational("1/3 + 1/6"), ratio(1, 2));
+        assert_eq!(rational("0.1 + 0.2 - 0.3"), ratio(0, 1));
+        assert_eq!(rational("(0 - (2/3)^-2) % 2"), ratio(-1, 4));
+        assert_eq!(rational("1.25 * 0.4"), ratio(1, 2));
+        assert_eq!(rational("floor(-7/2) + 4!"), ratio(20, 1));
+        assert_eq!(
+            rational("2^100").unwrap().unwrap().to_string(),
+            "1267650600228229401496703205376"
+        );
+    }
+    #[test]
+    fn eval_rational_fallback() {
+        let mut rational = session(rational());
+        assert_eq!(rational("sqrt(4)"), float(2.));
+        assert_eq!(rational("2^(1/2) * 0"), float(0.));
+        assert_eq!(rational("3 * pi"), float(9.4247779608));
+        assert_eq!(
+            rational("1/(1/3 - 1/3)"),
+            Err(CalcError::Math(MathError::DivideByZero))
+        );
+    }
+    #[test]
+    fn eval_exponent_literals() {
+        assert_eq!(eval("1.2345e4 + 5E-1", None), Ok(12345.5));
+        assert_eq!(eval("2e-1", None), Ok(0.2));
+        // without digits after it `e` is still the constant
+        assert_eq!(eval("2e + 2e-e", None), Ok(8.1548454854));
+        assert_eq!(session(rational())("1.5e-3"), ratio(3, 2000));
+        assert_eq!(
+            eval("1e400", None),
+            Err(CalcError::Syntax("Invalid number '1e400'".into()))
+        );
+    }
+    #[test]
+    fn eval_prefixed_literals() {
+        let ctx = FunctionContext::default();
+        let evaled = |input| eval_expr(&ctx, &mut Environment::new(), 10, input, None);
+        assert_eq!(evaled("0xFF + 0o17 + 0b1010"), float(280.));
+        assert_eq!(evaled("0x1.8"), float(1.5));
+        assert_eq!(evaled("0x1p-3 + 0xAp+1"), float(20.125));
+        assert_eq!(evaled("2 0b11"), float(6.));
+        let mut rational = session(rational());
+        assert_eq!(rational("0x0.1"), ratio(1, 16));
+        assert_eq!(rational("0b0.01 + 0o0.4"), ratio(3, 4));
+        assert_eq!(
+            evaled("1 + 0b102"),
+            Err(CalcError::Syntax("Invalid number '0b102'".into()).at(4..9))
+        );
+    }
+    #[test]
+    fn eval_input_base() {
+        let evaled = |ibase, input| {
+            let ctx = FunctionContext {
+                ibase,
+                ..Default::default()
+            };
+            eval_expr(&ctx, &mut Environment::new(), 10, input, None)
+        };
+        assert_eq!(evaled(16, "1F + 0FF"), float(286.));
+        assert_eq!(evaled(16, "10.8"), float(16.5));
+        // lower case letters stay names
+        assert_eq!(evaled(16, "2e"), evaled(10, "2 * e"));
+        assert_eq!(evaled(2, "101 * 0x10"), float(80.));
+        assert_eq!(
+            evaled(8, "19"),
+            Err(CalcError::Syntax("Invalid number '19'".into()).at(0..2))
+        );
+    }
+    #[test]
+    fn eval_bitwise() {
+        assert_eq!(eval("12 & 10 | 1", None), Ok(9.));
+        assert_eq!(eval("12 xor 10", None), Ok(6.));
+        assert_eq!(eval("1 + 2 << 1 + 1", None), Ok(12.));
+        assert_eq!(eval("0xFF & ~0xF", None), Ok(240.));
+        assert_eq!(eval("-17 >> 2", None), Ok(-5.));
+        let mut rational = session(rational());
+        assert_eq!(rational("2^100 | 1"), rational("2^100 + 1"));
+        assert_eq!(
+            eval("2.5 << 1", None),
+            Err(CalcError::Math(MathError::NotAnInteger))
+        );
+        assert_eq!(
+            eval("~i", None),
+            Err(CalcError::Math(MathError::NotAnInteger))
+        );
+    }
+    #[test]
+    fn eval_units() {
+        let mut eval = session(FunctionContext::default());
+        let mut evaled = |input| eval(input).map(|ans| ans.unwrap().to_string());
+        assert_eq!(evaled("5 km/h to m/s"), Ok("1.3888888889 m/s".into()));
+        assert_eq!(evaled("100 °C in °F"), Ok("212 °F".into()));
+        assert_eq!(evaled("-40 degF to degC"), Ok("-40 degC".into()));
+        assert_eq!(evaled("70 kg * 9.81 m/s^2 to N"), Ok("686.7 N".into()));
+        assert_eq!(evaled("2 m * 3 m"), Ok("6 m^2".into()));
+        assert_eq!(evaled("km/m"), Ok("1000".into()));
+        assert_eq!(evaled("1 mi + 1 ft to ft"), Ok("5281 ft".into()));
+        assert_eq!(
+            evaled("1 m + 1 s"),
+            Err(CalcError::Math(MathError::Units(
+                "Incompatible units 'm' and 's'".into()
+            )))
+        );
+        assert_eq!(
+            evaled("ln(2 s)"),
+            Err(CalcError::Math(MathError::Units(
+                "Function 'ln' needs a dimensionless argument".into()
+            )))
+        );
+        // a number and its unit bind tighter than `/`, but not than `^`
+        assert_eq!(evaled("100 km / 2 h"), Ok("50 km/h".into()));
+        assert_eq!(evaled("1 m / 1 m"), Ok("1".into()));
+        assert_eq!(evaled("1 / 2 m^2"), Ok("0.5 1/m^2".into()));
+        assert_eq!(evaled("1 / 2 min(4, 6)"), Ok("2".into()));
+        let offset = Err(CalcError::Math(MathError::Units(
+            "Cannot multiply 'degC', convert it to 'K' first".into(),
+        )));
+        assert_eq!(evaled("10 degC / 1 s"), offset);
+        assert_eq!(evaled("10 degC^2"), offset);
+        assert_eq!(evaled("2 * 10 degC"), Ok("20 degC".into()));
+        // variables cannot hide units, parameters shadow them in their body
+        assert_eq!(
+            evaled("m = 3"),
+            Err(CalcError::Syntax("Cannot assign to unit 'm'".into()))
+        );
+        assert_eq!(evaled("5 km to m"), Ok("5000 m".into()));
+        assert_eq!(eval("f(m) = 3m"), Ok(None));
+        assert_eq!(eval("f(2)"), float(6.));
+    }
+    #[test]
+    fn eval_matrices() {
+        let mut eval = session(FunctionContext::default());
+        let mut evaled = |input| eval(input).map(|ans| ans.unwrap().to_string());
+        assert_eq!(evaled("[1, 2; 3, 4] * [5; 6]"), Ok("[17; 39]".into()));
+        assert_eq!(evaled("2[1, 2] - [1, 1]"), Ok("[1, 3]".into()));
+        assert_eq!(evaled("det([1, 2; 3, 4])"), Ok("-2".into()));
+        assert_eq!(evaled("inv([2, 0; 0, 4])"), Ok("[0.5, 0; 0, 0.25]".into()));
+        assert_eq!(evaled("[1, 1; 0, 1]^3"), Ok("[1, 3; 0, 1]".into()));
+        assert_eq!(
+            evaled("cross([1, 0, 0], [0, 1, 0])"),
+            Ok("[0, 0, 1]".into())
+        );
+        assert_eq!(evaled("dot([1, 2, 3], [4; 5; 6])"), Ok("32".into()));
+        assert_eq!(
+            evaled("solve([2, 1; 1, 3], [3, 5])"),
+            Ok("[0.8, 1.4]".into())
+        );
+        assert_eq!(evaled("eig([2, 1; 1, 2])"), Ok("[3; 1]".into()));
+        assert_eq!(evaled("abs([-1, 2])"), Ok("[1, 2]".into()));
+        assert_eq!(
+            evaled("[1, 2] * [3, 4]"),
+            Err(CalcError::Math(MathError::Matrix(
+                "Cannot multiply 1x2 by 1x2".into()
+            )))
+        );
+        assert_eq!(
+            evaled("inv([1, 2; 2, 4])"),
+            Err(CalcError::Math(MathError::Matrix(
+                "Matrix is singular".into()
+            )))
+        );
+        let ctx = FunctionContext::default();
+        assert_eq!(
+            eval_expr(&ctx, &mut Environment::new(), 10, "[1, 2; 3]", None),
+            Err(CalcError::Syntax("Rows of a matrix must have the same length".into()).at(0..9))
+        );
+        let parsed = parse_expr(&ctx, "[1, x; -2, 3+4]", None).unwrap();
+        assert_eq!(parsed.to_string(), "[1, x; -2, 3 + 4]");
+    }
+    #[test]
+    fn eval_statistics() {
+        assert_eq!(eval("mean(3, 5, 8, 13)", None), Ok(7.25));
+        assert_eq!(eval("median(5, 1, 3, 2)", None), Ok(2.5));
+        assert_eq!(eval("mode(1, 2, 2, 3)", None), Ok(2.));
+        assert_eq!(eval("var(4, 7, 13, 16)", None), Ok(30.));
+        assert_eq!(eval("pstddev(2, 4, 4, 4, 5, 5, 7, 9)", None), Ok(2.));
+        assert_eq!(eval("percentile(90, 1, 2, 3, 4, 5)", None), Ok(4.6));
+        assert_eq!(eval("min(3, -1, 2) + max(3, -1, 2)", None), Ok(2.));
+        assert_eq!(eval("sum([1, 2; 3, 4], 5)", None), Ok(15.));
+        let mut rational = session(rational());
+        assert_eq!(rational("mean(1, 2, 2)"), ratio(5, 3));
+        assert_eq!(rational("pvar(1/2, 1/3)"), ratio(1, 144));
+        assert_eq!(
+            eval("var(1)", None),
+            Err(CalcError::Parser(
+                "Function 'var' takes at least 2 arguments, got 1".into()
+            ))
+        );
+        assert_eq!(
+            eval("sum()", None),
+            Err(CalcError::Parser(
+                "Function 'sum' takes at least 1 argument, got 0".into()
+            ))
+        );
+        assert_eq!(
+            eval("percentile(101, 1, 2)", None),
+            Err(CalcError::Math(MathError::OutOfBounds))
+        );
+    }
+    #[test]
+    fn eval_overloads() {
+        assert_eq!(eval("log(1000)", None), Ok(3.));
+        assert_eq!(eval("log(8, 2)", None), Ok(3.));
+        assert_eq!(eval("round(2.71828, 2)", None), Ok(2.72));
+        assert_eq!(eval("round(1250, -2)", None), Ok(1300.));
+        assert_eq!(session(rational())("round(22/7, 3)"), ratio(3143, 1000));
+        assert_eq!(
+            eval("log(1, 2, 3)", None),
+            Err(CalcError::Parser(
+                "Function 'log' takes 1 or 2 arguments, got 3".into()
+            ))
+        );
+        assert_eq!(
+            eval("round(1, 0.5)", None),
+            Err(CalcError::Math(MathError::OutOfBounds))
+        );
+        let log = &FUNCTIONS["log"];
+        assert_eq!(log.signatures(), vec!["log(x)", "log(x, y)"]);
+        assert_eq!(FUNCTIONS["mean"].signatures(), vec!["mean(x1, ...)"]);
+    }
+    #[test]
+    fn eval_booleans() {
+        let mut eval = session(FunctionContext::default());
+        let mut eval_value = |input| eval(input).map(Option::unwrap);
+        assert_eq!(eval_value("1 + 1 == 2"), Ok(Value::Bool(true)));
+        assert_eq!(eval_value("2 <= 1 || !(3 > 2)"), Ok(Value::Bool(false)));
+        assert_eq!(eval_value("1 << 2 >= 4 && 1 != 2"), Ok(Value::Bool(true)));
+        assert_eq!(eval_value("!false"), Ok(Value::Bool(true)));
+        // a factorial after an operand, a not before one
+        assert_eq!(eval_value("3!"), Ok(Value::Float(6.)));
+        assert_eq!(eval_value("3! != 6"), Ok(Value::Bool(false)));
+        // as in C, `!=` wins over a factorial
+        assert_eq!(eval_value("5!=120"), Ok(Value::Bool(true)));
+        assert_eq!(eval_value("1 m > 99 cm"), Ok(Value::Bool(true)));
+        assert_eq!(
+            eval_value("approx(0.1 + 0.2, 0.3, 0.000001)"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            eval_value("approx(1000, 1001, 0.0001)"),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(
+            eval_value("true + 1"),
+            Err(CalcError::Math(MathError::Type(
+                "Operator '+' needs numbers, got a boolean".into()
+            )))
+        );
+        assert_eq!(
+            eval_value("if(1, 2, 3)"),
+            Err(CalcError::Math(MathError::Type(
+                "Expected a boolean, got a number".into()
+            )))
+        );
+        assert_eq!(
+            eval_value("i < 1"),
+            Err(CalcError::Math(MathError::Type(
+                "Complex numbers have no order".into()
+            )))
+        );
+    }
+
+    #[test]
+    fn eval_conditionals() {
+        let ctx = FunctionContext::default();
+        let mut env = Environment::new();
+        // branches and right operands that are not taken are never evaluated
+        assert_eq!(eval("if(2 > 1, 10, 1/0)", None), Ok(10.));
+        assert_eq!(eval("if(false || 0 > 1, 1/0, 3)", None), Ok(3.));
+        assert_eq!(
+            eval_expr(&ctx, &mut env, 10, "false && 1/0 > 0", None),
+            Ok(Some(Value::Bool(false)))
+        );
+        eval_expr(&ctx, &mut env, 10, "x = 3 < 4", None).unwrap();
+        assert_eq!(env.get("x"), Some(Value::Bool(true)));
+        eval_expr(
+            &ctx,
+            &mut env,
+            10,
+            "f(n) = if(n <= 1, 1, n * f(n - 1))",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            eval_expr(&ctx, &mut env, 10, "f(10)", None),
+            float(3628800.)
+        );
+        let expr = parse_expr(&ctx, "!(a<b)||(c==d)>=e", None).unwrap();
+        assert_eq!(expr.to_string(), "!(a < b) || (c == d) >= e");
+    }
+
+    /// Evaluates in integer mode, results as they print.
+    fn integer() -> impl FnMut(&str) -> Result<String, CalcError> {
+        let mut eval = session(FunctionContext {
+            mode: NumberMode::Integer,
+            ..Default::default()
+        });
+        move |input| eval(input).map(|ans| ans.unwrap().to_string())
+    }
+    #[test]
+    fn eval_integer_mode() {
+        let mut eval_integer = integer();
+        assert_eq!(
+            eval_integer("2^100 - 1"),
+            Ok("1267650600228229401496703205375".into())
+        );
+        assert_eq!(eval_integer("171! / 170!"), Ok("171".into()));
+        assert_eq!(eval_integer("-7 // 2 + -7 % 2"), Ok("-5".into()));
+        assert_eq!(eval_integer("gcd(2^64, 6^40)"), Ok("1099511627776".into()));
+        assert_eq!(eval_integer("lcm(4, 6, 10)"), Ok("60".into()));
+        assert_eq!(
+            eval_integer("modpow(3, 10^20, 10^9 + 7)"),
+            Ok("139421235".into())
+        );
+        // fractional results leave the integers
+        assert_eq!(eval_integer("7 / 2"), Ok("3.5".into()));
+        assert_eq!(eval_integer("2^-1 + 0.25"), Ok("0.75".into()));
+        assert_eq!(
+            eval_integer("gcd(4, 0.5)"),
+            Err(CalcError::Math(MathError::NotAnInteger))
+        );
+        assert_eq!(eval("9 // 2 + gcd(12, 18)", None), Ok(10.));
+        assert_eq!(
+            eval("1 // 0", None),
+            Err(CalcError::Math(MathError::DivideByZero))
+        );
+    }
+    #[test]
+    fn eval_combinatorics() {
+        let mut eval_integer = integer();
+        assert_eq!(
+            eval_integer("ncr(100, 50)"),
+            Ok("100891344545564193334812497256".into())
+        );
+        assert_eq!(eval_integer("catalan(10) + npr(10, 3)"), Ok("17516".into()));
+        assert_eq!(
+            eval_integer("multinomial(3, 2, 2) + stirling2(10, 3) + bell(10)"),
+            Ok("125515".into())
+        );
+        assert_eq!(eval("ncr(52, 5)", None), Ok(2598960.));
+        assert_eq!(
+            eval("ncr(5, 2.5)", None),
+            Err(CalcError::Math(MathError::NotAnInteger))
+        );
+        assert_eq!(
+            eval("npr(-3, 2)", None),
+            Err(CalcError::Math(MathError::OutOfBounds))
+        );
+    }
+    #[test]
+    fn eval_number_theory() {
+        let mut eval_integer = integer();
+        assert_eq!(eval_integer("factor(360)"), Ok("2^3 * 3^2 * 5".into()));
+        assert_eq!(eval_integer("factor(-97)"), Ok("-97".into()));
+        // a factorization is still the number it factors
+        assert_eq!(eval_integer("factor(360) + 1"), Ok("361".into()));
+        assert_eq!(eval_integer("gcd(factor(360), 2^10)"), Ok("8".into()));
+        assert_eq!(
+            eval_integer("isprime(2^61 - 1) && !isprime(2^62 - 1)"),
+            Ok("true".into())
+        );
+        assert_eq!(
+            eval_integer("nextprime(2^64 - 60) - prevprime(2^64 - 1)"),
+            Ok("0".into())
+        );
+        assert_eq!(eval_integer("totient(36) + modinv(3, 7)"), Ok("17".into()));
+        assert_eq!(
+            eval_integer("modpow(3, -1, 7) == modinv(3, 7)"),
+            Ok("true".into())
+        );
+        assert_eq!(eval("sum(divisors(28)) - 2 * 28", None), Ok(0.));
+        assert_eq!(
+            eval("modinv(4, 6)", None),
+            Err(CalcError::Math(MathError::OutOfBounds))
+        );
+        assert_eq!(
+            eval_integer("isprime(2^64)"),
+            Err(CalcError::Math(MathError::ArgumentTooLarge(u64::MAX)))
+        );
+        // floats past 2^53 no longer tell neighbouring integers apart
+        assert_eq!(eval("isprime(2^53 - 111)", None), Ok(1.));
+        assert_eq!(
+            eval("isprime(9007199254740993)", None),
+            Err(CalcError::Math(MathError::ArgumentTooLarge((1 << 53) - 1)))
+        );
+        assert_eq!(
+            eval("catalan(10^7)", None),
+            Err(CalcError::Math(MathError::ArgumentTooLarge(100_000)))
+        );
+        assert_eq!(
+            eval("cross(1, 2)", None),
+            Err(CalcError::Math(MathError::Matrix(
+                "Cross product needs vectors of length 3".into()
+            )))
+        );
+    }
+    #[test]
+    fn eval_calculus() {
+        assert_eq!(eval("deriv(x^3 - 2x, x, 2)", None), Ok(10.));
+        assert_eq!(eval("integrate(3t^2, t, 0, 2)", None), Ok(8.));
+        // degrees, the integral of sin over half a turn is 2 * 180 / pi
+        assert_eq!(eval("integrate(sin(x), x, 0, 180) * pi", None), Ok(360.));
+        assert_eq!(
+            eval("integrate(integrate(x * y, x, 0, y), y, 0, 2)", None),
+            Ok(2.)
+        );
+        let mut eval = session(FunctionContext::default());
+        assert_eq!(eval("a = 3"), float(3.));
+        assert_eq!(eval("integrate(a * x, x, 0, 1)"), float(1.5));
+        // the bound variable does not outlive the call
+        assert_eq!(
+            eval("x"),
+            Err(CalcError::Syntax("Unknown variable 'x'".into()))
+        );
+        assert!(matches!(
+            eval("integrate(1/x, x, 0, 1)"),
+            Err(CalcError::Math(MathError::Convergence(_)))
+        ));
+        assert_eq!(
+            eval("deriv(x, 2, 1)"),
+            Err(CalcError::Syntax(
+                "Function 'deriv' expected a variable name, got '2'".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn eval_solve() {
+        assert_eq!(eval("solve(x^2 = 3, x, 1)", None), Ok(1.7320508076));
+        assert_eq!(eval("solve(x^2 - 3, x, -1)", None), Ok(-1.7320508076));
+        assert_eq!(eval("solve(e^x = 3x, x, 0)", None), Ok(0.6190612867));
+        // x^3 vanishes to third order, Newton gets there slowly
+        assert_eq!(eval("solve(x^3, x, 1)", None), Ok(0.));
+        assert_eq!(eval("solve(2x + 1 = 0, x)", None), Ok(-0.5));
+        // undefined below 0, the bracket takes over
+        assert_eq!(eval("solve(sqrt(x) = 0.5, x, -1)", None), Ok(0.25));
+        assert_eq!(eval("solve(6, 3)", None), Ok(0.5));
+        let mut radian = session(radian());
+        let mut roots = |input| radian(input).map(|x| x.unwrap().to_string());
+        assert_eq!(
+            roots("solve(sin(x), x, -1, 7)"),
+            Ok("[0, 3.1415926536, 6.2831853072]".into())
+        );
+        assert_eq!(roots("solve(x^2 = 4, x, -10, 10)"), Ok("[-2, 2]".into()));
+        assert!(matches!(
+            eval("solve(x^2 + 1, x, 0)", None),
+            Err(CalcError::Math(MathError::Convergence(_)))
+        ));
+        assert!(matches!(
+            eval("solve(1 / x, x, -1, 1)", None),
+            Err(CalcError::Math(MathError::Convergence(_)))
+        ));
+        assert_eq!(
+            eval("sqrt(x = 2)", None),
+            Err(CalcError::Syntax(
+                "Equations are only understood by 'solve'".into()
+            ))
+        );
+        // the unknown shadows a variable, matrices make a linear system
+        let mut solve = session(FunctionContext::default());
+        assert_eq!(solve("x = 3"), float(3.));
+        assert_eq!(solve("solve(2x - 1, x)"), float(0.5));
+        assert_eq!(solve("a = [2, 1; 1, 3]"), solve("[2, 1; 1, 3]"));
+        assert_eq!(solve("b = [3, 5]"), solve("[3, 5]"));
+        assert_eq!(solve("solve(a, b)"), solve("[0.8, 1.4]"));
+        assert!(matches!(
+            solve("solve(1 / (x - 1), x, 2)"),
+            Err(CalcError::Math(MathError::Convergence(_)))
+        ));
+    }
+
+    #[test]
+    fn eval_symbolic() {
+        let mut radian = session(radian());
+        let mut eval =
+            |input| radian(input).map(|ans| ans.map_or(String::new(), |x| x.to_string()));
+        assert_eq!(
+            eval("diff(x^2 * sin(x), x)"),
+            Ok("2x*sin(x) + x^2*cos(x)".into())
+        );
+        assert_eq!(eval("diff(x^3, x, 2)"), Ok("6x".into()));
+        assert_eq!(eval("diff(1/x, x)"), Ok("-1/x^2".into()));
+        assert_eq!(eval("diff(x^x, x)"), Ok("x^x*(1 + ln(x))".into()));
+        assert_eq!(eval("simplify(x + x + 2*3)"), Ok("6 + 2x".into()));
+        assert_eq!(eval("simplify(2*x*y/(4*y^2))"), Ok("x/(2y)".into()));
+        assert_eq!(eval("simplify(sin(x)^2 + cos(x)^2)"), Ok("1".into()));
+        assert_eq!(eval("simplify(sin(x)/cos(x))"), Ok("tan(x)".into()));
+        assert_eq!(eval("simplify(y*x - x*y)"), Ok("0".into()));
+        // a minus before a power would read back as raising the negation
+        assert_eq!(eval("simplify(0 - y^2)"), Ok("-(y^2)".into()));
+        assert_eq!(eval("simplify(1 - 2^x)"), Ok("1 - 2^x".into()));
+        // variables with a value are substituted
+        assert_eq!(eval("f(x) = x^3"), Ok(String::new()));
+        assert_eq!(eval("diff(f(x), x)"), Ok("3x^2".into()));
+        assert_eq!(eval("df(x) = diff(f(x), x)"), Ok(String::new()));
+        assert_eq!(eval("df(2)"), Ok("12".into()));
+        assert_eq!(eval("diff(x^2, x) + 1"), Ok("1 + 2x".into()));
+        assert_eq!(
+            eval("diff(gcd(x, 2), x)"),
+            Err(CalcError::Math(MathError::Type(
+                "Function 'gcd' has no derivative".into()
+            )))
+        );
+        assert_eq!(
+            eval("simplify(x/0)"),
+            Err(CalcError::Math(MathError::DivideByZero))
+        );
+        assert_eq!(
+            eval("diff(x, 2)"),
+            Err(CalcError::Syntax(
+                "Function 'diff' expected a variable name, got '2'".into()
+            ))
+        );
+    }
+
+    fn radian() -> FunctionContext {
+        FunctionContext {
+            angle_unit: AngleUnit::Radian,
+            ..Default::default()
+        }
+    }
+    fn complex(re: f64, im: f64) -> Result<Option<Value>, CalcError> {
+        Ok(Some(Value::Complex(Complex64::new(re, im))))
+    }
+    #[test]
+    fn eval_complex_arithmetic() {
+        let mut eval_complex = session(radian());
+        assert_eq!(eval_complex("i^2"), float(-1.));
+        assert_eq!(eval_complex("(3 + 4i) * (1 - 2i)"), complex(11., -2.));
+        assert_eq!(eval_complex("(1 + i) / (1 - i)"), complex(0., 1.));
+        assert_eq!(eval_complex("(0-8)^(1/3)"), complex(1., 1.7320508076));
+        assert_eq!(eval_complex("exp(i * pi)"), float(-1.));
+        assert_eq!(
+            eval_complex("i % 2"),
+            Err(CalcError::Math(MathError::OutOfBounds))
+        );
+    }
+    #[test]
+    fn eval_complex_functions() {
+        let mut eval_complex = session(radian());
+        assert_eq!(eval_complex("sqrt(0-4)"), complex(0., 2.));
+        assert_eq!(eval_complex("re(ln(0-3))"), float(1.0986122887));
+        assert_eq!(eval_complex("im(ln(0-3)) / pi"), float(1.));
+        assert_eq!(eval_complex("sin(i)"), complex(0., 1.1752011936));
+        assert_eq!(eval_complex("re(3+4i) + im(3+4i)"), float(7.));
+        assert_eq!(eval_complex("abs(3+4i)"), float(5.));
+        assert_eq!(eval_complex("arg(0-i) * 2 / pi"), float(-1.));
+        assert_eq!(eval_complex("conj(2+3i)"), complex(2., -3.));
+        assert_eq!(
+            eval_complex("floor(i)"),
+            Err(CalcError::Math(MathError::OutOfBounds))
+        );
+    }
+    #[test]
+    fn eval_angle_units() {
+        let eval = |angle_unit, mode, input| {
+            let ctx = FunctionContext {
+                angle_unit,
+                mode,
+                ..Default::default()
+            };
+            session(ctx)(input).map(|ans| ans.unwrap().to_string())
+        };
+        let (grad, deg) = (AngleUnit::Gradian, AngleUnit::Degree);
+        assert_eq!(eval(grad, NumberMode::Float, "sin(100)"), Ok("1".into()));
+        assert_eq!(eval(grad, NumberMode::Float, "cos(200)"), Ok("-1".into()));
+        assert_eq!(
+            eval(grad, NumberMode::Decimal(30), "sin(100)"),
+            Ok("1".into())
+        );
+        assert_eq!(
+            eval(grad, NumberMode::Float, "sin(50i) / i"),
+            Ok("0.8686709615".into())
+        );
+        assert_eq!(eval(deg, NumberMode::Float, "arg(0-1)"), Ok("180".into()));
+        assert_eq!(eval(grad, NumberMode::Float, "arg(0-1)"), Ok("200".into()));
+        assert_eq!(eval(deg, NumberMode::Float, "arg(1+i)"), Ok("45".into()));
+        assert_eq!(
+            eval(grad, NumberMode::Decimal(30), "arg(0-1)"),
+            Ok("200".into())
+        );
+    }
+    #[test]
+    fn eval_operator_errors() {
+        assert_eq!(
+            eval("2+", None),
+            Err(CalcError::Syntax(
+                "Unexpected end of expression".to_string()
+            ))
+        );
+        assert_eq!(
+            eval("2*/3", None),
+            Err(CalcError::Syntax("Unexpected '/'".to_string()))
+        );
+        assert_eq!(
+            eval("sin30", None),
+            Err(CalcError::Syntax(
+                "Function 'sin' expected parentheses".to_string()
+            ))
+        );
+        assert_eq!(eval("3!!", None), Ok(720.));
+        assert_eq!(eval("2^3!", None), Ok(64.));
+    }
+    #[test]
+    fn syntax_check() {
+        let ctx = FunctionContext::default();
+        let check = |input| check_syntax(&ctx, input, None).map_err(CalcError::without_span);
+        // nothing is evaluated, undefined names and math errors pass
+        assert_eq!(check("1/0 + foo(bar)"), Ok(()));
+        assert_eq!(check("f(x) = x + y"), Ok(()));
+        assert_eq!(check("help"), Ok(()));
+        assert_eq!(
+            check("a = 2 +"),
+            Err(CalcError::Syntax("Unexpected end of expression".into()))
+        );
+        assert_eq!(
+            check("_ + 1"),
+            Err(CalcError::Syntax("No previous answer!".into()))
+        );
+    }
+    #[test]
+    fn parse_tree() {
+        let ctx = FunctionContext::default();
+        let parsed = parse_expr(&ctx, "2pi!", None).unwrap();
+        assert_eq!(
+            parsed,
+            Expr::Binary {
+                op: "*",
+                lhs: Box::new(Expr::Literal {
+                    value: Value::Float(2.),
+                    span: 0..1,
+                }),
+                rhs: Box::new(Expr::Unary {
+                    op: ast::UnaryOp::Factorial,
+                    expr: Box::new(Expr::Ident {
+                        name: "pi".to_string(),
+                        span: 1..3,
+                    }),
+                    span: 1..4,
+                }),
+                span: 0..4,
+            }
+        );
+        let print = |input| parse_expr(&ctx, input, None).unwrap().to_string();
+        assert_eq!(print("2(3+4)"), "2 * (3 + 4)");
+        assert_eq!(print("(1-2)-(3-4)"), "1 - 2 - (3 - 4)");
+        assert_eq!(print("(2^3)^4 + 2^3^4"), "(2 ^ 3) ^ 4 + 2 ^ 3 ^ 4");
+        assert_eq!(print("-(1+2)! + log(e, -2)"), "(-(1 + 2))! + log(e, -2)");
+    }
+    #[test]
+    fn eval_error_spans() {
+        let ctx = FunctionContext::default();
+        let mut env = Environment::new();
+        eval_expr(&ctx, &mut env, 10, "f(x) = 1 / x", None).unwrap();
+        let mut span = |input| {
+            eval_expr(&ctx, &mut env, 10, input, None)
+                .unwrap_err()
+                .span()
+        };
+        assert_eq!(span("1 + foo(2)"), Some(4..7));
+        assert_eq!(span("2 * (3 + 4))"), Some(11..12));
+        assert_eq!(span("sqrt(1, 2)"), Some(0..4));
+        assert_eq!(span("2 +"), Some(3..3));
+        assert_eq!(span("1 / (2 - 2) + 1"), Some(0..11));
+        assert_eq!(span("3!!!!"), Some(0..4));
+        assert_eq!(span("rate = 2 + undefined"), Some(11..20));
+        assert_eq!(span("1 = 2"), Some(0..1));
+        assert_eq!(span("  3 + f(0)"), Some(6..10));
+    }
 }
@@ -0,0 +1,160 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Comparisons and boolean logic. Booleans are values of their own, they
+//! are neither numbers nor accepted where numbers are expected.
+
+use std::cmp::Ordering;
+
+use crate::error::{CalcError, MathError};
+use crate::lex::OPERATORS;
+use crate::units;
+use crate::value::{self, Value};
+
+pub fn is_logical(token: &str) -> bool {
+    matches!(token, "==" | "!=" | "<" | "<=" | ">" | ">=" | "&&" | "||")
+}
+
+/// `x op y` for the operators of `is_logical`, evaluating both operands.
+pub fn operate(token: &str, x: &Value, y: &Value) -> Result<Value, CalcError> {
+    let result = match token {
+        "&&" => truth(x)? && truth(y)?,
+        "||" => truth(x)? || truth(y)?,
+        "==" => equal(x, y)?,
+        "!=" => !equal(x, y)?,
+        _ => {
+            let ordering = order(x, y)?;
+            match token {
+                "<" => ordering == Some(Ordering::Less),
+                "<=" => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                ">" => ordering == Some(Ordering::Greater),
+                ">=" => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                _ => unreachable!(),
+            }
+        }
+    };
+    Ok(Value::Bool(result))
+}
+
+/// The boolean `x` stands for, conditions must be booleans.
+pub fn truth(x: &Value) -> Result<bool, CalcError> {
+    match x {
+        Value::Bool(b) => Ok(*b),
+        x => Err(type_error(format!("Expected a boolean, got {}", x.kind()))),
+    }
+}
+
+/// Whether `x` and `y` differ by at most `tolerance`, relative to the larger
+/// of them or absolute when both are below 1. Units are converted, matrices
+/// compared entry by entry.
+pub fn approx(x: &Value, y: &Value, tolerance: &Value) -> Result<Value, CalcError> {
+    let tolerance = match tolerance {
+        Value::Float(_) | Value::Decimal(_) | Value::Rational(_) => tolerance.to_f64(),
+        t => {
+            return Err(type_error(format!(
+                "Expected a number as tolerance, got {}",
+                t.kind()
+            )))
+        }
+    };
+    if tolerance.is_nan() || tolerance < 0. {
+        return Err(CalcError::Math(MathError::OutOfBounds));
+    }
+    if let Some(b) = [x, y].into_iter().find(|v| matches!(v, Value::Bool(_))) {
+        return Err(type_error(format!(
+            "Function 'approx' needs numbers, got {}",
+            b.kind()
+        )));
+    }
+    let difference = OPERATORS["-"].operate(x.clone(), y.clone())?;
+    // `y` in the unit of `x`
+    let y = OPERATORS["-"].operate(x.clone(), difference.clone())?;
+    let scale = size(x).max(size(&y)).max(1.);
+    Ok(Value::Bool(size(&difference) <= tolerance * scale))
+}
+
+/// Magnitude of a number or quantity, the largest entry of a matrix.
+fn size(x: &Value) -> f64 {
+    match x {
+        Value::Complex(z) => z.norm(),
+        Value::Quantity(q) => q.value.abs(),
+        Value::Matrix(m) => m.entries().iter().fold(0., |max, x| x.abs().max(max)),
+        x => x.to_f64().abs(),
+    }
+}
+
+fn equal(x: &Value, y: &Value) -> Result<bool, CalcError> {
+    match (x, y) {
+        (Value::Bool(a), Value::Bool(b)) => Ok(a == b),
+        (Value::Matrix(a), Value::Matrix(b)) => Ok(a == b),
+        // expressions are equal if they simplify to the same tree
+        (Value::Symbolic(a), Value::Symbolic(b)) => Ok(a == b),
+        (Value::Bool(_) | Value::Matrix(_) | Value::Symbolic(_), _)
+        | (_, Value::Bool(_) | Value::Matrix(_) | Value::Symbolic(_)) => Err(mismatch(x, y)),
+        (Value::Complex(_), Value::Quantity(_)) | (Value::Quantity(_), Value::Complex(_)) => {
+            Err(mismatch(x, y))
+        }
+        (Value::Complex(_), _) | (_, Value::Complex(_)) => Ok(x.to_complex() == y.to_complex()),
+        _ => Ok(order(x, y)? == Some(Ordering::Equal)),
+    }
+}
+
+/// How real numbers or quantities of the same dimension compare, `None` if
+/// either is NaN.
+fn order(x: &Value, y: &Value) -> Result<Option<Ordering>, CalcError> {
+    match (x, y) {
+        (Value::Complex(_), _) | (_, Value::Complex(_)) => {
+            Err(type_error("Complex numbers have no order".into()))
+        }
+        (Value::Matrix(_), _) | (_, Value::Matrix(_)) => {
+            Err(type_error("Matrices have no order".into()))
+        }
+        (Value::Bool(_), _) | (_, Value::Bool(_)) => {
+            Err(type_error("Booleans have no order".into()))
+        }
+        (Value::Symbolic(_), _) | (_, Value::Symbolic(_)) => {
+            Err(type_error("Expressions have no order".into()))
+        }
+        (Value::Quantity(_), _) | (_, Value::Quantity(_)) => {
+            let difference = units::operate("-", x, y)?.to_f64();
+            Ok(difference.partial_cmp(&0.))
+        }
+        (Value::Rational(a), Value::Rational(b)) => Ok(Some(a.cmp(b))),
+        (Value::Decimal(_), _) | (_, Value::Decimal(_)) => {
+            let args = value::to_decimals(&[x.clone(), y.clone()])?;
+            Ok(args[0].partial_cmp(&args[1]))
+        }
+        _ => Ok(x.to_f64().partial_cmp(&y.to_f64())),
+    }
+}
+
+fn mismatch(x: &Value, y: &Value) -> CalcError {
+    type_error(format!("Cannot compare {} and {}", x.kind(), y.kind()))
+}
+
+fn type_error(details: String) -> CalcError {
+    CalcError::Math(MathError::Type(details))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixed_representations() {
+        let (f, b) = (Value::Float, Value::Bool);
+        let half = Value::from(num::BigRational::new(1.into(), 2.into()));
+        assert_eq!(operate("==", &half, &f(0.5)), Ok(b(true)));
+        assert_eq!(operate("<", &f(f64::NAN), &f(1.)), Ok(b(false)));
+        assert_eq!(operate("!=", &f(f64::NAN), &f(f64::NAN)), Ok(b(true)));
+        assert_eq!(
+            operate("<", &b(true), &f(1.)),
+            Err(type_error("Booleans have no order".into()))
+        );
+        assert_eq!(
+            operate("==", &b(true), &f(1.)),
+            Err(type_error("Cannot compare a boolean and a number".into()))
+        );
+    }
+}
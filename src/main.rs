@@ -3,18 +3,29 @@
  *  Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
  */
 use clap::builder::{EnumValueParser, RangedU64ValueParser};
-use clap::{Arg, Command};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, Command};
 use directories::{ProjectDirs, UserDirs};
-use eva::lex::{AngleUnit, FunctionContext};
-use eva::{eval_expr};
+use eva::env::Environment;
+use eva::eval_expr;
+use eva::lex::{AngleUnit, FunctionContext, NumberMode};
+use eva::plot::{self, Plot};
+use eva::script;
+use eva::{CalcError, Value};
 use once_cell::sync::Lazy;
 use rustyline::error::ReadlineError;
+use std::cell::RefCell;
 use std::fs::create_dir_all;
+use std::io::{BufRead, BufReader, IsTerminal};
 use std::path::PathBuf;
+use std::rc::Rc;
 
 mod fmt;
 mod readline;
 
+use fmt::{Notation, Style};
+
 #[derive(Clone, Copy, Default)]
 struct ClapAngleUnit(AngleUnit);
 
@@ -40,33 +51,101 @@ static CONFIGURATION: Lazy<Configuration> = Lazy::new(parse_arguments);
 
 struct Configuration {
     angle_unit: AngleUnit,
-    fix: usize,
-    base: u8,
+    mode: NumberMode,
+    /// How results are printed at startup, `:notation` and the like change it.
+    style: Style,
+    /// Print complex numbers as magnitude and angle.
+    polar: bool,
+    /// Radix of unprefixed input literals.
+    ibase: u32,
     input: String,
+    /// Write plots to this SVG file instead of the terminal.
+    svg: Option<String>,
+    /// Script file to run instead of `input`.
+    script: Option<String>,
+    /// Run the rest of a script after a line failed.
+    keep_going: bool,
 }
 
+const PROMPT: &str = "> ";
+
+/// Size of SVG plots, in pixels.
+const SVG_SIZE: (usize, usize) = (800, 500);
+
 fn main() {
     let ctx = FunctionContext {
         angle_unit: CONFIGURATION.angle_unit,
+        mode: CONFIGURATION.mode,
+        ibase: CONFIGURATION.ibase,
     };
 
-    if !CONFIGURATION.input.is_empty() {
+    if let Some(path) = &CONFIGURATION.script {
+        std::process::exit(run_script(&ctx, path, CONFIGURATION.keep_going));
+    } else if !CONFIGURATION.input.is_empty() {
         // command mode //
-        let evaled = eval_expr(&ctx, CONFIGURATION.fix, &CONFIGURATION.input[..], Some(0.));
+        let mut env = Environment::new();
+        if plot::is_command(&CONFIGURATION.input) {
+            let svg = CONFIGURATION.svg.as_deref();
+            match plot(&ctx, &env, &CONFIGURATION.input, None, svg) {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => {
+                    eprintln!("Could not write plot: {}", err);
+                    std::process::exit(1);
+                }
+                Err(e) => {
+                    if let Some(span) = e.span() {
+                        eprintln!("{}", CONFIGURATION.input);
+                        eprintln!("{}", fmt::caret(&CONFIGURATION.input, span));
+                    }
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        let evaled = eval_expr(
+            &ctx,
+            &mut env,
+            CONFIGURATION.style.rounding(),
+            &CONFIGURATION.input[..],
+            Value::parse(&ctx, "0").as_ref(),
+        );
         match evaled {
-            Ok(ans) => fmt::pprint(CONFIGURATION.base, CONFIGURATION.fix, ans),
+            Ok(Some(ans)) => print_ans(&ans, &CONFIGURATION.style),
+            Ok(None) => {}
+            // scripts with a `#!/usr/bin/env eva` shebang get their path as
+            // input, which only counts if it is no expression
+            Err(_) if script::has_shebang(&CONFIGURATION.input) => {
+                std::process::exit(run_script(&ctx, &CONFIGURATION.input, false));
+            }
             Err(e) => {
+                if let Some(span) = e.span() {
+                    eprintln!("{}", CONFIGURATION.input);
+                    eprintln!("{}", fmt::caret(&CONFIGURATION.input, span));
+                }
                 eprintln!("{}", e);
                 std::process::exit(1);
             }
         };
+    } else if !std::io::stdin().is_terminal() {
+        // batch mode, for pipes: no prompt, no history, every line is run //
+        std::process::exit(run_lines(
+            &ctx,
+            "<stdin>",
+            std::io::stdin().lock().lines(),
+            true,
+            true,
+        ));
     } else {
         // REPL mode //
-        // create fancy readline
-        let mut rl = readline::create_readline(ctx.clone(), CONFIGURATION.fix);
+        // variables and the previous answer live across lines, the
+        // highlighter needs to see them too
+        let env = Rc::new(RefCell::new(Environment::new()));
+        let prev_ans = Rc::new(RefCell::new(None));
 
-        // previous answer
-        let mut prev_ans = None;
+        // create fancy readline
+        let mut rl = readline::create_readline(ctx.clone(), env.clone(), prev_ans.clone());
+        let mut style = CONFIGURATION.style;
 
         // handle history storage
         let eva_dirs = ProjectDirs::from("com", "NerdyPepper", "eva").unwrap();
@@ -96,20 +175,50 @@ fn main() {
 
         // repl loop begins here
         loop {
-            let readline = rl.readline("> ");
+            let readline = rl.readline(PROMPT);
             match readline {
                 Ok(line) => {
-                    rl.add_history_entry(line.as_str());
-                    let evaled = eval_expr(&ctx, CONFIGURATION.fix, &line[..], prev_ans);
+                    let _ = rl.add_history_entry(line.as_str());
+                    if is_switch(&line) {
+                        if let Err(err) = style.set(&line) {
+                            println!("{}", err);
+                        }
+                        continue;
+                    }
+                    if plot::is_command(&line) {
+                        match plot(&ctx, &env.borrow(), &line, prev_ans.borrow().as_ref(), None) {
+                            Ok(Ok(())) => {}
+                            Ok(Err(err)) => println!("Could not write plot: {}", err),
+                            Err(e) => {
+                                if let Some(span) = e.span() {
+                                    println!(
+                                        "{}{}",
+                                        " ".repeat(PROMPT.len()),
+                                        fmt::caret(&line, span)
+                                    );
+                                }
+                                println!("{}", e)
+                            }
+                        }
+                        continue;
+                    }
+                    let evaled = eval_expr(
+                        &ctx,
+                        &mut env.borrow_mut(),
+                        style.rounding(),
+                        &line[..],
+                        prev_ans.borrow().as_ref(),
+                    );
                     match evaled {
-                        Ok(ans) => {
+                        Ok(None) => {}
+                        Ok(Some(ans)) => {
                             use std::fs::OpenOptions;
                             use std::io::Write;
-                            prev_ans = Some(ans);
-                            fmt::pprint(CONFIGURATION.base, CONFIGURATION.fix, ans);
+                            print_ans(&ans, &style);
                             match OpenOptions::new()
                                 .write(true)
                                 .create(true)
+                                .truncate(true)
                                 .open(&previous_ans_path)
                             {
                                 Ok(mut file) => {
@@ -124,8 +233,15 @@ fn main() {
                                     println!("Error while writing previous answer to file: {}", err)
                                 }
                             }
+                            prev_ans.replace(Some(ans));
+                        }
+                        Err(e) => {
+                            // the line is still on screen, right after the prompt
+                            if let Some(span) = e.span() {
+                                println!("{}{}", " ".repeat(PROMPT.len()), fmt::caret(&line, span));
+                            }
+                            println!("{}", e)
                         }
-                        Err(e) => println!("{}", e),
                     };
                 }
                 Err(ReadlineError::Interrupted) => {
@@ -143,8 +259,187 @@ fn main() {
     }
 }
 
+/// Run the script at `path` line by line, printing results as they come.
+/// Returns the exit code, 1 if a line failed.
+fn run_script(ctx: &FunctionContext, path: &str, keep_going: bool) -> i32 {
+    match std::fs::File::open(path) {
+        Ok(file) => run_lines(ctx, path, BufReader::new(file).lines(), keep_going, false),
+        Err(err) => {
+            eprintln!("Could not read {}: {}", path, err);
+            1
+        }
+    }
+}
+
+/// Evaluate `lines` of the input called `name` in one environment, errors
+/// go to stderr. With `aligned`, lines without a result print an empty line,
+/// so that outputs line up with inputs. Returns the exit code, 1 if a line
+/// failed.
+fn run_lines(
+    ctx: &FunctionContext,
+    name: &str,
+    lines: impl Iterator<Item = std::io::Result<String>>,
+    keep_going: bool,
+    aligned: bool,
+) -> i32 {
+    let mut env = Environment::new();
+    let mut prev_ans = None;
+    let mut style = CONFIGURATION.style;
+    let mut failures = 0;
+    for (number, line) in lines.enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("Could not read {}: {}", name, err);
+                return 1;
+            }
+        };
+        let line = &line[..];
+        let location = format!("{}:{}: ", name, number + 1);
+        let code = script::strip_comment(line);
+        let evaled = if is_switch(code) {
+            if let Err(err) = style.set(code) {
+                eprintln!("{}{}", location, err);
+                failures += 1;
+            }
+            Ok(None)
+        } else if plot::is_command(code) {
+            plot(ctx, &env, code, prev_ans.as_ref(), None).map(|written| {
+                if let Err(err) = written {
+                    eprintln!("{}Could not write plot: {}", location, err);
+                    failures += 1;
+                }
+                None
+            })
+        } else {
+            script::eval_line(ctx, &mut env, style.rounding(), line, prev_ans.as_ref())
+        };
+        match evaled {
+            Ok(None) if aligned => println!(),
+            Ok(None) => {}
+            Ok(Some(ans)) => {
+                print_ans(&ans, &style);
+                prev_ans = Some(ans);
+            }
+            Err(e @ CalcError::Help(_)) => println!("{}", e),
+            Err(e) => {
+                if aligned {
+                    println!();
+                }
+                failures += 1;
+                match e.span() {
+                    Some(span) => {
+                        eprintln!("{}{}", location, line);
+                        let indent = " ".repeat(location.chars().count());
+                        eprintln!("{}{}", indent, fmt::caret(line, span));
+                    }
+                    None => eprint!("{}", location),
+                }
+                eprintln!("{}", e);
+            }
+        }
+        if failures > 0 && !keep_going {
+            return 1;
+        }
+    }
+    if failures > 0 {
+        let lines = if failures == 1 { "line" } else { "lines" };
+        eprintln!("{}: {} {} failed", name, failures, lines);
+        return 1;
+    }
+    0
+}
+
+/// Draw a `plot` command in the terminal, or write it to an SVG file. The
+/// inner result is that of writing the file.
+fn plot(
+    ctx: &FunctionContext,
+    env: &Environment,
+    line: &str,
+    prev_ans: Option<&Value>,
+    svg: Option<&str>,
+) -> Result<std::io::Result<()>, CalcError> {
+    let plot = Plot::parse(ctx, env, line, prev_ans)?;
+    match svg.or(plot.svg_path()) {
+        Some(path) => {
+            let image = plot.to_svg(SVG_SIZE.0, SVG_SIZE.1)?;
+            Ok(std::fs::write(path, image))
+        }
+        None => {
+            let (width, height) = match terminal_size::terminal_size() {
+                Some((terminal_size::Width(w), terminal_size::Height(h))) => {
+                    (w as usize, h as usize)
+                }
+                None => (80, 24),
+            };
+            // leave a line for the prompt
+            let drawing = plot.to_braille(width, height - 1, std::io::stdout().is_terminal())?;
+            println!("{}", drawing);
+            Ok(Ok(()))
+        }
+    }
+}
+
+/// Whether `line` is a switch like `:notation sci` rather than an expression.
+fn is_switch(line: &str) -> bool {
+    line.trim_start().starts_with(':')
+}
+
+fn print_ans(ans: &Value, style: &Style) {
+    let formatted = match ans {
+        Value::Complex(z) if CONFIGURATION.polar => {
+            fmt::format_polar(style, *z, CONFIGURATION.angle_unit)
+        }
+        _ => fmt::format(style, ans),
+    };
+    // mark results that had to leave the rationals
+    let inexact = matches!(
+        ans,
+        Value::Float(_) | Value::Complex(_) | Value::Quantity(_)
+    );
+    if CONFIGURATION.mode == NumberMode::Rational && inexact {
+        println!("≈ {}", formatted);
+    } else {
+        println!("{}", formatted);
+    }
+}
+
 fn cmd() -> Command {
     clap::command!()
+        .subcommand(
+            Command::new("run")
+                .about("Run a script of expressions, one per line, # starts a comment")
+                .arg(
+                    Arg::new("file")
+                        .value_name("FILE")
+                        .required(true)
+                        .help("Script to run, lines like 'assert x > 0' check conditions"),
+                )
+                .arg(
+                    Arg::new("keep_going")
+                        .long("keep-going")
+                        .action(ArgAction::SetTrue)
+                        .help("Run every line and report all failures, not just the first"),
+                ),
+        )
+        .subcommand(
+            Command::new("plot")
+                .about("Plot functions of x over a range, e.g. eva plot 'sin(x)' -pi pi")
+                .arg(
+                    Arg::new("svg")
+                        .long("svg")
+                        .value_name("FILE")
+                        .help("Write the plot to an SVG image instead of the terminal"),
+                )
+                .arg(
+                    Arg::new("arguments")
+                        .value_name("ARGS")
+                        .num_args(1..)
+                        .required(true)
+                        .allow_hyphen_values(true)
+                        .help("Functions of x, then the start and end of the range, after any options"),
+                ),
+        )
         .arg(
             Arg::new("input")
                 .value_name("INPUT")
@@ -159,6 +454,34 @@ fn cmd() -> Command {
                 .value_name("FIX")
                 .help("Number of decimal places in output (1 - 64)"),
         )
+        .arg(
+            Arg::new("notation")
+                .short('n')
+                .long("notation")
+                .value_parser(["fix", "sci", "eng", "auto"])
+                .default_value("fix")
+                .help("Notation of output: fix 1,234.5, sci 1.2345e3, eng 12.345e-6, or auto, sci outside --auto-window"),
+        )
+        .arg(
+            Arg::new("sig")
+                .short('s')
+                .long("sig")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..=1000))
+                .value_name("N")
+                .conflicts_with("fix")
+                .help("Number of significant figures in output instead of decimal places (1 - 1000)"),
+        )
+        .arg(
+            Arg::new("auto_window")
+                .long("auto-window")
+                .value_parser(|window: &str| {
+                    fmt::parse_window(window).ok_or("expected MIN:MAX, as in -5:12")
+                })
+                .default_value("-5:12")
+                .allow_hyphen_values(true)
+                .value_name("MIN:MAX")
+                .help("Decimal exponents the auto notation writes without an exponent"),
+        )
         .arg(
             Arg::new("base")
                 .short('b')
@@ -168,6 +491,42 @@ fn cmd() -> Command {
                 .value_name("RADIX")
                 .help("Radix of calculation output (1 - 36)"),
         )
+        .arg(
+            Arg::new("ibase")
+                .long("ibase")
+                .value_parser(RangedU64ValueParser::<u32>::new().range(2..=36))
+                .default_value("10")
+                .value_name("RADIX")
+                .help("Radix of input literals without a 0x, 0o or 0b prefix (2 - 36)"),
+        )
+        .arg(
+            Arg::new("precision")
+                .short('p')
+                .long("precision")
+                .value_parser(RangedU64ValueParser::<usize>::new().range(1..=1000))
+                .value_name("DIGITS")
+                .help("Evaluate with arbitrary precision, to DIGITS significant digits (1 - 1000)"),
+        )
+        .arg(
+            Arg::new("rational")
+                .long("rational")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("precision")
+                .help("Evaluate with exact fractions, marking inexact results with ≈"),
+        )
+        .arg(
+            Arg::new("integer")
+                .long("integer")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["precision", "rational"])
+                .help("Evaluate integer results exactly, however large"),
+        )
+        .arg(
+            Arg::new("polar")
+                .long("polar")
+                .action(ArgAction::SetTrue)
+                .help("Print complex numbers in polar form, using the angle unit"),
+        )
         .arg(
             Arg::new("angle_unit")
                 .short('a')
@@ -181,11 +540,64 @@ fn cmd() -> Command {
 fn parse_arguments() -> Configuration {
     let matches = cmd().get_matches();
 
+    let mode = match matches.get_one("precision") {
+        Some(&digits) => NumberMode::Decimal(digits),
+        None if matches.get_flag("rational") => NumberMode::Rational,
+        None if matches.get_flag("integer") => NumberMode::Integer,
+        None => NumberMode::Float,
+    };
+    let base = *matches.get_one("base").unwrap();
+    let notation = Notation::parse(matches.get_one::<String>("notation").unwrap()).unwrap();
+    // digits are only counted in base 10
+    if base != 10 && (matches.contains_id("sig") || notation != Notation::Fixed) {
+        cmd()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--sig and --notation only apply to base 10 output, not --base",
+            )
+            .exit();
+    }
+    // show every requested digit unless told otherwise
+    let significant = match (mode, matches.value_source("fix")) {
+        _ if matches.contains_id("sig") => matches.get_one("sig").copied(),
+        (NumberMode::Decimal(digits), Some(ValueSource::DefaultValue)) if base == 10 => {
+            Some(digits)
+        }
+        _ => None,
+    };
+    let style = Style {
+        base,
+        fix: *matches.get_one("fix").unwrap(),
+        significant,
+        notation,
+        window: *matches.get_one("auto_window").unwrap(),
+    };
+
+    let (mut input, svg) = match matches.subcommand_matches("plot") {
+        Some(plot) => {
+            let arguments: Vec<&String> = plot.get_many("arguments").unwrap().collect();
+            let arguments: Vec<&str> = arguments.iter().map(|a| a.as_str()).collect();
+            let input = format!("plot {}", arguments.join(", "));
+            (input, plot.get_one("svg").cloned())
+        }
+        None => (matches.get_one("input").cloned().unwrap_or_default(), None),
+    };
+    let (script, keep_going) = match matches.subcommand_matches("run") {
+        Some(run) => (run.get_one("file").cloned(), run.get_flag("keep_going")),
+        None if script::is_script(&input) => (Some(std::mem::take(&mut input)), false),
+        None => (None, false),
+    };
+
     Configuration {
         angle_unit: matches.get_one::<ClapAngleUnit>("angle_unit").unwrap().0,
-        fix: *matches.get_one("fix").unwrap(),
-        base: *matches.get_one("base").unwrap(),
-        input: matches.get_one("input").cloned().unwrap_or_default(),
+        mode,
+        style,
+        polar: matches.get_flag("polar"),
+        ibase: *matches.get_one("ibase").unwrap(),
+        input,
+        svg,
+        script,
+        keep_going,
     }
 }
 
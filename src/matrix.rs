@@ -0,0 +1,505 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Real matrices and the linear algebra built-ins. Vectors are matrices with
+//! a single row or column.
+
+use std::fmt;
+
+use num::complex::Complex64;
+use num::Zero;
+
+use crate::error::{CalcError, MathError};
+use crate::value::Value;
+
+/// Iterations allowed per eigenvalue before giving up.
+const MAX_QR_ITERATIONS: usize = 100;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matrix {
+    rows: usize,
+    cols: usize,
+    /// Entries in row major order.
+    data: Vec<f64>,
+}
+
+/// Row echelon form from `Matrix::eliminate`.
+struct Echelon {
+    a: Vec<Vec<f64>>,
+    /// Right hand sides after the same row operations.
+    rhs: Vec<Vec<f64>>,
+    /// Columns holding a pivot, one per non-zero row.
+    pivots: Vec<usize>,
+    /// Whether an odd number of rows were swapped.
+    odd: bool,
+}
+
+fn error(details: &str) -> CalcError {
+    CalcError::Math(MathError::Matrix(details.to_string()))
+}
+
+fn singular() -> CalcError {
+    error("Matrix is singular")
+}
+
+/// Real number a matrix entry or scalar operand stands for.
+pub(crate) fn scalar(x: &Value) -> Result<f64, CalcError> {
+    match x {
+        Value::Float(_) | Value::Decimal(_) | Value::Rational(_) => Ok(x.to_f64()),
+        _ => Err(error("Matrix entries must be real numbers")),
+    }
+}
+
+impl Matrix {
+    /// Matrix of `rows` rows, `None` if they are empty or differ in length.
+    pub fn new(rows: Vec<Vec<f64>>) -> Option<Matrix> {
+        let cols = rows.first()?.len();
+        if cols == 0 || rows.iter().any(|row| row.len() != cols) {
+            return None;
+        }
+        Some(Matrix {
+            rows: rows.len(),
+            cols,
+            data: rows.concat(),
+        })
+    }
+
+    fn from_fn(rows: usize, cols: usize, f: impl Fn(usize, usize) -> f64) -> Matrix {
+        let data = (0..rows * cols).map(|k| f(k / cols, k % cols)).collect();
+        Matrix { rows, cols, data }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Entries in row major order.
+    pub fn entries(&self) -> &[f64] {
+        &self.data
+    }
+
+    pub fn get(&self, row: usize, col: usize) -> f64 {
+        self.data[row * self.cols + col]
+    }
+
+    /// The matrix with `f` applied to every entry.
+    pub fn map(&self, f: impl FnMut(f64) -> f64) -> Matrix {
+        Matrix {
+            data: self.data.iter().copied().map(f).collect(),
+            ..*self
+        }
+    }
+
+    fn identity(n: usize) -> Matrix {
+        Matrix::from_fn(n, n, |i, j| if i == j { 1. } else { 0. })
+    }
+
+    fn is_vector(&self) -> bool {
+        self.rows == 1 || self.cols == 1
+    }
+
+    fn is_square(&self) -> Result<usize, CalcError> {
+        match self.rows == self.cols {
+            true => Ok(self.rows),
+            false => Err(error("Matrix must be square")),
+        }
+    }
+
+    fn rows_of(&self) -> Vec<Vec<f64>> {
+        self.data.chunks(self.cols).map(<[f64]>::to_vec).collect()
+    }
+
+    pub fn transpose(&self) -> Matrix {
+        Matrix::from_fn(self.cols, self.rows, |i, j| self.get(j, i))
+    }
+
+    fn zip(&self, other: &Matrix, f: impl Fn(f64, f64) -> f64) -> Result<Matrix, CalcError> {
+        if (self.rows, self.cols) != (other.rows, other.cols) {
+            return Err(error("Matrices must have the same shape"));
+        }
+        Ok(Matrix::from_fn(self.rows, self.cols, |i, j| {
+            f(self.get(i, j), other.get(i, j))
+        }))
+    }
+
+    pub fn mul(&self, other: &Matrix) -> Result<Matrix, CalcError> {
+        if self.cols != other.rows {
+            return Err(error(&format!(
+                "Cannot multiply {}x{} by {}x{}",
+                self.rows, self.cols, other.rows, other.cols
+            )));
+        }
+        Ok(Matrix::from_fn(self.rows, other.cols, |i, j| {
+            (0..self.cols)
+                .map(|k| self.get(i, k) * other.get(k, j))
+                .sum()
+        }))
+    }
+
+    fn pow(&self, n: f64) -> Result<Matrix, CalcError> {
+        let size = self.is_square()?;
+        if n.fract() != 0. || n.abs() > u32::MAX as f64 {
+            return Err(error("Matrix powers must be integers"));
+        }
+        let mut base = if n < 0. { self.inv()? } else { self.clone() };
+        let mut n = n.abs() as u32;
+        let mut result = Matrix::identity(size);
+        while n > 0 {
+            if n & 1 == 1 {
+                result = result.mul(&base)?;
+            }
+            base = base.mul(&base)?;
+            n >>= 1;
+        }
+        Ok(result)
+    }
+
+    /// Entries below which a pivot counts as zero.
+    fn tolerance(&self) -> f64 {
+        let largest = self.data.iter().fold(0f64, |m, x| m.max(x.abs()));
+        largest * self.rows.max(self.cols) as f64 * f64::EPSILON
+    }
+
+    /// Gaussian elimination with partial pivoting, applying the same row
+    /// operations to `rhs`.
+    fn eliminate(&self, mut rhs: Vec<Vec<f64>>) -> Echelon {
+        let tolerance = self.tolerance();
+        let mut a = self.rows_of();
+        let mut pivots = vec![];
+        let mut odd = false;
+        for col in 0..self.cols {
+            let row = pivots.len();
+            if row == self.rows {
+                break;
+            }
+            let best = (row..self.rows)
+                .max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))
+                .unwrap();
+            if a[best][col].abs() <= tolerance {
+                continue;
+            }
+            if best != row {
+                a.swap(best, row);
+                rhs.swap(best, row);
+                odd = !odd;
+            }
+            let (pivot, pivot_rhs) = (a[row].clone(), rhs[row].clone());
+            for i in row + 1..self.rows {
+                let factor = a[i][col] / pivot[col];
+                for (x, p) in a[i][col..].iter_mut().zip(&pivot[col..]) {
+                    *x -= factor * p;
+                }
+                for (x, p) in rhs[i].iter_mut().zip(&pivot_rhs) {
+                    *x -= factor * p;
+                }
+            }
+            pivots.push(col);
+        }
+        Echelon {
+            a,
+            rhs,
+            pivots,
+            odd,
+        }
+    }
+
+    pub fn det(&self) -> Result<f64, CalcError> {
+        let n = self.is_square()?;
+        let echelon = self.eliminate(vec![vec![]; n]);
+        if echelon.pivots.len() < n {
+            return Ok(0.);
+        }
+        let det: f64 = (0..n).map(|i| echelon.a[i][i]).product();
+        Ok(if echelon.odd { -det } else { det })
+    }
+
+    pub fn rank(&self) -> usize {
+        self.eliminate(vec![vec![]; self.rows]).pivots.len()
+    }
+
+    /// `x` such that `self * x = b`, for a square non-singular `self`.
+    pub fn solve(&self, b: &Matrix) -> Result<Matrix, CalcError> {
+        let n = self.is_square()?;
+        if b.rows != n {
+            return Err(error("Right hand side must have a row per equation"));
+        }
+        let Echelon { a, rhs, pivots, .. } = self.eliminate(b.rows_of());
+        if pivots.len() < n {
+            return Err(singular());
+        }
+        let mut x = vec![vec![0.; b.cols]; n];
+        for i in (0..n).rev() {
+            for k in 0..b.cols {
+                let known: f64 = (i + 1..n).map(|j| a[i][j] * x[j][k]).sum();
+                x[i][k] = (rhs[i][k] - known) / a[i][i];
+            }
+        }
+        Ok(Matrix {
+            rows: n,
+            cols: b.cols,
+            data: x.concat(),
+        })
+    }
+
+    pub fn inv(&self) -> Result<Matrix, CalcError> {
+        self.solve(&Matrix::identity(self.is_square()?))
+    }
+
+    /// Eigenvalues by shifted QR iteration, largest first.
+    pub fn eigenvalues(&self) -> Result<Vec<Complex64>, CalcError> {
+        let n = self.is_square()?;
+        let mut a: Vec<Vec<Complex64>> = self
+            .rows_of()
+            .into_iter()
+            .map(|row| row.into_iter().map(Complex64::from).collect())
+            .collect();
+        let norm = self.data.iter().map(|x| x * x).sum::<f64>().sqrt();
+        let mut eigenvalues = vec![];
+        let mut m = n;
+        while m > 1 {
+            let mut iterations = 0;
+            // the last row of the active block vanishes left of the diagonal
+            while (0..m - 1).any(|j| a[m - 1][j].norm() > f64::EPSILON * norm) {
+                iterations += 1;
+                if iterations > MAX_QR_ITERATIONS {
+                    return Err(error("Eigenvalues did not converge"));
+                }
+                let mut shift = wilkinson(
+                    a[m - 2][m - 2],
+                    a[m - 2][m - 1],
+                    a[m - 1][m - 2],
+                    a[m - 1][m - 1],
+                );
+                if iterations % 10 == 0 {
+                    // shake loose from cycles
+                    shift += a[m - 1][m - 2].norm() * 0.75;
+                }
+                qr_step(&mut a, m, shift);
+            }
+            eigenvalues.push(a[m - 1][m - 1]);
+            m -= 1;
+        }
+        eigenvalues.push(a[0][0]);
+        eigenvalues.sort_by(|x, y| y.re.total_cmp(&x.re).then(y.im.total_cmp(&x.im)));
+        Ok(eigenvalues)
+    }
+}
+
+/// Eigenvalue of `[a, b; c, d]` closest to `d`.
+fn wilkinson(a: Complex64, b: Complex64, c: Complex64, d: Complex64) -> Complex64 {
+    let mean = (a + d) / 2.;
+    let root = (((a - d) / 2.).powi(2) + b * c).sqrt();
+    let (x, y) = (mean + root, mean - root);
+    if (x - d).norm() < (y - d).norm() {
+        x
+    } else {
+        y
+    }
+}
+
+/// Replace the leading `m` by `m` block of `a` with `RQ + shift`, where
+/// `QR = a - shift`, using Givens rotations.
+fn qr_step(a: &mut [Vec<Complex64>], m: usize, shift: Complex64) {
+    for (i, row) in a.iter_mut().enumerate().take(m) {
+        row[i] -= shift;
+    }
+    let mut rotations = vec![];
+    for k in 0..m {
+        for i in k + 1..m {
+            let (x, y) = (a[k][k], a[i][k]);
+            let r = (x.norm_sqr() + y.norm_sqr()).sqrt();
+            if y.is_zero() || r == 0. {
+                continue;
+            }
+            let (c, s) = (x / r, y / r);
+            let (upper, lower) = a.split_at_mut(i);
+            for (top, bottom) in upper[k][..m].iter_mut().zip(&mut lower[0][..m]) {
+                (*top, *bottom) = (
+                    c.conj() * *top + s.conj() * *bottom,
+                    -s * *top + c * *bottom,
+                );
+            }
+            rotations.push((k, i, c, s));
+        }
+    }
+    for (k, i, c, s) in rotations {
+        for row in a.iter_mut().take(m) {
+            let (left, right) = (row[k], row[i]);
+            row[k] = left * c + right * s;
+            row[i] = -left * s.conj() + right * c.conj();
+        }
+    }
+    for (i, row) in a.iter_mut().enumerate().take(m) {
+        row[i] += shift;
+    }
+}
+
+/// Matrix `x op y` when either side is a matrix.
+pub fn operate(token: &str, x: &Value, y: &Value) -> Result<Value, CalcError> {
+    let result = match (x, y) {
+        (Value::Matrix(a), Value::Matrix(b)) => match token {
+            "+" => a.zip(b, |x, y| x + y)?,
+            "-" => a.zip(b, |x, y| x - y)?,
+            "*" => a.mul(b)?,
+            "/" => a.mul(&b.inv()?)?,
+            _ => return Err(error(&format!("Operator '{}' needs a scalar", token))),
+        },
+        (Value::Matrix(a), y) => {
+            let y = scalar(y)?;
+            match token {
+                "*" => a.map(|x| x * y),
+                "/" => a.map(|x| x / y),
+                "^" => a.pow(y)?,
+                _ => return Err(error(&format!("Operator '{}' needs two matrices", token))),
+            }
+        }
+        (x, Value::Matrix(b)) => {
+            let x = scalar(x)?;
+            match token {
+                "*" => b.map(|y| x * y),
+                "/" => b.inv()?.map(|y| x * y),
+                _ => return Err(error(&format!("Operator '{}' needs two matrices", token))),
+            }
+        }
+        _ => unreachable!(),
+    };
+    finite(result)
+}
+
+fn finite(m: Matrix) -> Result<Value, CalcError> {
+    match m.data.iter().all(|x| x.is_finite()) {
+        true => Ok(Value::Matrix(m)),
+        false => Err(CalcError::Math(MathError::TooLarge)),
+    }
+}
+
+/// Linear algebra built-ins on matrix arguments, `None` for functions that
+/// apply to each entry instead.
+pub fn apply(name: &str, args: &[Value]) -> Option<Result<Value, CalcError>> {
+    let matrix = |i: usize| argument(&args[i]);
+    let result = match name {
+        "det" => matrix(0).and_then(|m| m.det()).map(Value::Float),
+        "rank" => matrix(0).map(|m| Value::Float(m.rank() as f64)),
+        "transpose" => matrix(0).map(|m| Value::Matrix(m.transpose())),
+        "inv" => matrix(0).and_then(|m| m.inv()).and_then(finite),
+        "dot" => vectors(args)
+            .map(|(a, b)| Value::Float(a.data.iter().zip(&b.data).map(|(x, y)| x * y).sum())),
+        "cross" => cross(args),
+        "solve" => (|| {
+            let (a, b) = (matrix(0)?, matrix(1)?);
+            // a row vector of constants is solved as a column
+            let row = b.rows == 1 && a.rows != 1;
+            let b = if row { b.transpose() } else { b };
+            let x = a.solve(&b)?;
+            finite(if row { x.transpose() } else { x })
+        })(),
+        "eig" => matrix(0).and_then(|m| {
+            let eigenvalues = m.eigenvalues()?;
+            let scale = eigenvalues.iter().fold(1f64, |s, z| s.max(z.norm()));
+            if eigenvalues.iter().any(|z| z.im.abs() > 1e-9 * scale) {
+                return Err(error("Eigenvalues are complex"));
+            }
+            let column = eigenvalues.iter().map(|z| vec![z.re]).collect();
+            Ok(Value::Matrix(Matrix::new(column).unwrap()))
+        }),
+        _ => return None,
+    };
+    Some(result)
+}
+
+/// `cross(u, v)` of two vectors of length 3, in the shape of `u`.
+pub fn cross(args: &[Value]) -> Result<Value, CalcError> {
+    let (a, b) = vectors(args)?;
+    let (u, v) = (&a.data, &b.data);
+    if u.len() != 3 {
+        return Err(error("Cross product needs vectors of length 3"));
+    }
+    let w = vec![
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+    Ok(Value::Matrix(Matrix { data: w, ..a }))
+}
+
+/// An argument of the functions above, numbers act as 1x1 matrices.
+fn argument(x: &Value) -> Result<Matrix, CalcError> {
+    match x {
+        Value::Matrix(m) => Ok(m.clone()),
+        x => scalar(x).map(|x| Matrix::new(vec![vec![x]]).unwrap()),
+    }
+}
+
+/// The first two arguments, as vectors of the same length.
+fn vectors(args: &[Value]) -> Result<(Matrix, Matrix), CalcError> {
+    let (a, b) = (argument(&args[0])?, argument(&args[1])?);
+    match a.is_vector() && b.is_vector() && a.data.len() == b.data.len() {
+        true => Ok((a, b)),
+        false => Err(error("Expected two vectors of the same length")),
+    }
+}
+
+impl fmt::Display for Matrix {
+    /// Reads back as the same matrix, e.g. `[1, 2; 3, 4]`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows: Vec<String> = self
+            .rows_of()
+            .iter()
+            .map(|row| {
+                let row: Vec<String> = row.iter().map(|x| x.to_string()).collect();
+                row.join(", ")
+            })
+            .collect();
+        write!(f, "[{}]", rows.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m(rows: &[&[f64]]) -> Matrix {
+        Matrix::new(rows.iter().map(|row| row.to_vec()).collect()).unwrap()
+    }
+
+    #[test]
+    fn elimination() {
+        let a = m(&[&[2., 1., 1.], &[1., 3., 2.], &[1., 0., 0.]]);
+        assert!((a.det().unwrap() + 1.).abs() < 1e-12);
+        assert_eq!(a.rank(), 3);
+        assert_eq!(m(&[&[1., 2.], &[2., 4.]]).rank(), 1);
+        assert_eq!(m(&[&[0., 1.], &[1., 0.]]).det(), Ok(-1.));
+        let identity = a.mul(&a.inv().unwrap()).unwrap();
+        assert!(identity
+            .zip(&Matrix::identity(3), |x, y| (x - y).abs())
+            .unwrap()
+            .data
+            .iter()
+            .all(|e| *e < 1e-12));
+        assert_eq!(m(&[&[1., 2.], &[2., 4.]]).inv(), Err(singular()));
+    }
+
+    #[test]
+    fn eigenvalues() {
+        let close = |m: Matrix, expected: &[f64]| {
+            let eigenvalues = m.eigenvalues().unwrap();
+            assert_eq!(eigenvalues.len(), expected.len());
+            for (z, e) in eigenvalues.iter().zip(expected) {
+                assert!((z - e).norm() < 1e-9, "{} != {}", z, e);
+            }
+        };
+        close(m(&[&[2., 1.], &[1., 2.]]), &[3., 1.]);
+        close(m(&[&[1., 1.], &[0., 1.]]), &[1., 1.]);
+        close(
+            m(&[&[2., 0., 0.], &[1., 3., 0.], &[4., 5., 6.]]),
+            &[6., 3., 2.],
+        );
+        let rotation = m(&[&[0., -1.], &[1., 0.]]).eigenvalues().unwrap();
+        assert!((rotation[0] - Complex64::i()).norm() < 1e-9);
+    }
+}
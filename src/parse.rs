@@ -2,125 +2,196 @@
  * Refer to LICENCE for more information.
  * */
 
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+use crate::ast::{Expr, UnaryOp};
 use crate::error::CalcError;
-use crate::lex::{FunctionContext, Token};
+use crate::lex::{Span, Token};
+
+/// Build an expression tree from `tokens` by precedence climbing, `end` is
+/// where the input ends.
+pub fn parse(tokens: Vec<(Token, Span)>, end: usize) -> Result<Expr, CalcError> {
+    let mut parser = Parser {
+        tokens: tokens.into_iter().peekable(),
+        end,
+    };
+    let expr = parser.expr(0)?;
+    match parser.tokens.next() {
+        None => Ok(expr),
+        Some((Token::RParen, span)) => Err(mismatched(span)),
+        Some((token, span)) => Err(unexpected(&token, span)),
+    }
+}
+
+struct Parser {
+    tokens: Peekable<IntoIter<(Token, Span)>>,
+    end: usize,
+}
+
+fn unexpected(token: &Token, span: Span) -> CalcError {
+    CalcError::Syntax(format!("Unexpected '{}'", token)).at(span)
+}
+
+fn mismatched(span: Span) -> CalcError {
+    CalcError::Syntax("Mismatched parentheses!".into()).at(span)
+}
+
+impl Parser {
+    fn next_if(&mut self, token: &Token) -> Option<Span> {
+        self.tokens
+            .next_if(|(t, _)| t == token)
+            .map(|(_, span)| span)
+    }
+
+    /// Operators binding at least as tight as `min_precedence`, and their operands.
+    fn expr(&mut self, min_precedence: u8) -> Result<Expr, CalcError> {
+        let mut lhs = self.operand()?;
+        while let Some((Token::Operator(op), _)) = self.tokens.peek() {
+            let op = *op;
+            if op.precedence < min_precedence {
+                break;
+            }
+            self.tokens.next();
+            let next_precedence = match op.is_left_associative {
+                true => op.precedence + 1,
+                false => op.precedence,
+            };
+            let rhs = self.expr(next_precedence)?;
+            lhs = Expr::Binary {
+                op: op.token(),
+                span: lhs.span().start..rhs.span().end,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            };
+        }
+        Ok(lhs)
+    }
+
+    /// A prefixed operand followed by any number of `!`.
+    fn operand(&mut self) -> Result<Expr, CalcError> {
+        let mut expr = self.prefixed()?;
+        while let Some(bang) = self.next_if(&Token::Bang) {
+            expr = Expr::Unary {
+                op: UnaryOp::Factorial,
+                span: expr.span().start..bang.end,
+                expr: Box::new(expr),
+            };
+        }
+        Ok(expr)
+    }
 
-pub fn to_postfix(tokens: Vec<Token>) -> Result<Vec<Token>, CalcError> {
-    let mut postfixed: Vec<Token> = vec![];
-    let mut op_stack: Vec<Token> = vec![];
-    let mut tokens = tokens.into_iter().peekable();
-    while let Some(token) = tokens.next() {
+    fn prefixed(&mut self) -> Result<Expr, CalcError> {
+        let (token, span) = self.tokens.next().ok_or_else(|| {
+            CalcError::Syntax("Unexpected end of expression".into()).at(self.end..self.end)
+        })?;
         match token {
-            Token::Num(_) => {
-                postfixed.push(token);
+            Token::Operator(op) if op.token() == "+" => self.prefixed(),
+            Token::Operator(op) if op.token() == "-" => {
+                let expr = self.prefixed()?;
+                Ok(Expr::Unary {
+                    op: UnaryOp::Neg,
+                    span: span.start..expr.span().end,
+                    expr: Box::new(expr),
+                })
             }
-            Token::Function(_) => {
-                op_stack.push(token);
+            Token::LBracket => self.matrix(span),
+            Token::Tilde | Token::Not => {
+                let expr = self.prefixed()?;
+                Ok(Expr::Unary {
+                    op: match token {
+                        Token::Tilde => UnaryOp::BitNot,
+                        _ => UnaryOp::Not,
+                    },
+                    span: span.start..expr.span().end,
+                    expr: Box::new(expr),
+                })
             }
-            Token::Operator(current_op) => {
-                while let Some(top_op) = op_stack.last() {
-                    match top_op {
-                        Token::LParen | Token::Comma => {
-                            break;
-                        }
-                        Token::Operator(x) => {
-                            let tp = x.precedence;
-                            let cp = current_op.precedence;
-                            if tp > cp || (tp == cp && x.is_left_associative) {
-                                postfixed.push(op_stack.pop().unwrap());
-                            } else {
-                                break;
-                            }
-                        }
-                        Token::Function(_) => {
-                            postfixed.push(op_stack.pop().unwrap());
-                        }
-                        _ => {
-                            unreachable!();
-                        }
-                    }
+            Token::Num(value) => Ok(Expr::Literal { value, span }),
+            Token::Ident(name) => match self.next_if(&Token::LParen) {
+                Some(_) => {
+                    let (args, end) = self.args()?;
+                    Ok(Expr::Call {
+                        name,
+                        args,
+                        span: span.start..end,
+                    })
                 }
-                op_stack.push(token);
-            }
+                None => Ok(Expr::Ident { name, span }),
+            },
             Token::LParen => {
-                op_stack.push(token);
-            }
-            Token::RParen | Token::Comma => {
-                let mut push_until_paren: bool = false;
-                while let Some(token) = op_stack.pop() {
-                    if matches!(op_stack.last(), Some(Token::Function(_)) | None)
-                        && token == Token::Comma
-                        || token == Token::LParen
-                    {
-                        push_until_paren = true;
-                        break;
+                let mut expr = self.expr(0)?;
+                match self.tokens.next() {
+                    Some((Token::RParen, close)) => {
+                        // the parentheses belong to the grouped expression
+                        *expr.span_mut() = span.start..close.end;
+                        Ok(expr)
                     }
-                    postfixed.push(token);
-                }
-                if !push_until_paren {
-                    return Err(CalcError::Syntax("Mismatched parentheses!".into()));
-                }
-                if token == Token::Comma {
-                    if tokens.peek() == Some(&Token::Comma) {
-                        return Err(CalcError::Syntax("Empty argument".into()));
-                    }
-                    op_stack.push(token);
+                    // only calls take comma separated arguments
+                    Some((Token::Comma, comma)) => Err(unexpected(&Token::Comma, comma)),
+                    _ => Err(mismatched(span)),
                 }
             }
+            token => Err(unexpected(&token, span)),
         }
     }
-    while let Some(op) = op_stack.pop() {
-        postfixed.push(op);
-    }
-    // println!("{:?}", postfixed);
-    Ok(postfixed)
-}
 
-pub fn eval_postfix(ctx: &FunctionContext, postfixed: Vec<Token>) -> Result<f64, CalcError> {
-    let mut num_stack: Vec<f64> = vec![];
-    let mut args = vec![];
-    for token in postfixed {
-        match token {
-            Token::Num(n) => {
-                num_stack.push(n);
+    /// Rows of a matrix literal up to its closing bracket, `open` is the span
+    /// of the opening one.
+    fn matrix(&mut self, open: Span) -> Result<Expr, CalcError> {
+        let mut rows = vec![vec![]];
+        loop {
+            if let Some((Token::Comma | Token::Semicolon | Token::RBracket, span)) =
+                self.tokens.peek()
+            {
+                return Err(CalcError::Syntax("Empty matrix entry".into()).at(span.clone()));
             }
-            Token::Operator(op) => {
-                if let Some(n2) = num_stack.pop() {
-                    if let Some(n1) = num_stack.pop() {
-                        num_stack.push(op.operate(n1, n2)?);
-                    } else {
-                        return Err(CalcError::Parser(
-                            "Too many operators, too few operands".to_string(),
-                        ));
+            rows.last_mut().unwrap().push(self.expr(0)?);
+            match self.tokens.next() {
+                Some((Token::Comma, _)) => continue,
+                Some((Token::Semicolon, _)) => rows.push(vec![]),
+                Some((Token::RBracket, close)) => {
+                    let span = open.start..close.end;
+                    if rows.iter().any(|row| row.len() != rows[0].len()) {
+                        return Err(CalcError::Syntax(
+                            "Rows of a matrix must have the same length".into(),
+                        )
+                        .at(span));
                     }
-                } else {
-                    return Err(CalcError::Parser(
-                        "Too many operators, too few operands".to_string(),
-                    ));
+                    return Ok(Expr::Matrix { rows, span });
                 }
+                Some((token, span)) => return Err(unexpected(&token, span)),
+                None => return Err(CalcError::Syntax("Mismatched brackets!".into()).at(open)),
             }
-            Token::Function(func) => {
-                let arity = func.arity();
-                for _ in 0..arity {
-                    if let Some(arg) = num_stack.pop() {
-                        args.insert(0, arg);
-                    } else {
-                        return Err(CalcError::Parser(format!(
-                            "To few arguments for function, need {arity}"
-                        )));
-                    }
-                }
-                num_stack.push(func.apply(ctx, &args)?);
-                args.clear();
-            }
-            _ => unreachable!("wut"),
         }
     }
-    if num_stack.len() == 1 {
-        Ok(num_stack.pop().unwrap())
-    } else {
-        Err(CalcError::Parser(
-            "Too many operators, too few operands".to_string(),
-        ))
+
+    /// Comma separated arguments of a call and where its closing paren ends.
+    fn args(&mut self) -> Result<(Vec<Expr>, usize), CalcError> {
+        let mut args = vec![];
+        if let Some(span) = self.next_if(&Token::RParen) {
+            return Ok((args, span.end));
+        }
+        loop {
+            if let Some((Token::Comma | Token::RParen, span)) = self.tokens.peek() {
+                return Err(CalcError::Syntax("Empty argument".into()).at(span.clone()));
+            }
+            let mut arg = self.expr(0)?;
+            if self.next_if(&Token::Equals).is_some() {
+                let rhs = self.expr(0)?;
+                arg = Expr::Equation {
+                    span: arg.span().start..rhs.span().end,
+                    lhs: Box::new(arg),
+                    rhs: Box::new(rhs),
+                };
+            }
+            args.push(arg);
+            match self.tokens.next() {
+                Some((Token::Comma, _)) => continue,
+                Some((Token::RParen, span)) => return Ok((args, span.end)),
+                Some((token, span)) => return Err(unexpected(&token, span)),
+                None => return Err(mismatched(self.end..self.end)),
+            }
+        }
     }
 }
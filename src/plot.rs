@@ -0,0 +1,567 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! `plot sin(x), cos(x), -pi, pi`, expressions in `x` sampled over a range
+//! and drawn in braille characters, or as an SVG image with `> file.svg`.
+
+use std::fmt::Write;
+
+use crate::ast::Expr;
+use crate::calculus::{self, Bound};
+use crate::env::Environment;
+use crate::error::{CalcError, MathError};
+use crate::lex::FunctionContext;
+use crate::value::Value;
+
+/// The variable plotted expressions are in.
+const VARIABLE: &str = "x";
+
+/// ANSI colours of the curves in the terminal, in turn.
+const TERMINAL_COLOURS: [u8; 6] = [34, 31, 32, 33, 35, 36];
+
+/// Colours of the curves in SVG images, in turn.
+const SVG_COLOURS: [&str; 6] = [
+    "#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#17becf",
+];
+
+/// Margins of SVG images around the plot area, in pixels.
+const SVG_MARGIN: (f64, f64, f64, f64) = (60., 20., 20., 40.);
+
+/// A parsed `plot` command, sampled when it is drawn.
+pub struct Plot {
+    ctx: FunctionContext,
+    env: Environment,
+    curves: Vec<Curve>,
+    from: f64,
+    to: f64,
+    svg: Option<String>,
+}
+
+struct Curve {
+    label: String,
+    expr: Expr,
+    /// Where the expression starts in the command, for error locations.
+    offset: usize,
+}
+
+/// Samples of every curve at the same `x`, NaN where a curve is undefined.
+struct Samples {
+    xs: Vec<f64>,
+    ys: Vec<Vec<f64>>,
+    /// Bounds of the `y` axis, outliers near poles left out.
+    lo: f64,
+    hi: f64,
+}
+
+/// Whether `line` is a `plot` command rather than an expression.
+pub fn is_command(line: &str) -> bool {
+    let line = line.trim_start();
+    match line.strip_prefix("plot") {
+        Some(rest) => rest.starts_with(char::is_whitespace) && !rest.trim_start().starts_with('='),
+        None => false,
+    }
+}
+
+impl Plot {
+    /// Parse `plot f, g, ..., from, to`, optionally followed by `> file.svg`.
+    /// Errors point into `line`.
+    pub fn parse(
+        ctx: &FunctionContext,
+        env: &Environment,
+        line: &str,
+        prev_ans: Option<&Value>,
+    ) -> Result<Plot, CalcError> {
+        let start = line.find("plot").map_or(0, |i| i + "plot".len());
+        let (spec, svg) = match line[start..].rsplit_once('>') {
+            Some((spec, path)) if path.trim().ends_with(".svg") => {
+                (spec, Some(path.trim().to_string()))
+            }
+            _ => (&line[start..], None),
+        };
+        let parts = split_arguments(spec);
+        if parts.len() < 3 {
+            return Err(CalcError::Syntax(
+                "Plot expected functions and a range, as in 'plot sin(x), -pi, pi'".into(),
+            )
+            .at(start..start + spec.len()));
+        }
+        let bound = |(offset, text): (usize, &str)| -> Result<f64, CalcError> {
+            let expr =
+                crate::parse_expr(ctx, text, prev_ans).map_err(|e| e.shift(start + offset))?;
+            calculus::real("plot", &expr, ctx, env).map_err(|e| e.shift(start + offset))
+        };
+        let (from, to) = (
+            bound(parts[parts.len() - 2])?,
+            bound(parts[parts.len() - 1])?,
+        );
+        if !from.is_finite() || !to.is_finite() || from == to {
+            let (offset, text) = parts[parts.len() - 1];
+            let span = start + offset..start + offset + text.len();
+            return Err(CalcError::Math(MathError::OutOfBounds).at(span));
+        }
+        let curves = parts[..parts.len() - 2]
+            .iter()
+            .map(|&(offset, text)| {
+                Ok(Curve {
+                    label: text.trim().to_string(),
+                    expr: crate::parse_expr(ctx, text, prev_ans)
+                        .map_err(|e| e.shift(start + offset))?,
+                    offset: start + offset,
+                })
+            })
+            .collect::<Result<_, CalcError>>()?;
+        Ok(Plot {
+            ctx: ctx.clone(),
+            env: env.clone(),
+            curves,
+            from: from.min(to),
+            to: from.max(to),
+            svg,
+        })
+    }
+
+    /// The file given with `> file.svg`, if any.
+    pub fn svg_path(&self) -> Option<&str> {
+        self.svg.as_deref()
+    }
+
+    /// The plot in braille characters, `width` by `height` cells including
+    /// the labels, with ANSI colours if `colour` is set.
+    pub fn to_braille(
+        &self,
+        width: usize,
+        height: usize,
+        colour: bool,
+    ) -> Result<String, CalcError> {
+        // the x axis, its labels and the legend take three lines
+        let rows = height.saturating_sub(3).max(4);
+        // the y labels decide how much room is left for the plot
+        let range = self.sample(2 * width)?;
+        let margin = ticks(range.lo, range.hi, rows / 2)
+            .iter()
+            .map(|(_, label)| label.chars().count())
+            .max()
+            .unwrap_or(1)
+            + 1;
+        // x labels hang over the right edge by half their width
+        let cols = width.saturating_sub(margin + 4).max(8);
+        let samples = Samples {
+            lo: range.lo,
+            hi: range.hi,
+            ..self.sample(2 * cols)?
+        };
+        let mut canvas = Canvas::new(cols, rows);
+        let (dots_x, dots_y) = (2 * cols - 1, 4 * rows - 1);
+        let to_dot_y = |y: f64| (samples.hi - y) / (samples.hi - samples.lo) * dots_y as f64;
+        if samples.lo <= 0. && 0. <= samples.hi {
+            let row = to_dot_y(0.).round() as usize;
+            (0..=dots_x).for_each(|x| canvas.set(x, row, None));
+        }
+        if self.from <= 0. && 0. <= self.to {
+            let col = (-self.from / (self.to - self.from) * dots_x as f64).round() as usize;
+            (0..=dots_y).for_each(|y| canvas.set(col, y, None));
+        }
+        for (curve, ys) in samples.ys.iter().enumerate() {
+            for x in 1..ys.len() {
+                canvas.segment(x - 1, to_dot_y(ys[x - 1]), to_dot_y(ys[x]), Some(curve));
+            }
+        }
+
+        let y_ticks: Vec<(usize, String)> = ticks(samples.lo, samples.hi, rows / 2)
+            .into_iter()
+            .map(|(y, label)| (to_dot_y(y).round() as usize / 4, label))
+            .collect();
+        let x_ticks: Vec<(usize, String)> = ticks(self.from, self.to, cols / 10)
+            .into_iter()
+            .map(|(x, label)| {
+                let dot = (x - self.from) / (self.to - self.from) * dots_x as f64;
+                (dot.round() as usize / 2, label)
+            })
+            .collect();
+
+        let mut out = String::new();
+        for row in 0..rows {
+            let (label, tick) = match y_ticks.iter().find(|(r, _)| *r == row) {
+                Some((_, label)) => (&label[..], '┤'),
+                None => ("", '│'),
+            };
+            write!(out, "{:>1$}{2}", label, margin, tick).unwrap();
+            out.push_str(&canvas.line(row, colour));
+            out.push('\n');
+        }
+        let mut axis = vec!['─'; cols];
+        for (col, _) in &x_ticks {
+            axis[*col] = '┬';
+        }
+        writeln!(
+            out,
+            "{:>1$}└{2}",
+            "",
+            margin,
+            axis.iter().collect::<String>()
+        )
+        .unwrap();
+        let mut labels = String::new();
+        for (col, label) in &x_ticks {
+            // centred under the tick, unless it would run into the previous one
+            let at = (margin + 1 + col).saturating_sub(label.chars().count() / 2);
+            let used = labels.chars().count();
+            if at > used || used == 0 {
+                labels.push_str(&" ".repeat(at.saturating_sub(used)));
+                labels.push_str(label);
+            }
+        }
+        writeln!(out, "{}", labels).unwrap();
+        let legend: Vec<String> = self
+            .curves
+            .iter()
+            .enumerate()
+            .map(|(i, curve)| match colour {
+                true => format!("\x1b[{}m⠒⠒\x1b[0m {}", terminal_colour(i), curve.label),
+                false => format!("{} {}", legend_mark(i), curve.label),
+            })
+            .collect();
+        write!(out, "{:>1$} {2}", "", margin, legend.join("   ")).unwrap();
+        Ok(out)
+    }
+
+    /// The plot as an SVG image of `width` by `height` pixels.
+    pub fn to_svg(&self, width: usize, height: usize) -> Result<String, CalcError> {
+        let (left, top, right, bottom) = SVG_MARGIN;
+        let (w, h) = (width as f64 - left - right, height as f64 - top - bottom);
+        let samples = self.sample((2. * w).max(2.) as usize)?;
+        let px = |x: f64| left + (x - self.from) / (self.to - self.from) * w;
+        let py = |y: f64| top + (samples.hi - y) / (samples.hi - samples.lo) * h;
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}" font-family="sans-serif" font-size="12">"#,
+            width, height
+        )
+        .unwrap();
+        writeln!(out, r#"<rect width="100%" height="100%" fill="white"/>"#).unwrap();
+        writeln!(
+            out,
+            r#"<clipPath id="area"><rect x="{}" y="{}" width="{}" height="{}"/></clipPath>"#,
+            left, top, w, h
+        )
+        .unwrap();
+        for (y, label) in ticks(samples.lo, samples.hi, (h / 40.) as usize) {
+            let y = py(y);
+            writeln!(
+                out,
+                r##"<line x1="{}" y1="{y:.1}" x2="{}" y2="{y:.1}" stroke="#eee"/><text x="{}" y="{:.1}" text-anchor="end">{}</text>"##,
+                left,
+                left + w,
+                left - 6.,
+                y + 4.,
+                escape(&label)
+            )
+            .unwrap();
+        }
+        for (x, label) in ticks(self.from, self.to, (w / 80.) as usize) {
+            let x = px(x);
+            writeln!(
+                out,
+                r##"<line x1="{x:.1}" y1="{}" x2="{x:.1}" y2="{}" stroke="#eee"/><text x="{x:.1}" y="{}" text-anchor="middle">{}</text>"##,
+                top,
+                top + h,
+                top + h + 16.,
+                escape(&label)
+            )
+            .unwrap();
+        }
+        if samples.lo <= 0. && 0. <= samples.hi {
+            let y = py(0.);
+            writeln!(
+                out,
+                r##"<line x1="{}" y1="{y:.1}" x2="{}" y2="{y:.1}" stroke="#888"/>"##,
+                left,
+                left + w
+            )
+            .unwrap();
+        }
+        if self.from <= 0. && 0. <= self.to {
+            let x = px(0.);
+            writeln!(
+                out,
+                r##"<line x1="{x:.1}" y1="{}" x2="{x:.1}" y2="{}" stroke="#888"/>"##,
+                top,
+                top + h
+            )
+            .unwrap();
+        }
+        writeln!(
+            out,
+            r##"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="#444"/>"##,
+            left, top, w, h
+        )
+        .unwrap();
+        for (i, ys) in samples.ys.iter().enumerate() {
+            let mut path = String::new();
+            let mut previous = f64::NAN;
+            for (x, y) in samples.xs.iter().zip(ys) {
+                let y = py(*y);
+                // a jump from below the plot to above it is a pole, not a line
+                let pole = (previous < top && y > top + h) || (previous > top + h && y < top);
+                let command = if previous.is_nan() || pole { 'M' } else { 'L' };
+                previous = y;
+                if y.is_finite() {
+                    let y = y.clamp(top - h, top + 2. * h);
+                    write!(path, "{}{:.1},{:.1}", command, px(*x), y).unwrap();
+                }
+            }
+            writeln!(
+                out,
+                r#"<path d="{}" fill="none" stroke="{}" stroke-width="1.5" clip-path="url(#area)"/>"#,
+                path,
+                SVG_COLOURS[i % SVG_COLOURS.len()]
+            )
+            .unwrap();
+        }
+        for (i, curve) in self.curves.iter().enumerate() {
+            writeln!(
+                out,
+                r#"<text x="{}" y="{}" fill="{}">{}</text>"#,
+                left + 8.,
+                top + 16. * (i + 1) as f64,
+                SVG_COLOURS[i % SVG_COLOURS.len()],
+                escape(&curve.label)
+            )
+            .unwrap();
+        }
+        out.push_str("</svg>\n");
+        Ok(out)
+    }
+
+    /// Every curve at `count` evenly spaced points of the range.
+    fn sample(&self, count: usize) -> Result<Samples, CalcError> {
+        let count = count.max(2);
+        let xs: Vec<f64> = (0..count)
+            .map(|i| self.from + (self.to - self.from) * i as f64 / (count - 1) as f64)
+            .collect();
+        let variable = Expr::Ident {
+            name: VARIABLE.into(),
+            span: 0..0,
+        };
+        let mut ys = vec![];
+        for curve in &self.curves {
+            let mut f = Bound::new("plot", &self.ctx, &self.env, &curve.expr, &variable)?;
+            let mut error = None;
+            let values: Vec<f64> = xs
+                .iter()
+                .map(|&x| match f.at(x) {
+                    Ok(y) => y,
+                    Err(e) => {
+                        error.get_or_insert(e);
+                        f64::NAN
+                    }
+                })
+                .collect();
+            // undefined in places is a gap, undefined everywhere a mistake
+            if let (true, Some(e)) = (values.iter().all(|y| !y.is_finite()), error) {
+                return Err(e.shift(curve.offset));
+            }
+            ys.push(values);
+        }
+        let (lo, hi) = y_range(
+            ys.iter()
+                .flatten()
+                .copied()
+                .filter(|y| y.is_finite())
+                .collect(),
+        );
+        Ok(Samples { xs, ys, lo, hi })
+    }
+}
+
+/// Bounds of the `y` axis for `values`, leaving out spikes near poles.
+fn y_range(mut values: Vec<f64>) -> (f64, f64) {
+    if values.is_empty() {
+        return (-1., 1.);
+    }
+    values.sort_by(f64::total_cmp);
+    let quantile = |p: f64| values[((values.len() - 1) as f64 * p).round() as usize];
+    let (mut lo, mut hi) = (values[0], values[values.len() - 1]);
+    let (q_lo, q_hi) = (quantile(0.05), quantile(0.95));
+    if q_hi > q_lo && hi - lo > 10. * (q_hi - q_lo) {
+        let pad = q_hi - q_lo;
+        lo = lo.max(q_lo - pad);
+        hi = hi.min(q_hi + pad);
+    }
+    if hi - lo <= f64::EPSILON * lo.abs().max(hi.abs()) {
+        let pad = (lo.abs() / 10.).max(1.);
+        return (lo - pad, hi + pad);
+    }
+    // keep the extremes off the frame
+    let pad = (hi - lo) / 20.;
+    (lo - pad, hi + pad)
+}
+
+/// Round numbers between `lo` and `hi`, about `count` of them or a few more,
+/// with labels.
+fn ticks(lo: f64, hi: f64, count: usize) -> Vec<(f64, String)> {
+    let raw = (hi - lo) / count.max(1) as f64;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    let step = [1., 2., 5., 10.]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|&step| step >= raw / 1.5)
+        .unwrap_or(10. * magnitude);
+    let decimals = (-step.log10().floor()).max(0.) as usize;
+    let first = (lo / step).ceil() as i64;
+    let last = (hi / step).floor() as i64;
+    (first..=last)
+        .map(|i| {
+            let x = i as f64 * step;
+            let label = match i {
+                0 => "0".to_string(),
+                _ => format!("{:.*}", decimals, x),
+            };
+            (x, label)
+        })
+        .collect()
+}
+
+/// Dots of braille characters, each cell 2 dots wide and 4 high.
+struct Canvas {
+    cols: usize,
+    rows: usize,
+    dots: Vec<u8>,
+    /// Curve drawn last in each cell, which gives it its colour.
+    colours: Vec<Option<usize>>,
+}
+
+impl Canvas {
+    fn new(cols: usize, rows: usize) -> Canvas {
+        Canvas {
+            cols,
+            rows,
+            dots: vec![0; cols * rows],
+            colours: vec![None; cols * rows],
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, curve: Option<usize>) {
+        if x >= 2 * self.cols || y >= 4 * self.rows {
+            return;
+        }
+        const BITS: [[u8; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+        let cell = y / 4 * self.cols + x / 2;
+        self.dots[cell] |= BITS[x % 2][y % 4];
+        if curve.is_some() {
+            self.colours[cell] = curve;
+        }
+    }
+
+    /// A line from `from` in column `x` to `to` in column `x + 1`, drawn as
+    /// dots half way in each column and clipped to the canvas.
+    fn segment(&mut self, x: usize, from: f64, to: f64, curve: Option<usize>) {
+        let height = (4 * self.rows - 1) as f64;
+        // undefined, both ends off the same edge, or a pole in between
+        let off = |y: f64| (y < 0.) as i8 - (y > height) as i8;
+        if from.is_nan() || to.is_nan() || off(from) * off(to) != 0 {
+            return;
+        }
+        let middle = (from + to) / 2.;
+        let mut column = |x, a: f64, b: f64| {
+            let (a, b) = (a.min(b).max(0.), a.max(b).min(height));
+            if a <= b {
+                for y in a.round() as usize..=b.round() as usize {
+                    self.set(x, y, curve);
+                }
+            }
+        };
+        column(x, from, middle);
+        column(x + 1, middle, to);
+    }
+
+    fn line(&self, row: usize, colour: bool) -> String {
+        let mut line = String::new();
+        for col in 0..self.cols {
+            let cell = row * self.cols + col;
+            let c = char::from_u32(0x2800 + u32::from(self.dots[cell])).unwrap();
+            match (colour, self.colours[cell]) {
+                (true, Some(curve)) => {
+                    write!(line, "\x1b[{}m{}\x1b[0m", terminal_colour(curve), c).unwrap()
+                }
+                _ => line.push(c),
+            }
+        }
+        line
+    }
+}
+
+fn terminal_colour(curve: usize) -> u8 {
+    TERMINAL_COLOURS[curve % TERMINAL_COLOURS.len()]
+}
+
+/// Tells curves apart in the legend without colours.
+fn legend_mark(curve: usize) -> &'static str {
+    ["⠒⠒", "⠤⠤", "⠉⠉", "⣀⣀"][curve % 4]
+}
+
+/// Top level arguments of `a, f(b, c), d`, with their offsets.
+fn split_arguments(spec: &str) -> Vec<(usize, &str)> {
+    let mut parts = vec![];
+    let (mut depth, mut start) = (0usize, 0);
+    for (i, c) in spec.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth = depth.saturating_sub(1),
+            ',' if depth == 0 => {
+                parts.push((start, &spec[start..i]));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push((start, &spec[start..]));
+    parts
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_ticks() {
+        let labels = |lo, hi, count| -> Vec<String> {
+            ticks(lo, hi, count).into_iter().map(|(_, l)| l).collect()
+        };
+        assert_eq!(labels(-1., 1., 4), ["-1.0", "-0.5", "0", "0.5", "1.0"]);
+        assert_eq!(labels(-3.2, 3.2, 3), ["-2", "0", "2"]);
+        assert_eq!(labels(0., 1000., 2), ["0", "500", "1000"]);
+    }
+
+    #[test]
+    fn commands() {
+        assert!(is_command("plot sin(x), -pi, pi"));
+        assert!(!is_command("plot = 2"));
+        assert!(!is_command("plotted + 1"));
+        let (ctx, env) = (FunctionContext::default(), Environment::new());
+        let plot = Plot::parse(&ctx, &env, "plot x^2, 1 - x, -1, 1 > out.svg", None).unwrap();
+        assert_eq!(plot.svg_path(), Some("out.svg"));
+        let drawing = plot.to_braille(40, 12, false).unwrap();
+        assert_eq!(drawing.lines().count(), 12);
+        assert!(drawing.ends_with("⠒⠒ x^2   ⠤⠤ 1 - x"));
+        assert_eq!(plot.to_svg(400, 300).unwrap().matches("<path").count(), 2);
+        let error = |line| {
+            let plot = Plot::parse(&ctx, &env, line, None)?;
+            plot.to_braille(40, 12, false).map(|_| ())
+        };
+        assert_eq!(
+            error("plot sqrt(-1) + y, 0, 1").unwrap_err().span(),
+            Some(16..17)
+        );
+        assert_eq!(error("plot x, 1, 1").unwrap_err().span(), Some(10..12));
+    }
+}
@@ -0,0 +1,242 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Primality and factorization of integers up to 64 bits, deterministic
+//! Miller-Rabin for the tests and Pollard's rho for the factors.
+
+use std::fmt;
+
+use num::{BigInt, Integer, One, Signed, ToPrimitive, Zero};
+
+use crate::error::{CalcError, MathError};
+use crate::value::Value;
+
+/// Largest prime that fits in 64 bits.
+pub const LARGEST_PRIME: u64 = 18_446_744_073_709_551_557;
+
+/// Witnesses that make Miller-Rabin exact below 3.3 * 10^24.
+const WITNESSES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+/// Result of `factor`, prints as `2^3 * 3 * 5` and otherwise stands for the
+/// number it factors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Factorization {
+    pub number: Value,
+    pub negative: bool,
+    /// Prime factors in increasing order, with their multiplicity.
+    pub primes: Vec<(u64, u32)>,
+}
+
+pub fn is_prime(n: u64) -> bool {
+    if n < 2 {
+        return false;
+    }
+    if let Some(&p) = WITNESSES.iter().find(|&&p| n.is_multiple_of(p)) {
+        return n == p;
+    }
+    // n - 1 = d 2^s with d odd
+    let s = (n - 1).trailing_zeros();
+    let d = (n - 1) >> s;
+    WITNESSES.iter().all(|&a| {
+        let mut x = pow_mod(a, d, n);
+        if x == 1 || x == n - 1 {
+            return true;
+        }
+        (1..s).any(|_| {
+            x = mul_mod(x, x, n);
+            x == n - 1
+        })
+    })
+}
+
+/// Smallest prime above `n`, `None` past the largest 64 bit prime.
+pub fn next_prime(n: u64) -> Option<u64> {
+    (n.checked_add(1)?..=u64::MAX).find(|&k| is_prime(k))
+}
+
+/// Largest prime below `n`, if any.
+pub fn prev_prime(n: u64) -> Option<u64> {
+    (2..n).rev().find(|&k| is_prime(k))
+}
+
+/// Prime factors of `n > 0` in increasing order, with their multiplicity.
+pub fn factor(n: u64) -> Vec<(u64, u32)> {
+    let mut primes = vec![];
+    split(n, &mut primes);
+    primes.sort_unstable();
+    let mut factors: Vec<(u64, u32)> = vec![];
+    for p in primes {
+        match factors.last_mut() {
+            Some((q, k)) if *q == p => *k += 1,
+            _ => factors.push((p, 1)),
+        }
+    }
+    factors
+}
+
+/// Collect the prime factors of `n`, with repetitions and in any order.
+fn split(mut n: u64, primes: &mut Vec<u64>) {
+    for p in WITNESSES {
+        while n.is_multiple_of(p) {
+            primes.push(p);
+            n /= p;
+        }
+    }
+    if n == 1 {
+        return;
+    }
+    if is_prime(n) {
+        primes.push(n);
+        return;
+    }
+    let d = rho(n);
+    split(d, primes);
+    split(n / d, primes);
+}
+
+/// A non-trivial divisor of the composite `n`, which has no factor below 41,
+/// by Pollard's rho with Brent's cycle detection.
+fn rho(n: u64) -> u64 {
+    // gcds are taken over products of this many differences
+    const BATCH: u64 = 128;
+    for c in 1.. {
+        let f = |x: u64| ((u128::from(mul_mod(x, x, n)) + c) % u128::from(n)) as u64;
+        let (mut x, mut y, mut saved) = (2, 2, 2);
+        let (mut product, mut divisor, mut length) = (1, 1, 1);
+        while divisor == 1 {
+            x = y;
+            for _ in 0..length {
+                y = f(y);
+            }
+            let mut k = 0;
+            while k < length && divisor == 1 {
+                saved = y;
+                for _ in 0..BATCH.min(length - k) {
+                    y = f(y);
+                    product = mul_mod(product, x.abs_diff(y), n);
+                }
+                divisor = product.gcd(&n);
+                k += BATCH;
+            }
+            length *= 2;
+        }
+        if divisor == n {
+            // the batch overshot, retrace it one step at a time
+            loop {
+                saved = f(saved);
+                divisor = x.abs_diff(saved).gcd(&n);
+                if divisor > 1 {
+                    break;
+                }
+            }
+        }
+        if divisor != n {
+            return divisor;
+        }
+    }
+    unreachable!()
+}
+
+/// Euler's totient from the factorization of a number.
+pub fn totient(primes: &[(u64, u32)]) -> BigInt {
+    primes
+        .iter()
+        .map(|&(p, k)| BigInt::from(p).pow(k - 1) * (p - 1))
+        .product()
+}
+
+/// All divisors in increasing order, from the factorization of a number.
+pub fn divisors(primes: &[(u64, u32)]) -> Vec<u64> {
+    let mut divisors = vec![1];
+    for &(p, k) in primes {
+        let mut powers = vec![];
+        for d in &divisors {
+            let mut d = *d;
+            for _ in 0..k {
+                d *= p;
+                powers.push(d);
+            }
+        }
+        divisors.extend(powers);
+    }
+    divisors.sort_unstable();
+    divisors
+}
+
+/// Inverse of `a` modulo `m`, with the sign of `m`.
+pub fn mod_inv(a: &BigInt, m: &BigInt) -> Result<BigInt, CalcError> {
+    if m.is_zero() {
+        return Err(CalcError::Math(MathError::DivideByZero));
+    }
+    let gcd = a.extended_gcd(m);
+    if !gcd.gcd.abs().is_one() {
+        // `a` and `m` share a factor, nothing times `a` is 1
+        return Err(CalcError::Math(MathError::OutOfBounds));
+    }
+    Ok((gcd.x * gcd.gcd.signum()).mod_floor(m))
+}
+
+/// `n` as an argument of the functions above, whose size is all that counts.
+pub fn to_u64(n: &BigInt) -> Result<u64, CalcError> {
+    n.abs()
+        .to_u64()
+        .ok_or(CalcError::Math(MathError::ArgumentTooLarge(u64::MAX)))
+}
+
+fn mul_mod(a: u64, b: u64, n: u64) -> u64 {
+    (u128::from(a) * u128::from(b) % u128::from(n)) as u64
+}
+
+fn pow_mod(mut base: u64, mut exponent: u64, n: u64) -> u64 {
+    let mut result = 1;
+    base %= n;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul_mod(result, base, n);
+        }
+        base = mul_mod(base, base, n);
+        exponent >>= 1;
+    }
+    result
+}
+
+impl fmt::Display for Factorization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let factors: Vec<String> = self
+            .primes
+            .iter()
+            .map(|(p, k)| match k {
+                1 => p.to_string(),
+                k => format!("{}^{}", p, k),
+            })
+            .collect();
+        let sign = if self.negative { "-" } else { "" };
+        match factors.is_empty() {
+            true => write!(f, "{}1", sign),
+            false => write!(f, "{}{}", sign, factors.join(" * ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primes_across_the_range() {
+        // strong pseudoprimes to several bases
+        assert!(!is_prime(3_215_031_751) && !is_prime(3_825_123_056_546_413_051));
+        assert!(is_prime(LARGEST_PRIME));
+        assert_eq!(next_prime(LARGEST_PRIME), None);
+        assert_eq!(prev_prime(u64::MAX), Some(LARGEST_PRIME));
+        assert_eq!(factor(360), [(2, 3), (3, 2), (5, 1)]);
+        // product of two primes near 2^32
+        assert_eq!(
+            factor(18_446_743_979_220_271_189),
+            [(4_294_967_279, 1), (4_294_967_291, 1)]
+        );
+        assert_eq!(divisors(&factor(12)), [1, 2, 3, 4, 6, 12]);
+        assert_eq!(totient(&factor(36)), BigInt::from(12));
+    }
+}
@@ -0,0 +1,144 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Exact arithmetic on fractions. Every operation returns `None` when its
+//! result is not (cheaply) rational, the caller then falls back to floats.
+
+use num::{BigInt, BigRational, One, Signed, ToPrimitive, Zero};
+
+use crate::error::{CalcError, MathError};
+use crate::stats;
+
+/// Largest exponent `^` evaluates exactly.
+const MAX_EXPONENT: i32 = 100_000;
+
+/// Largest argument `!` evaluates exactly.
+const MAX_FACTORIAL: u64 = 10_000;
+
+fn pow(x: &BigRational, y: &BigRational) -> Option<Result<BigRational, CalcError>> {
+    if !y.is_integer() {
+        return None;
+    }
+    let n = y
+        .to_integer()
+        .to_i32()
+        .filter(|n| n.abs() <= MAX_EXPONENT)?;
+    if x.is_zero() && n < 0 {
+        return Some(Err(CalcError::Math(MathError::TooLarge)));
+    }
+    Some(Ok(x.pow(n)))
+}
+
+/// `x!`, defined for non-negative integers.
+pub fn factorial(x: &BigRational) -> Result<BigRational, CalcError> {
+    if !x.is_integer() || x.is_negative() {
+        return Err(CalcError::Math(MathError::OutOfBounds));
+    }
+    let n = x
+        .to_integer()
+        .to_u64()
+        .filter(|&n| n <= MAX_FACTORIAL)
+        .ok_or(CalcError::Math(MathError::TooLarge))?;
+    let product = (2..=n).fold(BigInt::one(), |acc, k| acc * k);
+    Ok(BigRational::from_integer(product))
+}
+
+/// Rational counterparts of everything in `lex::OPERATORS`.
+pub fn operate(
+    token: &str,
+    x: &BigRational,
+    y: &BigRational,
+) -> Option<Result<BigRational, CalcError>> {
+    let divide_by_zero = Err(CalcError::Math(MathError::DivideByZero));
+    Some(match token {
+        "+" => Ok(x + y),
+        "-" => Ok(x - y),
+        "*" => Ok(x * y),
+        "/" if y.is_zero() => divide_by_zero,
+        "/" => Ok(x / y),
+        "//" if y.is_zero() => divide_by_zero,
+        "//" => Ok((x / y).floor()),
+        "%" if y.is_zero() => divide_by_zero,
+        "%" => Ok(x - (x / y).trunc() * y),
+        "^" => return pow(x, y),
+        _ => unreachable!(),
+    })
+}
+
+/// Built-in functions that map rationals to rationals.
+pub fn apply(name: &str, args: &[BigRational]) -> Option<BigRational> {
+    let x = args.first()?;
+    match name {
+        "abs" => Some(x.abs()),
+        "re" | "conj" => Some(x.clone()),
+        "im" => Some(BigRational::zero()),
+        "ceil" => Some(x.ceil()),
+        "floor" => Some(x.floor()),
+        "round" => match args.get(1) {
+            Some(digits) => {
+                let digits = Some(digits)
+                    .filter(|d| d.is_integer())?
+                    .to_integer()
+                    .to_i32()
+                    .filter(|d| d.abs() <= MAX_EXPONENT)?;
+                let scale = BigRational::from_integer(10.into()).pow(digits.abs());
+                Some(match digits < 0 {
+                    true => (x / &scale).round() * scale,
+                    false => (x * &scale).round() / scale,
+                })
+            }
+            None => Some(x.round()),
+        },
+        "gamma" if x.is_integer() && x.is_positive() => factorial(&(x - BigRational::one())).ok(),
+        "beta" => {
+            let y = args.get(1).filter(|y| y.is_integer() && y.is_positive())?;
+            if !x.is_integer() || !x.is_positive() {
+                return None;
+            }
+            let one = BigRational::one();
+            // B(a, b) = (a - 1)! (b - 1)! / (a + b - 1)!
+            let numerator = factorial(&(x - &one)).ok()? * factorial(&(y - &one)).ok()?;
+            Some(numerator / factorial(&(x + y - one)).ok()?)
+        }
+        _ => stats::exact(name, args),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn r(n: i64, d: i64) -> BigRational {
+        BigRational::new(n.into(), d.into())
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(operate("+", &r(1, 3), &r(1, 6)).unwrap(), Ok(r(1, 2)));
+        assert_eq!(operate("/", &r(2, 3), &r(4, 1)).unwrap(), Ok(r(1, 6)));
+        assert_eq!(operate("%", &r(-7, 2), &r(1, 1)).unwrap(), Ok(r(-1, 2)));
+        assert_eq!(
+            operate("/", &r(1, 1), &r(0, 1)).unwrap(),
+            Err(CalcError::Math(MathError::DivideByZero))
+        );
+    }
+
+    #[test]
+    fn powers() {
+        assert_eq!(operate("^", &r(2, 3), &r(-2, 1)).unwrap(), Ok(r(9, 4)));
+        assert_eq!(operate("^", &r(2, 1), &r(1, 2)), None);
+        assert_eq!(factorial(&r(5, 1)), Ok(r(120, 1)));
+        assert_eq!(
+            factorial(&r(1, 2)),
+            Err(CalcError::Math(MathError::OutOfBounds))
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(apply("floor", &[r(-3, 2)]), Some(r(-2, 1)));
+        assert_eq!(apply("round", &[r(5, 2)]), Some(r(3, 1)));
+        assert_eq!(apply("sqrt", &[r(4, 1)]), None);
+    }
+}
@@ -1,21 +1,21 @@
 use std::borrow::Cow::{self, Owned};
-use std::path::PathBuf;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 use rustyline::completion::{Completer, FilenameCompleter, Pair};
 use rustyline::config::{Builder, ColorMode, CompletionType, EditMode};
 use rustyline::error::ReadlineError;
-use rustyline::highlight::Highlighter;
+use rustyline::highlight::{CmdKind, Highlighter};
 use rustyline::hint::{Hinter, HistoryHinter};
+use rustyline::history::DefaultHistory;
 use rustyline::validate::Validator;
 use rustyline::{Context, Editor, Helper};
 
-use directories::ProjectDirs;
-
 use regex::Regex;
 
-use eva::error::CalcError;
-use eva::eval_expr;
+use eva::env::Environment;
 use eva::lex::{FunctionContext, CONSTANTS, FUNCTIONS};
+use eva::{check_syntax, plot, Value};
 
 pub struct RLHelper {
     completer: FilenameCompleter,
@@ -25,40 +25,33 @@ pub struct RLHelper {
 
 struct LineHighlighter {
     ctx: FunctionContext,
-    fix: usize,
+    env: Rc<RefCell<Environment>>,
+    prev_ans: Rc<RefCell<Option<Value>>>,
 }
 impl Highlighter for LineHighlighter {
     fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
         Owned(format!("\x1b[90m{}\x1b[0m", hint))
     }
     fn highlight<'l>(&self, line: &'l str, _: usize) -> Cow<'l, str> {
-        use std::fs::OpenOptions;
-        use std::io::{BufRead, BufReader};
-
-        let eva_dirs = ProjectDirs::from("com", "NerdyPepper", "eva").unwrap();
-        let eva_data_dir = eva_dirs.data_dir();
-        let mut previous_ans_path = PathBuf::from(eva_data_dir);
-        previous_ans_path.push("previous_ans.txt");
-
-        let file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .append(true)
-            .open(&previous_ans_path)
-            .unwrap();
-
-        let rdr = BufReader::new(file);
-        let lines = rdr.lines().map(|l| l.unwrap());
-        let prev_ans = match lines.last() {
-            Some(val) => val.parse::<f64>().ok(),
-            None => None,
-        };
-        let op = eval_expr(&self.ctx, self.fix, line, prev_ans);
-        match op {
+        // only parse, evaluating on every keystroke could take as long as an
+        // integral, and switches like `:sig 3` or plots are no expressions
+        if crate::is_switch(line) || plot::is_command(line) {
+            return Cow::Borrowed(line);
+        }
+        let prev_ans = self.prev_ans.borrow();
+        match check_syntax(&self.ctx, line, prev_ans.as_ref()) {
+            Ok(_) if line.replace(' ', "") == "help" => {
+                Owned(line.replace("help", "\x1b[36mhelp\x1b[0m"))
+            }
             Ok(_) => {
                 let constants = CONSTANTS.keys();
-                let functions = FUNCTIONS.keys();
-                let ops = Regex::new(r"(?P<o>[\+-/\*%\^!])").unwrap();
+                let env = self.env.borrow();
+                let user_functions = env.functions();
+                let functions = FUNCTIONS
+                    .keys()
+                    .copied()
+                    .chain(user_functions.iter().map(|f| &f.name[..]));
+                let ops = Regex::new(r"(?P<o>[\+-/\*%\^!&|~<>=])").unwrap();
                 let mut coloured: String = ops.replace_all(line, "\x1b[35m$o\x1b[0m").into();
 
                 for c in constants {
@@ -79,7 +72,8 @@ impl Highlighter for LineHighlighter {
                     // of binary operators that happens before) breaks, the escape-codes & operator
                     // (called `r`) are appended after the highlighted constant.
                     let re = Regex::new(
-                        format!("(?P<o>{})(?P<r>(\x1b\\[35m)?([\\+-/\\*%\\^! ]|$))", c).as_str(),
+                        format!("(?P<o>{})(?P<r>(\x1b\\[35m)?([\\+-/\\*%\\^!&|~<>= ]|$))", c)
+                            .as_str(),
                     )
                     .unwrap();
                     coloured = re.replace_all(&coloured, "\x1b[33m$o\x1b[0m$r").into();
@@ -90,14 +84,13 @@ impl Highlighter for LineHighlighter {
                 }
                 Owned(coloured)
             }
-            Err(CalcError::Help) => Owned(line.replace("help", "\x1b[36mhelp\x1b[0m")),
             Err(_) => Owned(format!("\x1b[31m{}\x1b[0m", line)),
         }
     }
 }
 
 impl Highlighter for RLHelper {
-    fn highlight_char(&self, _: &str, _: usize) -> bool {
+    fn highlight_char(&self, _: &str, _: usize, _: CmdKind) -> bool {
         true
     }
     fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
@@ -131,7 +124,11 @@ impl Validator for RLHelper {}
 
 impl Helper for RLHelper {}
 
-pub fn create_readline(ctx: FunctionContext, fix: usize) -> Editor<RLHelper> {
+pub fn create_readline(
+    ctx: FunctionContext,
+    env: Rc<RefCell<Environment>>,
+    prev_ans: Rc<RefCell<Option<Value>>>,
+) -> Editor<RLHelper, DefaultHistory> {
     let config_builder = Builder::new();
     let config = config_builder
         .color_mode(ColorMode::Enabled)
@@ -139,11 +136,12 @@ pub fn create_readline(ctx: FunctionContext, fix: usize) -> Editor<RLHelper> {
         .history_ignore_space(true)
         .completion_type(CompletionType::Circular)
         .max_history_size(1000)
+        .unwrap()
         .build();
     let mut rl = Editor::with_config(config).unwrap();
     let h = RLHelper {
         completer: FilenameCompleter::new(),
-        highlighter: LineHighlighter { ctx, fix },
+        highlighter: LineHighlighter { ctx, env, prev_ans },
         hinter: HistoryHinter {},
     };
     rl.set_helper(Some(h));
@@ -0,0 +1,298 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Roots of expressions in one unknown, `solve(x^2 = 2, x, 1)`. Newton's
+//! method does the work near a good guess, Brent's method takes over once a
+//! sign change brackets a root.
+
+use std::borrow::Cow;
+
+use crate::ast::Expr;
+use crate::calculus::{self, Bound};
+use crate::env::Environment;
+use crate::error::{CalcError, MathError};
+use crate::lex::{Function, FunctionContext, Relation};
+use crate::matrix::Matrix;
+use crate::value::{self, Value};
+
+/// Most steps Newton's method takes.
+const MAX_ITERATIONS: usize = 200;
+
+/// Most steps Brent's method takes, bisection alone shrinks any bracket to
+/// the tolerance in about 2100.
+const MAX_BRENT_ITERATIONS: usize = 5000;
+
+/// Points at which an interval is searched for sign changes.
+const SAMPLES: usize = 1000;
+
+/// `solve(A, b)` for the linear system `A x = b`, otherwise `solve(f, x)`,
+/// `solve(f, x, guess)` for the root of `f` nearest `guess` or
+/// `solve(f, x, a, b)` for all roots between `a` and `b`. `f` is an
+/// expression or an equation `lhs = rhs`, the unknown shadows any variable
+/// of the same name.
+pub fn solve(ctx: &FunctionContext, env: &Environment, args: &[Expr]) -> Result<Value, CalcError> {
+    if let [a, b] = args {
+        // `A` is a matrix, or a number for a 1x1 system
+        let system = match b {
+            Expr::Ident { .. } => matches!(a.eval(ctx, env), Ok(Value::Matrix(_))),
+            _ => !matches!(a, Expr::Equation { .. }),
+        };
+        if system {
+            return linear(ctx, env, args);
+        }
+    }
+    let expr = match &args[0] {
+        Expr::Equation { lhs, rhs, span } => Expr::Binary {
+            op: "-",
+            lhs: lhs.clone(),
+            rhs: rhs.clone(),
+            span: span.clone(),
+        },
+        expr => expr.clone(),
+    };
+    let mut f = Bound::new("solve", ctx, env, &expr, &args[1])?;
+    match args {
+        [_, _, a, b] => {
+            let a = calculus::real("solve", a, ctx, env)?;
+            let b = calculus::real("solve", b, ctx, env)?;
+            let roots = roots_between(&mut f, a.min(b), a.max(b))?;
+            Ok(Value::Matrix(Matrix::new(vec![roots]).unwrap()))
+        }
+        [_, _, guess] => {
+            let guess = calculus::real("solve", guess, ctx, env)?;
+            nearest_root(&mut f, guess).map(Value::Float)
+        }
+        _ => nearest_root(&mut f, 0.).map(Value::Float),
+    }
+}
+
+fn linear(ctx: &FunctionContext, env: &Environment, args: &[Expr]) -> Result<Value, CalcError> {
+    let args = args
+        .iter()
+        .map(|arg| arg.eval(ctx, env))
+        .collect::<Result<Vec<_>, _>>()?;
+    // numbers are 1x1 systems
+    let solve = Function {
+        token: Cow::Borrowed("solve"),
+        relation: Relation::N2(|_ctx, a, b| b / a),
+    };
+    solve.apply(ctx, env, &args).map(|x| value::conform(ctx, x))
+}
+
+/// Newton's method from `guess`, and Brent's in the closest bracket around
+/// `guess` if that does not converge.
+fn nearest_root(f: &mut Bound, guess: f64) -> Result<f64, CalcError> {
+    if let Some(root) = newton(f, guess) {
+        return Ok(root);
+    }
+    let no_root = || not_converged(format!("Found no root near {}", guess));
+    let (a, b) = bracket(f, guess).ok_or_else(no_root)?;
+    let root = brent(f, a, b)?;
+    match vanishes(f, root, a, b) {
+        true => Ok(root),
+        false => Err(no_root()),
+    }
+}
+
+/// Whether `f` vanishes at `root`, found by Brent's method between `a` and
+/// `b`. Poles and jumps change sign as well, but `f` gets no smaller there.
+fn vanishes(f: &mut Bound, root: f64, a: f64, b: f64) -> bool {
+    let mut y = |x| f.at(x).map_or(f64::NAN, f64::abs);
+    let ends = y(a).min(y(b));
+    y(root) <= ends / 2.
+}
+
+/// Roots where `f` changes sign or vanishes, searched at `SAMPLES` points.
+fn roots_between(f: &mut Bound, a: f64, b: f64) -> Result<Vec<f64>, CalcError> {
+    let xs: Vec<f64> = (0..=SAMPLES)
+        .map(|i| a + (b - a) * i as f64 / SAMPLES as f64)
+        .collect();
+    // points where `f` is undefined interrupt the search
+    let ys: Vec<Option<f64>> = xs.iter().map(|&x| f.at(x).ok()).collect();
+    if ys.iter().all(Option::is_none) {
+        f.at(a)?;
+    }
+    let mut roots = vec![];
+    for i in 0..=SAMPLES {
+        match (ys[i], ys.get(i + 1).copied().flatten()) {
+            (Some(0.), _) => roots.push(xs[i]),
+            (Some(y0), Some(y1)) if y1 != 0. && (y0 > 0.) != (y1 > 0.) => {
+                match brent(f, xs[i], xs[i + 1]) {
+                    Ok(root) if vanishes(f, root, xs[i], xs[i + 1]) => roots.push(root),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    if roots.is_empty() {
+        return Err(not_converged(format!(
+            "Found no root between {} and {}",
+            a, b
+        )));
+    }
+    Ok(roots)
+}
+
+/// Newton's method with a central difference for the slope, `None` if it
+/// leaves the domain or does not settle.
+fn newton(f: &mut Bound, mut x: f64) -> Option<f64> {
+    for _ in 0..MAX_ITERATIONS {
+        let y = f.at(x).ok()?;
+        if y == 0. {
+            return Some(x);
+        }
+        // small enough for roots near 0, not so small that rounding in `f` dominates
+        let h = 1e-6 * x.abs().max(1e-3);
+        let slope = (f.at(x + h).ok()? - f.at(x - h).ok()?) / (2. * h);
+        let step = y / slope;
+        if !step.is_finite() {
+            return None;
+        }
+        x -= step;
+        if step.abs() <= 1e-14 * x.abs().max(1.) {
+            return Some(x);
+        }
+    }
+    None
+}
+
+/// Ends of the first sign change found stepping away from `guess` in both
+/// directions with growing steps.
+fn bracket(f: &mut Bound, guess: f64) -> Option<(f64, f64)> {
+    let start = (guess, f.at(guess).ok());
+    let (mut left, mut right) = (start, start);
+    let mut step = 0.01 * guess.abs().max(1.);
+    for _ in 0..80 {
+        for (side, direction) in [(&mut right, 1.), (&mut left, -1.)] {
+            let x = guess + direction * step;
+            let y = f.at(x).ok();
+            if let (Some(y0), Some(y1)) = (side.1, y) {
+                if (y0 > 0.) != (y1 > 0.) || y1 == 0. {
+                    return Some((side.0.min(x), side.0.max(x)));
+                }
+            }
+            *side = (x, y);
+        }
+        step *= 1.6;
+    }
+    None
+}
+
+/// Brent's method on `[a, b]`, where `f` changes sign.
+fn brent(f: &mut Bound, mut a: f64, mut b: f64) -> Result<f64, CalcError> {
+    let (mut fa, mut fb) = (f.at(a)?, f.at(b)?);
+    if fa == 0. {
+        return Ok(a);
+    }
+    let (mut c, mut fc) = (a, fa);
+    let (mut d, mut e) = (b - a, b - a);
+    for _ in 0..MAX_BRENT_ITERATIONS {
+        if (fb > 0.) == (fc > 0.) {
+            (c, fc) = (a, fa);
+            d = b - a;
+            e = d;
+        }
+        if fc.abs() < fb.abs() {
+            (a, fa) = (b, fb);
+            (b, fb) = (c, fc);
+            (c, fc) = (a, fa);
+        }
+        let tolerance = 2. * f64::EPSILON * b.abs() + 1e-300;
+        let middle = (c - b) / 2.;
+        if middle.abs() <= tolerance || fb == 0. {
+            return Ok(b);
+        }
+        if e.abs() >= tolerance && fa.abs() > fb.abs() {
+            // inverse quadratic interpolation, or the secant if only two points differ
+            let s = fb / fa;
+            let (mut p, mut q) = if a == c {
+                (2. * middle * s, 1. - s)
+            } else {
+                let (q, r) = (fa / fc, fb / fc);
+                (
+                    s * (2. * middle * q * (q - r) - (b - a) * (r - 1.)),
+                    (q - 1.) * (r - 1.) * (s - 1.),
+                )
+            };
+            if p > 0. {
+                q = -q;
+            }
+            p = p.abs();
+            if 2. * p < (3. * middle * q - (tolerance * q).abs()).min((e * q).abs()) {
+                e = d;
+                d = p / q;
+            } else {
+                d = middle;
+                e = d;
+            }
+        } else {
+            d = middle;
+            e = d;
+        }
+        (a, fa) = (b, fb);
+        b += if d.abs() > tolerance {
+            d
+        } else {
+            tolerance.copysign(middle)
+        };
+        fb = f.at(b)?;
+    }
+    Err(not_converged(format!(
+        "Found no root between {} and {}",
+        a, b
+    )))
+}
+
+fn not_converged(details: String) -> CalcError {
+    CalcError::Math(MathError::Convergence(details))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_expr;
+
+    fn solve_for(args: &[&str]) -> Result<Value, CalcError> {
+        let ctx = FunctionContext::default();
+        let args: Vec<Expr> = args
+            .iter()
+            .map(|a| parse_expr(&ctx, a, None).unwrap())
+            .collect();
+        solve(&ctx, &Environment::new(), &args)
+    }
+
+    #[test]
+    fn newton_and_brent() {
+        let close = |args: &[&str], y: f64| {
+            let x = solve_for(args).unwrap().to_f64();
+            (x - y).abs() <= 1e-14 * y.abs().max(1.)
+        };
+        assert!(close(&["x^2 - 2", "x", "1"], 2f64.sqrt()));
+        assert!(close(&["x^2 - 2", "x", "-1"], -(2f64.sqrt())));
+        // Newton's slope vanishes at the guess, the bracket finds the root
+        assert!(close(&["x^3 - 1", "x", "0"], 1.));
+        // sign changes at a pole or a jump are not roots
+        assert_eq!(
+            solve_for(&["1 / (x - 1)", "x", "0", "2"]),
+            Err(not_converged("Found no root between 0 and 2".into()))
+        );
+        assert_eq!(
+            solve_for(&["1 / (x - 1)", "x", "2"]),
+            Err(not_converged("Found no root near 2".into()))
+        );
+        assert_eq!(
+            solve_for(&["1 / x", "x", "1"]),
+            Err(not_converged("Found no root near 1".into()))
+        );
+        assert_eq!(
+            solve_for(&["floor(x) - 0.5", "x", "0"]),
+            Err(not_converged("Found no root near 0".into()))
+        );
+        assert_eq!(
+            solve_for(&["floor(x) - 0.5", "x", "-3", "3"]),
+            Err(not_converged("Found no root between -3 and 3".into()))
+        );
+    }
+}
@@ -0,0 +1,96 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Lines of script files run with `eva run file.eva`: `#` starts a comment,
+//! which also covers a `#!/usr/bin/env eva` shebang, and `assert <expr>` or
+//! `assert(<expr>)` checks that a condition holds.
+
+use crate::env::Environment;
+use crate::error::CalcError;
+use crate::lex::FunctionContext;
+use crate::logic;
+use crate::value::Value;
+
+/// `line` without its comment.
+pub fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(i) => &line[..i],
+        None => line,
+    }
+}
+
+/// Whether `path` names a script rather than being an expression, which it
+/// does if it names a file ending in `.eva`.
+pub fn is_script(path: &str) -> bool {
+    let path = std::path::Path::new(path);
+    path.is_file() && path.extension().is_some_and(|e| e == "eva")
+}
+
+/// Whether `path` names a file starting with a shebang, a script if `path`
+/// is no expression, as in `./circle` rather than `e`.
+pub fn has_shebang(path: &str) -> bool {
+    let path = std::path::Path::new(path);
+    path.is_file() && std::fs::read(path).is_ok_and(|bytes| bytes.starts_with(b"#!"))
+}
+
+/// Evaluate a line of a script, nothing for blank lines, comments and
+/// assertions that hold.
+pub fn eval_line(
+    ctx: &FunctionContext,
+    env: &mut Environment,
+    fix: usize,
+    line: &str,
+    prev_ans: Option<&Value>,
+) -> Result<Option<Value>, CalcError> {
+    let code = strip_comment(line);
+    if code.trim().is_empty() {
+        return Ok(None);
+    }
+    let trimmed = code.trim_start();
+    let condition = match trimmed.strip_prefix("assert") {
+        Some(rest) if rest.starts_with(|c: char| c.is_whitespace() || c == '(') => rest,
+        _ => return crate::eval_expr(ctx, env, fix, code, prev_ans),
+    };
+    let offset = code.len() - condition.len();
+    let value = crate::eval_body(ctx, env, condition, prev_ans).map_err(|e| e.shift(offset))?;
+    let span = offset + condition.len() - condition.trim_start().len()..code.trim_end().len();
+    match logic::truth(&value).map_err(|e| e.at(span.clone()))? {
+        true => Ok(None),
+        false => Err(CalcError::Assertion(condition.trim().to_string()).at(span)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::MathError;
+
+    #[test]
+    fn script_lines() {
+        let ctx = FunctionContext::default();
+        let mut env = Environment::new();
+        let mut eval = |line| eval_line(&ctx, &mut env, 10, line, None);
+        assert_eq!(eval("#!/usr/bin/env eva"), Ok(None));
+        assert_eq!(eval("   # nothing here"), Ok(None));
+        assert_eq!(eval("r = 2 # the radius"), Ok(Some(Value::Float(2.))));
+        assert_eq!(eval("assert r^2 == 4"), Ok(None));
+        let failed = eval("assert r > 3  # too small").unwrap_err();
+        assert_eq!(failed.span(), Some(7..12));
+        assert_eq!(failed.without_span(), CalcError::Assertion("r > 3".into()));
+        assert_eq!(
+            eval("assert r").map_err(CalcError::without_span),
+            Err(CalcError::Math(MathError::Type(
+                "Expected a boolean, got a number".into()
+            )))
+        );
+        assert_eq!(eval("assert 1 / 0 == 1").unwrap_err().span(), Some(7..12));
+        assert_eq!(eval("assert(r == 2)"), Ok(None));
+        let failed = eval("assert(r < 2)").unwrap_err();
+        assert_eq!(failed.span(), Some(6..13));
+        assert_eq!(
+            failed.without_span(),
+            CalcError::Assertion("(r < 2)".into())
+        );
+    }
+}
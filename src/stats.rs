@@ -0,0 +1,164 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Descriptive statistics over argument lists. Floats are summed with
+//! compensation so that `sum(1e16, 1, -1e16)` is still 1, rationals exactly.
+
+use num::{BigRational, One, Signed, Zero};
+
+/// Neumaier's compensated sum, keeps the low order bits that plain
+/// accumulation drops.
+pub fn sum(xs: &[f64]) -> f64 {
+    let mut sum = 0.;
+    let mut compensation = 0.;
+    for &x in xs {
+        let t = sum + x;
+        if sum.abs() >= x.abs() {
+            compensation += (sum - t) + x;
+        } else {
+            compensation += (x - t) + sum;
+        }
+        sum = t;
+    }
+    sum + compensation
+}
+
+pub fn mean(xs: &[f64]) -> f64 {
+    let n = xs.len() as f64;
+    let mean = sum(xs) / n;
+    if mean.is_finite() {
+        mean
+    } else {
+        // the sum overflowed, the mean may not
+        sum(&xs.iter().map(|x| x / n).collect::<Vec<_>>())
+    }
+}
+
+/// Variance around the mean, divided by `n - 1` for a `sample` and by `n`
+/// for a whole population.
+pub fn variance(xs: &[f64], sample: bool) -> f64 {
+    let n = xs.len() as f64;
+    let denominator = if sample { n - 1. } else { n };
+    if denominator <= 0. {
+        return f64::NAN;
+    }
+    let mean = mean(xs);
+    let deviations: Vec<f64> = xs.iter().map(|x| x - mean).collect();
+    let squares: Vec<f64> = deviations.iter().map(|d| d * d).collect();
+    // corrected two-pass algorithm, the second term cancels rounding in `mean`
+    let drift = sum(&deviations);
+    ((sum(&squares) - drift * drift / n) / denominator).max(0.)
+}
+
+fn sorted(xs: &[f64]) -> Vec<f64> {
+    let mut xs = xs.to_vec();
+    xs.sort_by(f64::total_cmp);
+    xs
+}
+
+pub fn median(xs: &[f64]) -> f64 {
+    percentile(50., xs)
+}
+
+/// Most frequent value, the smallest one on a tie.
+pub fn mode(xs: &[f64]) -> f64 {
+    longest_run(&sorted(xs))[0]
+}
+
+/// First of the longest runs of equal values in `xs`.
+fn longest_run<T: PartialEq>(xs: &[T]) -> &[T] {
+    xs.chunk_by(|a, b| a == b).fold(
+        &xs[..0],
+        |best, run| if run.len() > best.len() { run } else { best },
+    )
+}
+
+/// The `p`th percentile, interpolating linearly between the closest ranks.
+pub fn percentile(p: f64, xs: &[f64]) -> f64 {
+    if !(0. ..=100.).contains(&p) || xs.is_empty() {
+        return f64::NAN;
+    }
+    let xs = sorted(xs);
+    let rank = (xs.len() - 1) as f64 * p / 100.;
+    let below = rank.floor() as usize;
+    match xs.get(below + 1) {
+        Some(above) => xs[below] + (rank - below as f64) * (above - xs[below]),
+        None => xs[below],
+    }
+}
+
+/// Exact counterparts for rationals, `None` when the result is irrational
+/// or undefined.
+pub fn exact(name: &str, xs: &[BigRational]) -> Option<BigRational> {
+    let n = BigRational::from_integer(xs.len().into());
+    let sum = || xs.iter().fold(BigRational::zero(), |s, x| s + x);
+    let mean = || sum() / &n;
+    let variance = |sample: bool| {
+        let denominator = if sample {
+            &n - BigRational::one()
+        } else {
+            n.clone()
+        };
+        if !denominator.is_positive() {
+            return None;
+        }
+        let mean = mean();
+        let squares = xs.iter().map(|x| (x - &mean) * (x - &mean));
+        Some(squares.fold(BigRational::zero(), |s, x| s + x) / denominator)
+    };
+    let sorted = |xs: &[BigRational]| {
+        let mut xs = xs.to_vec();
+        xs.sort();
+        xs
+    };
+    let percentile = |p: &BigRational, xs: &[BigRational]| {
+        let hundred = BigRational::from_integer(100.into());
+        if p.is_negative() || *p > hundred || xs.is_empty() {
+            return None;
+        }
+        let xs = sorted(xs);
+        let rank = BigRational::from_integer((xs.len() - 1).into()) * p / hundred;
+        let below = rank.floor();
+        let i: usize = below.to_integer().try_into().ok()?;
+        Some(match xs.get(i + 1) {
+            Some(above) => &xs[i] + (&rank - below) * (above - &xs[i]),
+            None => xs[i].clone(),
+        })
+    };
+    match name {
+        "sum" => Some(sum()),
+        "mean" => Some(mean()),
+        "median" => percentile(&BigRational::from_integer(50.into()), xs),
+        "mode" => longest_run(&sorted(xs)).first().cloned(),
+        "var" => variance(true),
+        "pvar" => variance(false),
+        "percentile" => percentile(xs.first()?, &xs[1..]),
+        "min" => xs.iter().min().cloned(),
+        "max" => xs.iter().max().cloned(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compensated() {
+        assert_eq!(sum(&[1e16, 1., -1e16]), 1.);
+        assert_eq!(sum(&[0.1; 10]), 1.);
+        let shifted: Vec<f64> = [4., 7., 13., 16.].iter().map(|x| x + 1e9).collect();
+        assert_eq!(variance(&shifted, true), 30.);
+        assert_eq!(variance(&[4., 7., 13., 16.], false), 22.5);
+    }
+
+    #[test]
+    fn order_statistics() {
+        assert_eq!(median(&[3., 1., 2.]), 2.);
+        assert_eq!(median(&[4., 1., 3., 2.]), 2.5);
+        assert_eq!(mode(&[3., 1., 3., 1., 2.]), 1.);
+        assert_eq!(percentile(90., &[1., 2., 3., 4., 5.]), 4.6);
+        assert!(percentile(101., &[1.]).is_nan());
+    }
+}
@@ -0,0 +1,1162 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Expressions in variables without a value, for `diff(x^2 * sin(x), x)` and
+//! `simplify(...)`. Trees are only built through constructors that keep them
+//! canonical: sums and products are flattened and sorted, numbers are folded
+//! exactly, like terms and like factors are combined and a few trig
+//! identities are applied. Expressions that simplify to the same thing are
+//! then equal as trees.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use num::{BigInt, BigRational, One, Signed, ToPrimitive, Zero};
+
+use crate::ast::{self, Expr, UnaryOp};
+use crate::decimal::{Decimal, GUARD};
+use crate::env::{Environment, MAX_CALL_DEPTH};
+use crate::error::{CalcError, MathError};
+use crate::integer;
+use crate::lex::{AngleUnit, FunctionContext, NumberMode, Relation, Span, FUNCTIONS, OPERATORS};
+use crate::value::{self, Value};
+
+/// Constants that stay symbols rather than being replaced by their value.
+const CONSTANTS: [&str; 3] = ["pi", "e", "i"];
+
+/// Most bits of a number raised to an integer power exactly.
+const MAX_POWER_BITS: u64 = 100_000;
+
+/// Largest `n` of `diff(f, x, n)`.
+const MAX_ORDER: u32 = 100;
+
+/// An expression in canonical form, build it with the operators and `pow`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Symbolic {
+    Number(BigRational),
+    /// A variable without a value, or one of `CONSTANTS`.
+    Symbol(String),
+    /// At least two terms, in order of increasing degree.
+    Sum(Vec<Symbolic>),
+    /// At least two factors, a number only comes first and is not 1.
+    Product(Vec<Symbolic>),
+    Power(Box<Symbolic>, Box<Symbolic>),
+    /// A built-in function.
+    Call(String, Vec<Symbolic>),
+    /// `lhs op rhs` for a comparison or logical operator, as in the condition
+    /// of `if`.
+    Compare(&'static str, Box<Symbolic>, Box<Symbolic>),
+}
+
+/// How the derivative of a built-in function is found.
+enum Rule {
+    /// Partial derivatives in each argument, for the chain rule.
+    Partials(fn(&FunctionContext, &[Symbolic]) -> Vec<Symbolic>),
+    /// An equivalent expression, differentiated instead.
+    Rewrite(fn(&FunctionContext, &[Symbolic]) -> Symbolic),
+    /// Piecewise constant, the derivative is 0 where it exists.
+    Constant,
+    /// `if(c, a, b)`, whose derivative is `if(c, a', b')`.
+    Branch,
+    /// Evaluated while expressions are built, never part of one.
+    Lowered,
+    /// Not differentiable, or not in terms of built-ins.
+    Undefined,
+}
+
+/// `diff(f, x)` and `diff(f, x, n)`, the first or `n`th derivative of `f` in
+/// `x`. If `x` has a value the derivative is taken there.
+pub fn diff(ctx: &FunctionContext, env: &Environment, args: &[Expr]) -> Result<Value, CalcError> {
+    let derivative = Lowering::new(ctx, env).diff(args)?;
+    to_value(ctx, env, derivative)
+}
+
+/// `simplify(f)`, `f` in canonical form.
+pub fn simplify(
+    ctx: &FunctionContext,
+    env: &Environment,
+    args: &[Expr],
+) -> Result<Value, CalcError> {
+    let simplified = Lowering::new(ctx, env).lower(&args[0])?;
+    to_value(ctx, env, simplified)
+}
+
+/// `x op y` where either of them is an expression.
+pub(crate) fn operate(token: &str, x: &Value, y: &Value) -> Result<Value, CalcError> {
+    let (x, y) = (from_value(x)?, from_value(y)?);
+    let result = match token {
+        "+" => x + y,
+        "-" => x - y,
+        "*" => x * y,
+        "/" => x / y,
+        "^" => x.pow(y),
+        _ => {
+            return Err(CalcError::Math(MathError::Type(format!(
+                "Operator '{}' needs numbers, got an expression",
+                token
+            ))))
+        }
+    };
+    Ok(Value::Symbolic(Box::new(checked(result)?)))
+}
+
+/// `name(args)` where some argument is an expression.
+pub(crate) fn apply(ctx: &FunctionContext, name: &str, args: &[Value]) -> Result<Value, CalcError> {
+    let args = args.iter().map(from_value).collect::<Result<_, _>>()?;
+    Ok(Value::Symbolic(Box::new(call(ctx, name, args))))
+}
+
+/// The number `r` as `ctx.mode` represents results.
+pub(crate) fn number_value(ctx: &FunctionContext, r: BigRational) -> Value {
+    match ctx.mode {
+        NumberMode::Float => Value::Float(r.to_f64().unwrap_or(f64::NAN)),
+        NumberMode::Decimal(digits) => match Decimal::from_rational(&r, digits + GUARD) {
+            Some(d) => Value::Decimal(d),
+            None => Value::Float(r.to_f64().unwrap_or(f64::NAN)),
+        },
+        NumberMode::Rational | NumberMode::Integer => value::conform(ctx, Value::Rational(r)),
+    }
+}
+
+/// Result of `diff` or `simplify`, expressions in constants only are
+/// evaluated.
+fn to_value(ctx: &FunctionContext, env: &Environment, s: Symbolic) -> Result<Value, CalcError> {
+    match checked(s)? {
+        Symbolic::Number(r) => Ok(number_value(ctx, r)),
+        s if s.is_constant() => s.to_expr(ctx).eval(ctx, env),
+        s => Ok(Value::Symbolic(Box::new(s))),
+    }
+}
+
+/// `x` as an expression, only real numbers have one.
+fn from_value(x: &Value) -> Result<Symbolic, CalcError> {
+    let number = |r| Ok(Symbolic::Number(r));
+    match x {
+        Value::Symbolic(s) => Ok((**s).clone()),
+        Value::Rational(r) => number(r.clone()),
+        Value::Decimal(d) => number(d.to_rational()),
+        Value::Factors(f) => from_value(&f.number),
+        // the shortest decimal that reads back as `x`, 0.1 is 1/10
+        Value::Float(x) => match Decimal::from_f64(*x, 17) {
+            Some(d) => number(d.to_rational()),
+            None => Err(CalcError::Math(MathError::OutOfBounds)),
+        },
+        x => Err(CalcError::Math(MathError::Type(format!(
+            "Expressions hold real numbers, got {}",
+            x.kind()
+        )))),
+    }
+}
+
+/// `s`, unless it divides by 0 somewhere.
+fn checked(s: Symbolic) -> Result<Symbolic, CalcError> {
+    if s.divides_by_zero() {
+        Err(CalcError::Math(MathError::DivideByZero))
+    } else {
+        Ok(s)
+    }
+}
+
+/// What a name in a tree stands for while it is lowered.
+enum Scope {
+    /// The variable of a derivative, or one bound by `integrate` and the like.
+    Free(String),
+    /// Parameters of a user function being expanded.
+    Bound(HashMap<String, Symbolic>),
+}
+
+/// Turns parsed trees into expressions, resolving names against the session.
+struct Lowering<'a> {
+    ctx: &'a FunctionContext,
+    env: &'a Environment,
+    /// Innermost last.
+    scopes: Vec<Scope>,
+    depth: usize,
+}
+
+impl<'a> Lowering<'a> {
+    fn new(ctx: &'a FunctionContext, env: &'a Environment) -> Lowering<'a> {
+        Lowering {
+            ctx,
+            env,
+            scopes: vec![],
+            depth: 0,
+        }
+    }
+
+    fn lower(&mut self, expr: &Expr) -> Result<Symbolic, CalcError> {
+        match expr {
+            Expr::Literal { value, span } => from_value(value).map_err(|e| e.at(span.clone())),
+            Expr::Ident { name, span } => self.ident(name).map_err(|e| e.at(span.clone())),
+            Expr::Unary { op, expr, span } => {
+                let x = self.lower(expr)?;
+                match op {
+                    UnaryOp::Neg => Ok(-x),
+                    UnaryOp::Factorial => {
+                        Ok(call(self.ctx, "gamma", vec![x + Symbolic::integer(1)]))
+                    }
+                    UnaryOp::Not | UnaryOp::BitNot => Err(no_symbolic_form(match op {
+                        UnaryOp::Not => "!",
+                        _ => "~",
+                    })
+                    .at(span.clone())),
+                }
+            }
+            Expr::Binary { op, lhs, rhs, span } => {
+                let (x, y) = (self.lower(lhs)?, self.lower(rhs)?);
+                match *op {
+                    "+" => Ok(x + y),
+                    "-" => Ok(x - y),
+                    "*" => Ok(x * y),
+                    "/" => Ok(x / y),
+                    "^" => Ok(x.pow(y)),
+                    op if crate::logic::is_logical(op) => {
+                        Ok(Symbolic::Compare(op, Box::new(x), Box::new(y)))
+                    }
+                    op => Err(no_symbolic_form(op).at(span.clone())),
+                }
+            }
+            Expr::Call { name, args, span } => self.call(name, args, span),
+            Expr::Matrix { span, .. } => Err(CalcError::Math(MathError::Type(
+                "Expressions cannot hold matrices".into(),
+            ))
+            .at(span.clone())),
+            // fails the way evaluating it does
+            Expr::Equation { .. } => expr.eval(self.ctx, self.env).and_then(|x| from_value(&x)),
+        }
+    }
+
+    /// A variable, replaced by its value if it has one.
+    fn ident(&self, name: &str) -> Result<Symbolic, CalcError> {
+        for scope in self.scopes.iter().rev() {
+            match scope {
+                Scope::Free(free) if free == name => return Ok(Symbolic::Symbol(name.into())),
+                // a function body sees its parameters and the session, not
+                // the scopes of its caller
+                Scope::Bound(params) => match params.get(name) {
+                    Some(x) => return Ok(x.clone()),
+                    None => break,
+                },
+                Scope::Free(_) => {}
+            }
+        }
+        if CONSTANTS.contains(&name) {
+            return Ok(Symbolic::Symbol(name.into()));
+        }
+        match self.env.get(name) {
+            Some(x) => from_value(&x),
+            // units are symbols as well
+            None => Ok(Symbolic::Symbol(name.into())),
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr], span: &Span) -> Result<Symbolic, CalcError> {
+        let func = ast::resolve_call(name, args.len(), span, self.env)?;
+        if let Relation::User(f) = &func.relation {
+            if self.depth >= MAX_CALL_DEPTH {
+                return Err(CalcError::RecursionLimit(MAX_CALL_DEPTH));
+            }
+            let args = args
+                .iter()
+                .map(|arg| self.lower(arg))
+                .collect::<Result<Vec<_>, _>>()?;
+            let params = f.params.iter().cloned().zip(args).collect();
+            self.scopes.push(Scope::Bound(params));
+            self.depth += 1;
+            let expanded = self.lower(&f.body);
+            self.depth -= 1;
+            self.scopes.pop();
+            // spans inside the body refer to the body, not the input
+            return expanded.map_err(|e| e.without_span().at(span.clone()));
+        }
+        let result = match name {
+            "diff" => self.diff(args),
+            "simplify" => self.lower(&args[0]),
+            "deriv" | "integrate" | "solve" => {
+                // the variable in the second argument is bound by the call
+                let bound = match &args[1] {
+                    Expr::Ident { name, .. } => Some(name.clone()),
+                    _ => None,
+                };
+                let scopes = self.scopes.len();
+                self.scopes.extend(bound.map(Scope::Free));
+                let args = args
+                    .iter()
+                    .map(|arg| self.lower(arg))
+                    .collect::<Result<Vec<_>, _>>();
+                self.scopes.truncate(scopes);
+                Ok(Symbolic::Call(name.into(), args?))
+            }
+            _ => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.lower(arg))
+                    .collect::<Result<_, _>>()?;
+                Ok(call(self.ctx, name, args))
+            }
+        };
+        result.map_err(|e| e.at(span.clone()))
+    }
+
+    fn diff(&mut self, args: &[Expr]) -> Result<Symbolic, CalcError> {
+        let variable = match &args[1] {
+            Expr::Ident { name, .. } => name,
+            other => {
+                return Err(CalcError::Syntax(format!(
+                    "Function 'diff' expected a variable name, got '{}'",
+                    other
+                ))
+                .at(other.span()))
+            }
+        };
+        let order = match args.get(2) {
+            Some(n) => {
+                let value = n.eval(self.ctx, self.env)?;
+                integer::from_value(&value)
+                    .ok()
+                    .and_then(|n| n.to_u32())
+                    .filter(|&n| n <= MAX_ORDER)
+                    .ok_or_else(|| CalcError::Math(MathError::OutOfBounds).at(n.span()))?
+            }
+            None => 1,
+        };
+        self.scopes.push(Scope::Free(variable.clone()));
+        let f = self.lower(&args[0]);
+        self.scopes.pop();
+        let mut f = f?;
+        for _ in 0..order {
+            f = f.derivative(self.ctx, variable)?;
+        }
+        match self.ident(variable)? {
+            Symbolic::Symbol(name) if &name == variable => Ok(f),
+            x => Ok(f.substitute(self.ctx, variable, &x)),
+        }
+    }
+}
+
+impl Symbolic {
+    pub fn integer(n: i64) -> Symbolic {
+        Symbolic::Number(BigRational::from_integer(BigInt::from(n)))
+    }
+
+    fn rational(numer: i64, denom: i64) -> Symbolic {
+        Symbolic::Number(BigRational::new(numer.into(), denom.into()))
+    }
+
+    fn symbol(name: &str) -> Symbolic {
+        Symbolic::Symbol(name.into())
+    }
+
+    pub fn pow(self, exponent: Symbolic) -> Symbolic {
+        power(self, exponent)
+    }
+
+    fn is_number(&self, n: i64) -> bool {
+        matches!(self, Symbolic::Number(r) if *r == BigRational::from_integer(n.into()))
+    }
+
+    /// Whether the expression prints with a leading minus.
+    fn is_negative(&self) -> bool {
+        match self {
+            Symbolic::Number(r) => r.is_negative(),
+            Symbolic::Product(factors) => factors[0].is_negative(),
+            _ => false,
+        }
+    }
+
+    /// Whether no variables appear, only numbers and `CONSTANTS`.
+    fn is_constant(&self) -> bool {
+        match self {
+            Symbolic::Number(_) => true,
+            Symbolic::Symbol(name) => CONSTANTS.contains(&&name[..]),
+            Symbolic::Sum(xs) | Symbolic::Product(xs) | Symbolic::Call(_, xs) => {
+                xs.iter().all(Symbolic::is_constant)
+            }
+            Symbolic::Power(x, y) | Symbolic::Compare(_, x, y) => {
+                x.is_constant() && y.is_constant()
+            }
+        }
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        match self {
+            Symbolic::Number(_) => false,
+            Symbolic::Symbol(symbol) => symbol == name,
+            Symbolic::Sum(xs) | Symbolic::Product(xs) | Symbolic::Call(_, xs) => {
+                xs.iter().any(|x| x.contains(name))
+            }
+            Symbolic::Power(x, y) | Symbolic::Compare(_, x, y) => {
+                x.contains(name) || y.contains(name)
+            }
+        }
+    }
+
+    fn divides_by_zero(&self) -> bool {
+        match self {
+            Symbolic::Power(base, exponent) if base.is_number(0) => exponent.is_negative(),
+            Symbolic::Number(_) | Symbolic::Symbol(_) => false,
+            Symbolic::Sum(xs) | Symbolic::Product(xs) | Symbolic::Call(_, xs) => {
+                xs.iter().any(Symbolic::divides_by_zero)
+            }
+            Symbolic::Power(x, y) | Symbolic::Compare(_, x, y) => {
+                x.divides_by_zero() || y.divides_by_zero()
+            }
+        }
+    }
+
+    /// The expression with `name` replaced by `x`, simplified again.
+    fn substitute(&self, ctx: &FunctionContext, name: &str, x: &Symbolic) -> Symbolic {
+        let map = |xs: &[Symbolic]| xs.iter().map(|e| e.substitute(ctx, name, x)).collect();
+        match self {
+            Symbolic::Symbol(symbol) if symbol == name => x.clone(),
+            Symbolic::Number(_) | Symbolic::Symbol(_) => self.clone(),
+            Symbolic::Sum(terms) => sum(map(terms)),
+            Symbolic::Product(factors) => product(map(factors)),
+            Symbolic::Power(base, exponent) => power(
+                base.substitute(ctx, name, x),
+                exponent.substitute(ctx, name, x),
+            ),
+            Symbolic::Call(f, args) => call(ctx, f, map(args)),
+            Symbolic::Compare(op, lhs, rhs) => Symbolic::Compare(
+                op,
+                Box::new(lhs.substitute(ctx, name, x)),
+                Box::new(rhs.substitute(ctx, name, x)),
+            ),
+        }
+    }
+
+    fn derivative(&self, ctx: &FunctionContext, x: &str) -> Result<Symbolic, CalcError> {
+        if !self.contains(x) {
+            return Ok(Symbolic::integer(0));
+        }
+        Ok(match self {
+            Symbolic::Number(_) => Symbolic::integer(0),
+            Symbolic::Symbol(_) => Symbolic::integer(1),
+            Symbolic::Sum(terms) => sum(terms
+                .iter()
+                .map(|t| t.derivative(ctx, x))
+                .collect::<Result<_, _>>()?),
+            Symbolic::Product(factors) => {
+                let mut terms = vec![];
+                for (i, factor) in factors.iter().enumerate() {
+                    let mut others = factors.clone();
+                    others[i] = factor.derivative(ctx, x)?;
+                    terms.push(product(others));
+                }
+                sum(terms)
+            }
+            Symbolic::Power(base, exponent) => {
+                let (base, exponent) = (*base.clone(), *exponent.clone());
+                let (db, de) = (base.derivative(ctx, x)?, exponent.derivative(ctx, x)?);
+                if de.is_number(0) {
+                    let reduced = exponent.clone() - Symbolic::integer(1);
+                    exponent * base.pow(reduced) * db
+                } else {
+                    // d(b^e) = b^e (e' ln(b) + e b' / b)
+                    let ln = call(ctx, "ln", vec![base.clone()]);
+                    self.clone() * (de * ln + exponent * db / base)
+                }
+            }
+            Symbolic::Call(name, args) => {
+                let rule = rule(name).ok_or_else(|| no_derivative(name))?;
+                match rule {
+                    Rule::Partials(partials) => {
+                        let mut terms = vec![];
+                        for (partial, arg) in partials(ctx, args).into_iter().zip(args) {
+                            terms.push(partial * arg.derivative(ctx, x)?);
+                        }
+                        sum(terms)
+                    }
+                    Rule::Rewrite(rewrite) => rewrite(ctx, args).derivative(ctx, x)?,
+                    Rule::Constant => Symbolic::integer(0),
+                    Rule::Branch => call(
+                        ctx,
+                        "if",
+                        vec![
+                            args[0].clone(),
+                            args[1].derivative(ctx, x)?,
+                            args[2].derivative(ctx, x)?,
+                        ],
+                    ),
+                    Rule::Lowered | Rule::Undefined => return Err(no_derivative(name)),
+                }
+            }
+            Symbolic::Compare(..) => {
+                return Err(CalcError::Math(MathError::Type(
+                    "Comparisons have no derivative".into(),
+                )))
+            }
+        })
+    }
+
+    /// Sum of powers of variables, to order terms by.
+    fn degree(&self) -> BigRational {
+        match self {
+            Symbolic::Symbol(name) if !CONSTANTS.contains(&&name[..]) => BigRational::one(),
+            Symbolic::Power(base, exponent) => match &**exponent {
+                Symbolic::Number(n) => base.degree() * n,
+                _ => BigRational::zero(),
+            },
+            Symbolic::Product(factors) => factors.iter().map(Symbolic::degree).sum(),
+            Symbolic::Sum(terms) => terms.iter().map(Symbolic::degree).max().unwrap_or_default(),
+            _ => BigRational::zero(),
+        }
+    }
+
+    /// The tree that evaluates to this expression.
+    fn to_expr(&self, ctx: &FunctionContext) -> Expr {
+        let binary = |op, lhs: Expr, rhs: Expr| Expr::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+            span: 0..0,
+        };
+        let fold = |op, xs: &[Symbolic]| {
+            xs[1..]
+                .iter()
+                .fold(xs[0].to_expr(ctx), |acc, x| binary(op, acc, x.to_expr(ctx)))
+        };
+        match self {
+            Symbolic::Number(r) => Expr::Literal {
+                value: number_value(ctx, r.clone()),
+                span: 0..0,
+            },
+            Symbolic::Symbol(name) => Expr::Ident {
+                name: name.clone(),
+                span: 0..0,
+            },
+            Symbolic::Sum(terms) => fold("+", terms),
+            Symbolic::Product(factors) => fold("*", factors),
+            Symbolic::Power(base, exponent) => {
+                binary("^", base.to_expr(ctx), exponent.to_expr(ctx))
+            }
+            Symbolic::Call(name, args) => Expr::Call {
+                name: name.clone(),
+                args: args.iter().map(|x| x.to_expr(ctx)).collect(),
+                span: 0..0,
+            },
+            Symbolic::Compare(op, lhs, rhs) => binary(op, lhs.to_expr(ctx), rhs.to_expr(ctx)),
+        }
+    }
+}
+
+impl Add for Symbolic {
+    type Output = Symbolic;
+
+    fn add(self, other: Symbolic) -> Symbolic {
+        sum(vec![self, other])
+    }
+}
+
+impl Sub for Symbolic {
+    type Output = Symbolic;
+
+    fn sub(self, other: Symbolic) -> Symbolic {
+        sum(vec![self, -other])
+    }
+}
+
+impl Mul for Symbolic {
+    type Output = Symbolic;
+
+    fn mul(self, other: Symbolic) -> Symbolic {
+        product(vec![self, other])
+    }
+}
+
+impl Div for Symbolic {
+    type Output = Symbolic;
+
+    fn div(self, other: Symbolic) -> Symbolic {
+        product(vec![self, power(other, Symbolic::integer(-1))])
+    }
+}
+
+impl Neg for Symbolic {
+    type Output = Symbolic;
+
+    fn neg(self) -> Symbolic {
+        product(vec![Symbolic::integer(-1), self])
+    }
+}
+
+/// Canonical sum of `terms`.
+fn sum(terms: Vec<Symbolic>) -> Symbolic {
+    // coefficients of the distinct terms, numbers are multiples of 1
+    let mut like: Vec<(Symbolic, BigRational)> = vec![];
+    let mut pending = terms;
+    while let Some(term) = pending.pop() {
+        match term {
+            Symbolic::Sum(terms) => pending.extend(terms),
+            term => {
+                let (coefficient, rest) = split_coefficient(term);
+                add_like(&mut like, rest, coefficient);
+            }
+        }
+    }
+    pythagoras(&mut like);
+    let mut terms: Vec<(BigRational, Symbolic, BigRational)> = like
+        .into_iter()
+        .filter(|(_, coefficient)| !coefficient.is_zero())
+        .map(|(rest, coefficient)| (rest.degree(), rest, coefficient))
+        .collect();
+    terms.sort();
+    let mut terms: Vec<Symbolic> = terms
+        .into_iter()
+        .map(|(_, rest, coefficient)| product(vec![Symbolic::Number(coefficient), rest]))
+        .collect();
+    match terms.len() {
+        0 => Symbolic::integer(0),
+        1 => terms.pop().unwrap(),
+        _ => Symbolic::Sum(terms),
+    }
+}
+
+/// `c * rest` for a term, `rest` without a numeric factor.
+fn split_coefficient(term: Symbolic) -> (BigRational, Symbolic) {
+    match term {
+        Symbolic::Number(r) => (r, Symbolic::integer(1)),
+        Symbolic::Product(mut factors) => match factors[0].clone() {
+            Symbolic::Number(r) => {
+                factors.remove(0);
+                let rest = match factors.len() {
+                    1 => factors.pop().unwrap(),
+                    _ => Symbolic::Product(factors),
+                };
+                (r, rest)
+            }
+            _ => (BigRational::one(), Symbolic::Product(factors)),
+        },
+        term => (BigRational::one(), term),
+    }
+}
+
+fn add_like(like: &mut Vec<(Symbolic, BigRational)>, rest: Symbolic, coefficient: BigRational) {
+    match like.iter_mut().find(|(r, _)| *r == rest) {
+        Some((_, c)) => *c += coefficient,
+        None => like.push((rest, coefficient)),
+    }
+}
+
+/// `a sin(u)^2 + a cos(u)^2 = a` and `a sin(u)^2 - a = -a cos(u)^2` among
+/// the terms of a sum, with any common factor.
+fn pythagoras(like: &mut Vec<(Symbolic, BigRational)>) {
+    'search: loop {
+        for i in 0..like.len() {
+            let Some((name, u, others)) = trig_square(&like[i].0) else {
+                continue;
+            };
+            let other = if name == "sin" { "cos" } else { "sin" };
+            let partner =
+                others.clone() * Symbolic::Call(other.into(), vec![u]).pow(Symbolic::integer(2));
+            let a = like[i].1.clone();
+            if let Some(j) = like.iter().position(|(r, c)| *r == partner && *c == a) {
+                remove_pair(like, i, j);
+                add_like(like, others, a);
+                continue 'search;
+            }
+            if let Some(j) = like.iter().position(|(r, c)| *r == others && *c == -&a) {
+                remove_pair(like, i, j);
+                add_like(like, partner, -a);
+                continue 'search;
+            }
+        }
+        return;
+    }
+}
+
+/// `sin(u)^2` or `cos(u)^2` among the factors of `term`, with the others.
+fn trig_square(term: &Symbolic) -> Option<(&'static str, Symbolic, Symbolic)> {
+    let factors = match term {
+        Symbolic::Product(factors) => &factors[..],
+        term => std::slice::from_ref(term),
+    };
+    factors
+        .iter()
+        .enumerate()
+        .find_map(|(i, factor)| match factor {
+            Symbolic::Power(base, exponent) if exponent.is_number(2) => match &**base {
+                Symbolic::Call(name, args) if name == "sin" || name == "cos" => {
+                    let mut others = factors.to_vec();
+                    others.remove(i);
+                    let name = if name == "sin" { "sin" } else { "cos" };
+                    Some((name, args[0].clone(), product(others)))
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+}
+
+fn remove_pair<T>(items: &mut Vec<T>, i: usize, j: usize) {
+    items.remove(i.max(j));
+    items.remove(i.min(j));
+}
+
+/// Canonical product of `factors`.
+fn product(factors: Vec<Symbolic>) -> Symbolic {
+    let mut coefficient = BigRational::one();
+    // exponents of the distinct bases
+    let mut powers: Vec<(Symbolic, Symbolic)> = vec![];
+    let mut pending = factors;
+    while let Some(factor) = pending.pop() {
+        match factor {
+            Symbolic::Number(r) => coefficient *= r,
+            Symbolic::Product(factors) => pending.extend(factors),
+            Symbolic::Power(base, exponent) => add_power(&mut powers, *base, *exponent),
+            factor => add_power(&mut powers, factor, Symbolic::integer(1)),
+        }
+    }
+    if coefficient.is_zero() {
+        return Symbolic::integer(0);
+    }
+    tangents(&mut powers);
+    let mut factors = vec![];
+    for (base, exponent) in powers {
+        match power(base, exponent) {
+            Symbolic::Number(r) => coefficient *= r,
+            Symbolic::Product(more) => {
+                for factor in more {
+                    match factor {
+                        Symbolic::Number(r) => coefficient *= r,
+                        factor => factors.push(factor),
+                    }
+                }
+            }
+            factor => factors.push(factor),
+        }
+    }
+    if coefficient.is_zero() {
+        return Symbolic::integer(0);
+    }
+    if let ([Symbolic::Sum(terms)], false) = (&factors[..], coefficient.is_one()) {
+        // numbers are distributed over sums, 2(x + 1) is 2x + 2
+        let c = Symbolic::Number(coefficient);
+        return sum(terms.iter().map(|t| c.clone() * t.clone()).collect());
+    }
+    factors.sort_by(factor_order);
+    if factors.is_empty() {
+        return Symbolic::Number(coefficient);
+    }
+    if coefficient.is_one() && factors.len() == 1 {
+        return factors.pop().unwrap();
+    }
+    if !coefficient.is_one() {
+        factors.insert(0, Symbolic::Number(coefficient));
+    }
+    Symbolic::Product(factors)
+}
+
+fn add_power(powers: &mut Vec<(Symbolic, Symbolic)>, base: Symbolic, exponent: Symbolic) {
+    match powers.iter_mut().find(|(b, _)| *b == base) {
+        Some((_, e)) => *e = e.clone() + exponent,
+        None => powers.push((base, exponent)),
+    }
+}
+
+/// `sin(u)^k cos(u)^-k = tan(u)^k` among the factors of a product.
+fn tangents(powers: &mut Vec<(Symbolic, Symbolic)>) {
+    let is_call = |x: &Symbolic, f: &str| matches!(x, Symbolic::Call(name, _) if name == f);
+    while let Some((i, j)) = powers.iter().enumerate().find_map(|(i, (sin, k))| {
+        let Symbolic::Call(_, u) = sin else {
+            return None;
+        };
+        if !is_call(sin, "sin") || !matches!(k, Symbolic::Number(_)) {
+            return None;
+        }
+        let cos = Symbolic::Call("cos".into(), u.clone());
+        let j = powers
+            .iter()
+            .position(|(b, e)| *b == cos && *e == -k.clone())?;
+        Some((i, j))
+    }) {
+        let (sin, k) = powers[i].clone();
+        let Symbolic::Call(_, u) = sin else {
+            unreachable!()
+        };
+        remove_pair(powers, i, j);
+        powers.push((Symbolic::Call("tan".into(), u), k));
+    }
+}
+
+/// Factors sorted by their base, so that `x^2 y` keeps `x` first.
+fn factor_order(a: &Symbolic, b: &Symbolic) -> Ordering {
+    let base = |x: &Symbolic| match x {
+        Symbolic::Power(base, _) => (**base).clone(),
+        x => x.clone(),
+    };
+    base(a).cmp(&base(b)).then_with(|| a.cmp(b))
+}
+
+/// Canonical `base^exponent`.
+fn power(base: Symbolic, exponent: Symbolic) -> Symbolic {
+    match (base, exponent) {
+        (_, e) if e.is_number(0) => Symbolic::integer(1),
+        (base, e) if e.is_number(1) => base,
+        (b, _) if b.is_number(1) => Symbolic::integer(1),
+        (Symbolic::Number(b), Symbolic::Number(e)) => match number_power(&b, &e) {
+            Some(r) => Symbolic::Number(r),
+            None => Symbolic::Power(Box::new(Symbolic::Number(b)), Box::new(Symbolic::Number(e))),
+        },
+        // the powers of i repeat
+        (Symbolic::Symbol(i), Symbolic::Number(e)) if i == "i" && e.is_integer() => {
+            let n: BigInt = (e.to_integer() % 4 + 4) % 4;
+            match n.to_u8() {
+                Some(0) => Symbolic::integer(1),
+                Some(1) => Symbolic::symbol("i"),
+                Some(2) => Symbolic::integer(-1),
+                _ => -Symbolic::symbol("i"),
+            }
+        }
+        (Symbolic::Power(base, inner), Symbolic::Number(e)) if e.is_integer() => {
+            power(*base, *inner * Symbolic::Number(e))
+        }
+        (Symbolic::Product(factors), Symbolic::Number(e)) if e.is_integer() => product(
+            factors
+                .into_iter()
+                .map(|f| power(f, Symbolic::Number(e.clone())))
+                .collect(),
+        ),
+        (base, exponent) => Symbolic::Power(Box::new(base), Box::new(exponent)),
+    }
+}
+
+/// `b^e` if it is rational and not too large, 4^(1/2) is 2.
+fn number_power(b: &BigRational, e: &BigRational) -> Option<BigRational> {
+    if b.is_zero() {
+        return (e.is_positive()).then(BigRational::zero);
+    }
+    let root = |n: &BigInt, q: u32| {
+        let r = n.nth_root(q);
+        (r.pow(q) == *n).then_some(r)
+    };
+    let b = if e.is_integer() {
+        b.clone()
+    } else {
+        let q = e.denom().to_u32().filter(|&q| q <= 64)?;
+        if b.is_negative() {
+            return None;
+        }
+        BigRational::new(root(b.numer(), q)?, root(b.denom(), q)?)
+    };
+    let n = e.numer().to_i32()?;
+    let bits = (b.numer().bits() + b.denom().bits()) * u64::from(n.unsigned_abs());
+    (bits <= MAX_POWER_BITS).then(|| b.pow(n))
+}
+
+/// Canonical `name(args)` for a built-in, folded when the arguments are
+/// numbers and the result is exact.
+fn call(ctx: &FunctionContext, name: &str, mut args: Vec<Symbolic>) -> Symbolic {
+    let odd = [
+        "sin", "tan", "csc", "cot", "sinh", "tanh", "asin", "atan", "acsc", "acot",
+    ];
+    let even = ["cos", "sec", "cosh"];
+    match (name, &args[..]) {
+        ("sqrt", [_]) => return power(args.remove(0), Symbolic::rational(1, 2)),
+        ("exp", [_]) => return power(Symbolic::symbol("e"), args.remove(0)),
+        ("exp2", [_]) => return power(Symbolic::integer(2), args.remove(0)),
+        ("nroot", [_, n]) => {
+            let inverse = power(n.clone(), Symbolic::integer(-1));
+            return power(args.remove(0), inverse);
+        }
+        ("ln", [Symbolic::Symbol(e)]) if e == "e" => return Symbolic::integer(1),
+        ("ln", [Symbolic::Power(base, u)]) if **base == Symbolic::symbol("e") => {
+            return (**u).clone()
+        }
+        (f, [u]) if u.is_negative() && odd.contains(&f) => {
+            return -call(ctx, name, vec![-u.clone()]);
+        }
+        (f, [u]) if u.is_negative() && even.contains(&f) => {
+            return call(ctx, name, vec![-u.clone()]);
+        }
+        _ => {}
+    }
+    let numbers: Option<Vec<&BigRational>> = args
+        .iter()
+        .map(|x| match x {
+            Symbolic::Number(r) => Some(r),
+            _ => None,
+        })
+        .collect();
+    if let Some(r) = numbers.and_then(|numbers| fold(ctx, name, &numbers)) {
+        return Symbolic::Number(r);
+    }
+    Symbolic::Call(name.into(), args)
+}
+
+/// `name(numbers)` if it is exact, `sqrt(4)` but not `sqrt(2)`.
+fn fold(ctx: &FunctionContext, name: &str, numbers: &[&BigRational]) -> Option<BigRational> {
+    let func = FUNCTIONS.get(name)?;
+    if matches!(func.relation.select(numbers.len()), Relation::Special(..)) {
+        return None;
+    }
+    let exact = FunctionContext {
+        mode: NumberMode::Rational,
+        ..ctx.clone()
+    };
+    let args: Vec<Value> = numbers
+        .iter()
+        .map(|&r| Value::Rational(r.clone()))
+        .collect();
+    match func
+        .clone()
+        .apply(&exact, &Environment::new(), &args)
+        .ok()?
+        .number()
+    {
+        Value::Rational(r) => Some(r),
+        Value::Float(x) if x.fract() == 0. => BigRational::from_float(x),
+        _ => None,
+    }
+}
+
+/// Derivative of the built-in `name`, every entry of `lex::FUNCTIONS` has one.
+fn rule(name: &str) -> Option<Rule> {
+    use Rule::*;
+    fn f(ctx: &FunctionContext, name: &str, u: &Symbolic) -> Symbolic {
+        call(ctx, name, vec![u.clone()])
+    }
+    fn n(n: i64) -> Symbolic {
+        Symbolic::integer(n)
+    }
+    /// `1/sqrt(1 - u^2)`, the derivative of `asin(u)`.
+    fn arcsine(u: &Symbolic) -> Symbolic {
+        (n(1) - u.clone().pow(n(2))).pow(Symbolic::rational(-1, 2))
+    }
+    /// Derivative of `asin(1/u)`.
+    fn arccosecant(u: &Symbolic) -> Symbolic {
+        -(u.clone().pow(n(-2)) * arcsine(&u.clone().pow(n(-1))))
+    }
+    fn mean(u: &[Symbolic]) -> Symbolic {
+        sum(u.to_vec()) / n(u.len() as i64)
+    }
+    fn pvar(u: &[Symbolic]) -> Symbolic {
+        let squares: Vec<Symbolic> = u.iter().map(|x| x.clone().pow(n(2))).collect();
+        mean(&squares) - mean(u).pow(n(2))
+    }
+    fn var(u: &[Symbolic]) -> Symbolic {
+        let count = u.len() as i64;
+        pvar(u) * n(count) / n(count - 1)
+    }
+    Some(match name {
+        "sin" => Partials(|ctx, u| vec![angle(ctx) * f(ctx, "cos", &u[0])]),
+        "cos" => Partials(|ctx, u| vec![-(angle(ctx) * f(ctx, "sin", &u[0]))]),
+        "tan" => Partials(|ctx, u| vec![angle(ctx) * f(ctx, "sec", &u[0]).pow(n(2))]),
+        "csc" => {
+            Partials(|ctx, u| vec![-(angle(ctx) * f(ctx, "csc", &u[0]) * f(ctx, "cot", &u[0]))])
+        }
+        "sec" => Partials(|ctx, u| vec![angle(ctx) * f(ctx, "sec", &u[0]) * f(ctx, "tan", &u[0])]),
+        "cot" => Partials(|ctx, u| vec![-(angle(ctx) * f(ctx, "csc", &u[0]).pow(n(2)))]),
+        "sinh" => Partials(|ctx, u| vec![f(ctx, "cosh", &u[0])]),
+        "cosh" => Partials(|ctx, u| vec![f(ctx, "sinh", &u[0])]),
+        "tanh" => Partials(|ctx, u| vec![f(ctx, "cosh", &u[0]).pow(n(-2))]),
+        "ln" => Partials(|_, u| vec![u[0].clone().pow(n(-1))]),
+        "log2" => Partials(|ctx, u| vec![(u[0].clone() * f(ctx, "ln", &n(2))).pow(n(-1))]),
+        "log10" => Partials(|ctx, u| vec![(u[0].clone() * f(ctx, "ln", &n(10))).pow(n(-1))]),
+        "rad" => Partials(|_, _| vec![Symbolic::symbol("pi") / n(180)]),
+        "deg" => Partials(|_, _| vec![n(180) / Symbolic::symbol("pi")]),
+        "abs" => Partials(|ctx, u| vec![u[0].clone() / f(ctx, "abs", &u[0])]),
+        "asin" => Partials(|_, u| vec![arcsine(&u[0])]),
+        "acos" => Partials(|_, u| vec![-arcsine(&u[0])]),
+        "atan" => Partials(|_, u| vec![(n(1) + u[0].clone().pow(n(2))).pow(n(-1))]),
+        "acot" => Partials(|_, u| vec![-(n(1) + u[0].clone().pow(n(2))).pow(n(-1))]),
+        "acsc" => Partials(|_, u| vec![arccosecant(&u[0])]),
+        "asec" => Partials(|_, u| vec![-arccosecant(&u[0])]),
+        "gamma" => Partials(|ctx, u| vec![f(ctx, "gamma", &u[0]) * f(ctx, "digamma", &u[0])]),
+        "lgamma" => Partials(|ctx, u| vec![f(ctx, "digamma", &u[0])]),
+        "beta" => Partials(|ctx, u| {
+            let beta = call(ctx, "beta", u.to_vec());
+            let total = f(ctx, "digamma", &(u[0].clone() + u[1].clone()));
+            vec![
+                beta.clone() * (f(ctx, "digamma", &u[0]) - total.clone()),
+                beta * (f(ctx, "digamma", &u[1]) - total),
+            ]
+        }),
+        // variables are real numbers and 1x1 matrices
+        "re" | "conj" | "det" | "transpose" | "eig" => Partials(|_, _| vec![n(1)]),
+        "inv" => Partials(|_, u| vec![-u[0].clone().pow(n(-2))]),
+        "dot" => Partials(|_, u| vec![u[1].clone(), u[0].clone()]),
+        "sum" => Partials(|_, u| vec![n(1); u.len()]),
+        "mean" => Partials(|_, u| vec![n(1) / n(u.len() as i64); u.len()]),
+        "sqrt" => Rewrite(|_, u| u[0].clone().pow(Symbolic::rational(1, 2))),
+        "exp" => Rewrite(|_, u| Symbolic::symbol("e").pow(u[0].clone())),
+        "exp2" => Rewrite(|_, u| n(2).pow(u[0].clone())),
+        "nroot" => Rewrite(|_, u| u[0].clone().pow(u[1].clone().pow(n(-1)))),
+        "log" => Rewrite(|ctx, u| {
+            let base = u.get(1).cloned().unwrap_or(n(10));
+            f(ctx, "ln", &u[0]) / f(ctx, "ln", &base)
+        }),
+        "pvar" => Rewrite(|_, u| pvar(u)),
+        "var" => Rewrite(|_, u| var(u)),
+        "pstddev" => Rewrite(|_, u| pvar(u).pow(Symbolic::rational(1, 2))),
+        "stddev" => Rewrite(|_, u| var(u).pow(Symbolic::rational(1, 2))),
+        "ceil" | "floor" | "round" | "im" | "arg" | "rank" => Constant,
+        "if" => Branch,
+        "diff" | "simplify" => Lowered,
+        // the derivative of digamma is not a built-in
+        "digamma" | "approx" | "deriv" | "integrate" | "solve" | "cross" | "median" | "mode"
+        | "percentile" | "min" | "max" | "xor" => Undefined,
+        "gcd" | "lcm" | "modpow" | "modinv" | "ncr" | "npr" | "multinomial" | "catalan"
+        | "stirling2" | "bell" | "isprime" | "nextprime" | "prevprime" | "factor" | "totient"
+        | "divisors" => Undefined,
+        _ => return None,
+    })
+}
+
+/// Derivative of `x` in the angle unit, with respect to `x` in radians.
+fn angle(ctx: &FunctionContext) -> Symbolic {
+    match ctx.angle_unit {
+        AngleUnit::Radian => Symbolic::integer(1),
+        AngleUnit::Degree => Symbolic::symbol("pi") / Symbolic::integer(180),
+        AngleUnit::Gradian => Symbolic::symbol("pi") / Symbolic::integer(200),
+    }
+}
+
+fn no_derivative(name: &str) -> CalcError {
+    CalcError::Math(MathError::Type(format!(
+        "Function '{}' has no derivative",
+        name
+    )))
+}
+
+fn no_symbolic_form(op: &str) -> CalcError {
+    CalcError::Math(MathError::Type(format!(
+        "Operator '{}' has no symbolic form",
+        op
+    )))
+}
+
+impl fmt::Display for Symbolic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Symbolic::Number(r) => write!(f, "{}", r),
+            Symbolic::Symbol(name) => write!(f, "{}", name),
+            Symbolic::Sum(terms) => {
+                for (i, term) in terms.iter().enumerate() {
+                    let negative = term.is_negative();
+                    match (i, negative) {
+                        // a leading term carries its own sign
+                        (0, _) => write!(f, "{}", term)?,
+                        (_, false) => write!(f, " + {}", term)?,
+                        (_, true) => write!(f, " - {}", -term.clone())?,
+                    }
+                }
+                Ok(())
+            }
+            Symbolic::Product(_) | Symbolic::Power(..) => write_product(f, self),
+            Symbolic::Call(name, args) => {
+                let args: Vec<String> = args.iter().map(|x| x.to_string()).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+            Symbolic::Compare(op, lhs, rhs) => {
+                let precedence = OPERATORS[op].precedence;
+                let operand = |x: &Symbolic| match x {
+                    Symbolic::Compare(inner, ..) if OPERATORS[inner].precedence <= precedence => {
+                        format!("({})", x)
+                    }
+                    x => x.to_string(),
+                };
+                write!(f, "{} {} {}", operand(lhs), op, operand(rhs))
+            }
+        }
+    }
+}
+
+/// A product as `2x^2*sin(x)/(3y)`, factors with negative exponents go
+/// below the line.
+fn write_product(f: &mut fmt::Formatter<'_>, x: &Symbolic) -> fmt::Result {
+    let (coefficient, factors) = match x {
+        Symbolic::Product(factors) => match &factors[0] {
+            Symbolic::Number(c) => (c.clone(), &factors[1..]),
+            _ => (BigRational::one(), &factors[..]),
+        },
+        x => (BigRational::one(), std::slice::from_ref(x)),
+    };
+    let mut above = vec![];
+    let mut below = vec![];
+    for factor in factors {
+        match factor {
+            Symbolic::Power(base, exponent) if exponent.is_negative() => {
+                below.push(power((**base).clone(), -(**exponent).clone()));
+            }
+            factor => above.push(factor.clone()),
+        }
+    }
+    let numerator = coefficient.numer().abs();
+    // unary minus binds tighter than `^`, `-y^2` would read back as `(-y)^2`
+    let leading_power = numerator.is_one()
+        && matches!(above.first(), Some(Symbolic::Power(_, e)) if **e != Symbolic::rational(1, 2));
+    let mut above = join(&numerator, &above);
+    let below = join(coefficient.denom(), &below);
+    if above.is_empty() {
+        // nothing but a denominator
+        above.push("1".into());
+    }
+    let text = match below.len() {
+        0 => above.concat(),
+        1 => format!("{}/{}", above.concat(), below.concat()),
+        _ => format!("{}/({})", above.concat(), below.concat()),
+    };
+    match (coefficient.is_negative(), leading_power) {
+        (true, true) => write!(f, "-({})", text),
+        (true, false) => write!(f, "-{}", text),
+        (false, _) => write!(f, "{}", text),
+    }
+}
+
+/// Parts of `number * factors`, without a number 1, a number is written
+/// next to a following variable.
+fn join(number: &BigInt, factors: &[Symbolic]) -> Vec<String> {
+    let mut parts = vec![];
+    if !number.is_one() {
+        parts.push(number.to_string());
+    }
+    for factor in factors {
+        let text = match factor {
+            Symbolic::Sum(_) | Symbolic::Compare(..) => format!("({})", factor),
+            Symbolic::Power(base, exponent) => power_string(base, exponent),
+            factor => factor.to_string(),
+        };
+        let beside_number = matches!(factor, Symbolic::Symbol(_))
+            || matches!(factor, Symbolic::Power(base, e)
+                if matches!(**base, Symbolic::Symbol(_)) && **e != Symbolic::rational(1, 2));
+        match parts.len() {
+            0 => parts.push(text),
+            1 if beside_number && !number.is_one() => parts.push(text),
+            _ => parts.push(format!("*{}", text)),
+        }
+    }
+    parts
+}
+
+fn power_string(base: &Symbolic, exponent: &Symbolic) -> String {
+    if *exponent == Symbolic::rational(1, 2) {
+        return format!("sqrt({})", base);
+    }
+    let base = match base {
+        Symbolic::Symbol(_) | Symbolic::Call(..) => base.to_string(),
+        Symbolic::Number(r) if r.is_integer() && !r.is_negative() => base.to_string(),
+        base => format!("({})", base),
+    };
+    let exponent = match exponent {
+        Symbolic::Symbol(_) | Symbolic::Call(..) => exponent.to_string(),
+        Symbolic::Number(r) if r.is_integer() && !r.is_negative() => exponent.to_string(),
+        exponent => format!("({})", exponent),
+    };
+    format!("{}^{}", base, exponent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_function_has_a_rule() {
+        for name in FUNCTIONS.keys() {
+            assert!(rule(name).is_some(), "no derivative rule for '{}'", name);
+        }
+    }
+}
@@ -0,0 +1,521 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Dimensioned quantities. A unit name evaluates to one of that unit, so
+//! `5 km/h` is `5 * km / h`, and `to` (or `in`) converts between units of the
+//! same dimension.
+
+use std::fmt;
+
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+
+use crate::error::{CalcError, MathError};
+use crate::lex;
+use crate::value::Value;
+
+/// Exponents of the SI base dimensions, in the order of `BASE_UNITS`.
+type Dimension = [i32; 7];
+
+const BASE_UNITS: [&str; 7] = ["m", "kg", "s", "A", "K", "mol", "cd"];
+
+/// Product of named units, e.g. `km/h`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unit {
+    /// Unit names and their powers, in the order they were written.
+    terms: Vec<(String, i32)>,
+    /// Size of the unit in SI base units.
+    factor: f64,
+    /// SI value of the unit's zero, only non-zero for °C and °F.
+    offset: f64,
+    dimension: Dimension,
+}
+
+/// `value` of `unit`, e.g. 5 km/h.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quantity {
+    pub value: f64,
+    pub unit: Unit,
+}
+
+struct Definition {
+    factor: f64,
+    offset: f64,
+    dimension: Dimension,
+    /// Accepts metric prefixes, as in `km` or `mL`.
+    prefixed: bool,
+}
+
+static PREFIXES: &[(&str, f64)] = &[
+    ("Y", 1e24),
+    ("Z", 1e21),
+    ("E", 1e18),
+    ("P", 1e15),
+    ("T", 1e12),
+    ("G", 1e9),
+    ("M", 1e6),
+    ("k", 1e3),
+    ("h", 1e2),
+    ("da", 1e1),
+    ("d", 1e-1),
+    ("c", 1e-2),
+    ("m", 1e-3),
+    ("u", 1e-6),
+    ("µ", 1e-6),
+    ("n", 1e-9),
+    ("p", 1e-12),
+    ("f", 1e-15),
+    ("a", 1e-18),
+];
+
+static UNITS: Lazy<HashMap<&str, Definition>> = Lazy::new(|| {
+    fn add(
+        map: &mut HashMap<&str, Definition>,
+        names: &[&'static str],
+        factor: f64,
+        dimension: Dimension,
+        prefixed: bool,
+    ) {
+        for name in names {
+            let definition = Definition {
+                factor,
+                offset: 0.,
+                dimension,
+                prefixed,
+            };
+            map.insert(*name, definition);
+        }
+    }
+    const LENGTH: Dimension = [1, 0, 0, 0, 0, 0, 0];
+    const MASS: Dimension = [0, 1, 0, 0, 0, 0, 0];
+    const TIME: Dimension = [0, 0, 1, 0, 0, 0, 0];
+    const CURRENT: Dimension = [0, 0, 0, 1, 0, 0, 0];
+    const TEMPERATURE: Dimension = [0, 0, 0, 0, 1, 0, 0];
+    const AREA: Dimension = [2, 0, 0, 0, 0, 0, 0];
+    const VOLUME: Dimension = [3, 0, 0, 0, 0, 0, 0];
+    const SPEED: Dimension = [1, 0, -1, 0, 0, 0, 0];
+    const FORCE: Dimension = [1, 1, -2, 0, 0, 0, 0];
+    const PRESSURE: Dimension = [-1, 1, -2, 0, 0, 0, 0];
+    const ENERGY: Dimension = [2, 1, -2, 0, 0, 0, 0];
+    const POWER: Dimension = [2, 1, -3, 0, 0, 0, 0];
+    const CHARGE: Dimension = [0, 0, 1, 1, 0, 0, 0];
+    const VOLTAGE: Dimension = [2, 1, -3, -1, 0, 0, 0];
+    let mut m = HashMap::new();
+
+    // SI base units, the kilogram is prefixed as a gram
+    add(&mut m, &["m"], 1., LENGTH, true);
+    add(&mut m, &["g"], 1e-3, MASS, true);
+    add(&mut m, &["s"], 1., TIME, true);
+    add(&mut m, &["A"], 1., CURRENT, true);
+    add(&mut m, &["K"], 1., TEMPERATURE, true);
+    add(&mut m, &["mol"], 1., [0, 0, 0, 0, 0, 1, 0], true);
+    add(&mut m, &["cd"], 1., [0, 0, 0, 0, 0, 0, 1], true);
+
+    // derived units
+    add(&mut m, &["Hz"], 1., [0, 0, -1, 0, 0, 0, 0], true);
+    add(&mut m, &["N"], 1., FORCE, true);
+    add(&mut m, &["Pa"], 1., PRESSURE, true);
+    add(&mut m, &["J"], 1., ENERGY, true);
+    add(&mut m, &["W"], 1., POWER, true);
+    add(&mut m, &["C"], 1., CHARGE, true);
+    add(&mut m, &["V"], 1., VOLTAGE, true);
+    add(&mut m, &["ohm", "Ω"], 1., [2, 1, -3, -2, 0, 0, 0], true);
+    add(&mut m, &["F"], 1., [-2, -1, 4, 2, 0, 0, 0], true);
+    add(&mut m, &["T"], 1., [0, 1, -2, -1, 0, 0, 0], true);
+    add(&mut m, &["L"], 1e-3, VOLUME, true);
+    add(&mut m, &["bar"], 1e5, PRESSURE, true);
+    add(&mut m, &["eV"], 1.602_176_634e-19, ENERGY, true);
+    add(&mut m, &["cal"], 4.184, ENERGY, true);
+    add(&mut m, &["Wh"], 3600., ENERGY, true);
+
+    // time, mass and area outside the SI
+    add(&mut m, &["min"], 60., TIME, false);
+    add(&mut m, &["h"], 3600., TIME, false);
+    add(&mut m, &["d", "day"], 86400., TIME, false);
+    add(&mut m, &["week"], 604_800., TIME, false);
+    add(&mut m, &["yr"], 31_557_600., TIME, false);
+    add(&mut m, &["t"], 1e3, MASS, false);
+    add(&mut m, &["ha"], 1e4, AREA, false);
+    add(&mut m, &["atm"], 101_325., PRESSURE, false);
+
+    // imperial and US customary units
+    add(&mut m, &["inch"], 0.0254, LENGTH, false);
+    add(&mut m, &["ft"], 0.3048, LENGTH, false);
+    add(&mut m, &["yd"], 0.9144, LENGTH, false);
+    add(&mut m, &["mi"], 1609.344, LENGTH, false);
+    add(&mut m, &["nmi"], 1852., LENGTH, false);
+    add(&mut m, &["acre"], 4_046.856_422_4, AREA, false);
+    add(&mut m, &["gal"], 3.785_411_784e-3, VOLUME, false);
+    add(&mut m, &["oz"], 0.028_349_523_125, MASS, false);
+    add(&mut m, &["lb"], 0.453_592_37, MASS, false);
+    add(&mut m, &["mph"], 0.447_04, SPEED, false);
+    add(&mut m, &["kn"], 1852. / 3600., SPEED, false);
+    add(&mut m, &["lbf"], 4.448_221_615_260_5, FORCE, false);
+    add(&mut m, &["psi"], 6_894.757_293_168, PRESSURE, false);
+    add(&mut m, &["hp"], 745.699_871_582_270_2, POWER, false);
+    add(&mut m, &["BTU"], 1_055.055_852_62, ENERGY, false);
+
+    // temperatures with a zero of their own
+    for (names, factor, offset) in [
+        (&["°C", "degC"], 1., 273.15),
+        (&["°F", "degF"], 5. / 9., 273.15 - 32. * 5. / 9.),
+    ] {
+        for name in names {
+            let definition = Definition {
+                factor,
+                offset,
+                dimension: TEMPERATURE,
+                prefixed: false,
+            };
+            m.insert(*name, definition);
+        }
+    }
+    m
+});
+
+/// One of the unit called `name`, possibly with a metric prefix.
+pub fn lookup(name: &str) -> Option<Value> {
+    let unit = |definition: &Definition, scale: f64| Unit {
+        terms: vec![(name.to_string(), 1)],
+        factor: definition.factor * scale,
+        offset: definition.offset,
+        dimension: definition.dimension,
+    };
+    let unit = match UNITS.get(name) {
+        Some(definition) => unit(definition, 1.),
+        None => PREFIXES.iter().find_map(|(prefix, scale)| {
+            UNITS
+                .get(name.strip_prefix(prefix)?)
+                .filter(|definition| definition.prefixed)
+                .map(|definition| unit(definition, *scale))
+        })?,
+    };
+    Some(Value::Quantity(Quantity { value: 1., unit }))
+}
+
+fn incompatible(x: &Unit, y: &Unit) -> CalcError {
+    CalcError::Math(MathError::Units(format!(
+        "Incompatible units '{}' and '{}'",
+        x, y
+    )))
+}
+
+impl Unit {
+    /// The unit of plain numbers.
+    fn one() -> Unit {
+        Unit {
+            terms: vec![],
+            factor: 1.,
+            offset: 0.,
+            dimension: [0; 7],
+        }
+    }
+
+    /// The coherent SI unit of `dimension`, e.g. `kg*m/s^2`.
+    fn base(dimension: Dimension) -> Unit {
+        let terms = BASE_UNITS
+            .iter()
+            .zip(dimension)
+            .filter(|(_, power)| *power != 0)
+            .map(|(name, power)| (name.to_string(), power))
+            .collect();
+        Unit {
+            terms,
+            factor: 1.,
+            offset: 0.,
+            dimension,
+        }
+    }
+
+    fn is_dimensionless(&self) -> bool {
+        self.dimension == [0; 7]
+    }
+
+    fn mul(&self, other: &Unit, sign: i32) -> Unit {
+        let mut terms = self.terms.clone();
+        for (name, power) in &other.terms {
+            match terms.iter_mut().find(|(n, _)| n == name) {
+                Some((_, p)) => *p += sign * power,
+                None => terms.push((name.clone(), sign * power)),
+            }
+        }
+        terms.retain(|(_, power)| *power != 0);
+        let mut dimension = self.dimension;
+        for (d, o) in dimension.iter_mut().zip(other.dimension) {
+            *d += sign * o;
+        }
+        Unit {
+            terms,
+            factor: self.factor * other.factor.powi(sign),
+            offset: 0.,
+            dimension,
+        }
+    }
+}
+
+impl Quantity {
+    fn plain(x: &Value) -> Result<Quantity, CalcError> {
+        match x {
+            Value::Quantity(q) => Ok(q.clone()),
+            Value::Complex(_) => Err(CalcError::Math(MathError::Units(
+                "Quantities must be real".into(),
+            ))),
+            x => Ok(Quantity {
+                value: x.to_f64(),
+                unit: Unit::one(),
+            }),
+        }
+    }
+
+    /// Value in SI base units.
+    fn si(&self) -> f64 {
+        self.value * self.unit.factor + self.unit.offset
+    }
+
+    /// Value in `unit`, which must have the same dimension.
+    fn value_in(&self, unit: &Unit) -> Result<f64, CalcError> {
+        if self.unit.dimension != unit.dimension {
+            return Err(CalcError::Math(MathError::Units(format!(
+                "Cannot convert '{}' to '{}'",
+                self.unit, unit
+            ))));
+        }
+        Ok((self.si() - unit.offset) / unit.factor)
+    }
+
+    fn into_value(self) -> Value {
+        match self.unit.is_dimensionless() {
+            true => Value::Float(self.value * self.unit.factor),
+            false => Value::Quantity(self),
+        }
+    }
+}
+
+/// `x op y` when either side is a quantity, or `op` is a conversion.
+pub fn operate(token: &str, x: &Value, y: &Value) -> Result<Value, CalcError> {
+    let (x, y) = (Quantity::plain(x)?, Quantity::plain(y)?);
+    let value = match token {
+        "+" | "-" | "%" if x.unit.dimension != y.unit.dimension => {
+            return Err(incompatible(&x.unit, &y.unit))
+        }
+        "+" | "-" | "%" => {
+            // `20 °C + 5 K` is five kelvin warmer, not an absolute temperature
+            let y_value = match (x.unit.offset, y.unit.offset) {
+                (o, 0.) if o != 0. => y.value * y.unit.factor / x.unit.factor,
+                _ => y.value_in(&x.unit)?,
+            };
+            let value = match token {
+                "+" => x.value + y_value,
+                "-" => x.value - y_value,
+                _ => x.value % y_value,
+            };
+            Quantity {
+                value,
+                unit: x.unit,
+            }
+        }
+        // scaling keeps the unit, offset or not
+        "*" | "/" if y.unit.is_dimensionless() => {
+            let scale = y.value * y.unit.factor;
+            Quantity {
+                value: if token == "*" {
+                    x.value * scale
+                } else {
+                    x.value / scale
+                },
+                unit: x.unit,
+            }
+        }
+        "*" if x.unit.is_dimensionless() => Quantity {
+            value: x.value * x.unit.factor * y.value,
+            unit: y.unit,
+        },
+        "*" | "/" => {
+            let (x, y) = (absolute(x)?, absolute(y)?);
+            let sign = if token == "*" { 1 } else { -1 };
+            Quantity {
+                value: if sign == 1 {
+                    x.value * y.value
+                } else {
+                    x.value / y.value
+                },
+                unit: x.unit.mul(&y.unit, sign),
+            }
+        }
+        "^" => {
+            if !y.unit.is_dimensionless() {
+                return Err(CalcError::Math(MathError::Units(
+                    "Exponents must be dimensionless".into(),
+                )));
+            }
+            pow(absolute(x)?, y.value * y.unit.factor)?
+        }
+        // `1 m to 2 cm` would silently drop the 2
+        "to" if y.value != 1. => {
+            return Err(CalcError::Math(MathError::Units(format!(
+                "Can only convert to a bare unit, not '{}'",
+                y
+            ))))
+        }
+        "to" => Quantity {
+            value: x.value_in(&y.unit)?,
+            unit: y.unit,
+        },
+        _ => return Err(CalcError::Math(MathError::OutOfBounds)),
+    };
+    if !value.value.is_finite() {
+        return Err(CalcError::Math(MathError::TooLarge));
+    }
+    Ok(value.into_value())
+}
+
+/// `x` as an operand of products and powers, `10 °C / 1 s` is ambiguous as
+/// long as the temperature is counted from an offset.
+fn absolute(x: Quantity) -> Result<Quantity, CalcError> {
+    match x.unit.offset {
+        0. => Ok(x),
+        _ => Err(CalcError::Math(MathError::Units(format!(
+            "Cannot multiply '{}', convert it to '{}' first",
+            x.unit,
+            Unit::base(x.unit.dimension)
+        )))),
+    }
+}
+
+fn pow(x: Quantity, n: f64) -> Result<Quantity, CalcError> {
+    let power = |p: i32| {
+        let p = p as f64 * n;
+        match p.fract() {
+            0. => Ok(p as i32),
+            _ => Err(CalcError::Math(MathError::Units(format!(
+                "Cannot raise '{}' to {}",
+                x.unit, n
+            )))),
+        }
+    };
+    let mut unit = x.unit.clone();
+    for (_, p) in unit.terms.iter_mut() {
+        *p = power(*p)?;
+    }
+    for p in unit.dimension.iter_mut() {
+        *p = power(*p)?;
+    }
+    unit.factor = x.unit.factor.powf(n);
+    Ok(Quantity {
+        value: x.value.powf(n),
+        unit,
+    })
+}
+
+/// Built-in functions applied to quantities, only those that keep the
+/// dimension make sense.
+pub fn apply(name: &str, args: &[Value]) -> Result<Value, CalcError> {
+    let x = Quantity::plain(&args[0])?;
+    let value = match name {
+        "sqrt" => return Ok(pow(x, 0.5)?.into_value()),
+        "abs" => x.value.abs(),
+        "ceil" => x.value.ceil(),
+        "floor" => x.value.floor(),
+        "round" => match args.get(1) {
+            Some(digits) => lex::round_to(x.value, digits.to_f64()),
+            None => x.value.round(),
+        },
+        _ => {
+            return Err(CalcError::Math(MathError::Units(format!(
+                "Function '{}' needs a dimensionless argument",
+                name
+            ))))
+        }
+    };
+    Ok(Value::Quantity(Quantity {
+        value,
+        unit: x.unit,
+    }))
+}
+
+impl fmt::Display for Unit {
+    /// Reads back as the same unit, e.g. `kg*m^2/s^2`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let term = |name: &str, power: i32| match power.abs() {
+            1 => name.to_string(),
+            p => format!("{}^{}", name, p),
+        };
+        let numerator: Vec<_> = self
+            .terms
+            .iter()
+            .filter(|(_, p)| *p > 0)
+            .map(|(name, p)| term(name, *p))
+            .collect();
+        match numerator.is_empty() {
+            true => write!(f, "1")?,
+            false => write!(f, "{}", numerator.join("*"))?,
+        }
+        for (name, p) in self.terms.iter().filter(|(_, p)| *p < 0) {
+            write!(f, "/{}", term(name, *p))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.value, self.unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn q(value: f64, unit: &str) -> Value {
+        let Some(Value::Quantity(one)) = lookup(unit) else {
+            panic!("unknown unit {}", unit)
+        };
+        Value::Quantity(Quantity {
+            value,
+            unit: one.unit,
+        })
+    }
+
+    #[test]
+    fn prefixes() {
+        assert!(lookup("km").is_some());
+        assert!(lookup("mL").is_some());
+        assert!(lookup("kft").is_none());
+        assert!(lookup("x").is_none());
+        assert_eq!(operate("to", &q(1., "km"), &q(1., "mm")), Ok(q(1e6, "mm")));
+        assert_eq!(
+            operate("to", &q(1., "m"), &q(2., "cm")),
+            Err(CalcError::Math(MathError::Units(
+                "Can only convert to a bare unit, not '2 cm'".into()
+            )))
+        );
+    }
+
+    #[test]
+    fn temperatures() {
+        let to = |x, unit| match operate("to", &x, &q(1., unit)) {
+            Ok(Value::Quantity(q)) => q.value,
+            result => panic!("{:?}", result),
+        };
+        assert!((to(q(100., "°C"), "°F") - 212.).abs() < 1e-9);
+        assert!((to(q(-40., "°F"), "degC") + 40.).abs() < 1e-9);
+        assert!((to(q(0., "K"), "°C") + 273.15).abs() < 1e-9);
+        assert_eq!(operate("+", &q(20., "°C"), &q(5., "K")), Ok(q(25., "°C")));
+    }
+
+    #[test]
+    fn dimensions() {
+        let speed = operate("/", &q(5., "km"), &q(1., "h")).unwrap();
+        assert_eq!(speed.to_string(), "5 km/h");
+        let ratio = operate("/", &q(1., "km"), &q(1., "m"));
+        assert_eq!(ratio, Ok(Value::Float(1000.)));
+        assert_eq!(
+            operate("+", &q(1., "m"), &q(1., "s")),
+            Err(CalcError::Math(MathError::Units(
+                "Incompatible units 'm' and 's'".into()
+            )))
+        );
+    }
+}
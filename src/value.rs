@@ -0,0 +1,267 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+use std::fmt;
+
+use num::complex::Complex64;
+use num::{BigInt, BigRational, ToPrimitive};
+
+use crate::decimal::{self, Decimal, GUARD};
+use crate::error::{CalcError, MathError};
+use crate::lex::{FunctionContext, NumberMode};
+use crate::matrix::Matrix;
+use crate::primes::Factorization;
+use crate::symbolic::{self, Symbolic};
+use crate::units::Quantity;
+
+/// Largest `p` exponent accepted in hexadecimal literals.
+const MAX_BINARY_EXPONENT: i32 = 100_000;
+
+/// Largest `e` exponent accepted in literals evaluated exactly.
+const MAX_DECIMAL_EXPONENT: i64 = 100_000;
+
+/// Result of an evaluation, the representation depends on `NumberMode`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Float(f64),
+    Decimal(Decimal),
+    /// Exact fraction, only produced in `NumberMode::Rational`.
+    Rational(BigRational),
+    /// Complex number with a non-zero imaginary part.
+    Complex(Complex64),
+    /// Number with a physical unit, never dimensionless.
+    Quantity(Quantity),
+    Matrix(Matrix),
+    /// Result of a comparison, not a number.
+    Bool(bool),
+    /// Prime factorization, a number that prints as its factors.
+    Factors(Box<Factorization>),
+    /// Expression in variables without a value, from `diff` or `simplify`.
+    Symbolic(Box<Symbolic>),
+}
+
+impl Value {
+    /// Read a numeric literal in the representation `ctx` asks for, unprefixed
+    /// literals are in `ctx.ibase`.
+    pub fn parse(ctx: &FunctionContext, literal: &str) -> Option<Value> {
+        if ctx.ibase != 10 {
+            return Value::parse_radix(ctx, literal, ctx.ibase);
+        }
+        match ctx.mode {
+            NumberMode::Float => literal
+                .parse()
+                .ok()
+                .filter(|x: &f64| x.is_finite())
+                .map(Value::Float),
+            NumberMode::Decimal(digits) => {
+                Decimal::parse(literal, digits + GUARD).map(Value::Decimal)
+            }
+            NumberMode::Rational | NumberMode::Integer => Decimal::parse(literal, literal.len())
+                .filter(|d| d.magnitude().abs() <= MAX_DECIMAL_EXPONENT)
+                .map(|d| conform(ctx, Value::Rational(d.to_rational()))),
+        }
+    }
+
+    /// Read the digits of a literal in `radix`, as in `ff`, `1.8` or, for
+    /// hexadecimal, `1p-3` with a binary exponent.
+    pub fn parse_radix(ctx: &FunctionContext, literal: &str, radix: u32) -> Option<Value> {
+        let (mantissa, exponent) = match literal.split_once(['p', 'P']) {
+            Some((m, e)) if radix == 16 => (m, e.parse::<i32>().ok()?),
+            _ => (literal, 0),
+        };
+        if exponent.abs() > MAX_BINARY_EXPONENT {
+            return None;
+        }
+        let (integral, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let digits = format!("{}{}", integral, fraction);
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return None;
+        }
+        let digits = BigInt::parse_bytes(digits.as_bytes(), radix)?;
+        let radix = BigRational::from_integer(BigInt::from(radix));
+        let two = BigRational::from_integer(BigInt::from(2));
+        let r = BigRational::from_integer(digits) / radix.pow(fraction.len() as i32)
+            * two.pow(exponent);
+        match ctx.mode {
+            NumberMode::Float => r.to_f64().map(Value::Float),
+            NumberMode::Decimal(digits) => {
+                Decimal::from_rational(&r, digits + GUARD).map(Value::Decimal)
+            }
+            NumberMode::Rational | NumberMode::Integer => Some(conform(ctx, Value::Rational(r))),
+        }
+    }
+
+    /// Value of the constant `name` in the representation `ctx` asks for.
+    pub fn constant(ctx: &FunctionContext, name: &str) -> Option<Value> {
+        match ctx.mode {
+            NumberMode::Float | NumberMode::Rational | NumberMode::Integer => None,
+            NumberMode::Decimal(digits) => {
+                decimal::constant(name, digits + GUARD).map(Value::Decimal)
+            }
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Value::Float(x) => *x,
+            Value::Decimal(d) => d.to_f64(),
+            Value::Rational(r) => r.to_f64().unwrap_or(f64::NAN),
+            Value::Complex(z) => z.re,
+            Value::Quantity(q) => q.value,
+            Value::Matrix(_) => f64::NAN,
+            Value::Bool(b) => f64::from(u8::from(*b)),
+            Value::Factors(f) => f.number.to_f64(),
+            Value::Symbolic(_) => f64::NAN,
+        }
+    }
+
+    pub fn to_complex(&self) -> Complex64 {
+        match self {
+            Value::Complex(z) => *z,
+            real => Complex64::from(real.to_f64()),
+        }
+    }
+
+    /// What kind of value this is, for error messages, e.g. `a matrix`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Value::Float(_) | Value::Decimal(_) | Value::Rational(_) | Value::Factors(_) => {
+                "a number"
+            }
+            Value::Complex(_) => "a complex number",
+            Value::Quantity(_) => "a quantity",
+            Value::Matrix(_) => "a matrix",
+            Value::Bool(_) => "a boolean",
+            Value::Symbolic(_) => "an expression",
+        }
+    }
+
+    /// The number a factorization stands for, other values unchanged.
+    pub fn number(self) -> Value {
+        match self {
+            Value::Factors(f) => f.number,
+            value => value,
+        }
+    }
+}
+
+/// `value` as results are represented in `ctx.mode`, fractions become floats
+/// in `NumberMode::Integer`.
+pub(crate) fn conform(ctx: &FunctionContext, value: Value) -> Value {
+    match value {
+        Value::Rational(r) if ctx.mode == NumberMode::Integer && !r.is_integer() => {
+            Value::Float(r.to_f64().unwrap_or(f64::NAN))
+        }
+        // an expression whose variables cancelled
+        Value::Symbolic(s) => match *s {
+            Symbolic::Number(r) => symbolic::number_value(ctx, r),
+            s => Value::Symbolic(Box::new(s)),
+        },
+        value => value,
+    }
+}
+
+/// Rationals of `values`, `None` if any of them is inexact.
+pub(crate) fn to_rationals(values: &[Value]) -> Option<Vec<BigRational>> {
+    values
+        .iter()
+        .map(|v| match v {
+            Value::Rational(r) => Some(r.clone()),
+            Value::Factors(f) => to_rationals(std::slice::from_ref(&f.number))?.pop(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Promote every value to a decimal at the highest precision among them.
+pub(crate) fn to_decimals(values: &[Value]) -> Result<Vec<Decimal>, CalcError> {
+    let precision = values
+        .iter()
+        .filter_map(|v| match v {
+            Value::Decimal(d) => Some(d.precision()),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(GUARD);
+    values
+        .iter()
+        .map(|v| match v {
+            Value::Float(x) => Decimal::from_f64(*x, precision),
+            Value::Decimal(d) => Some(d.clone()),
+            Value::Rational(r) => Decimal::from_rational(r, precision),
+            Value::Factors(f) => to_decimals(std::slice::from_ref(&f.number)).ok()?.pop(),
+            Value::Complex(_)
+            | Value::Quantity(_)
+            | Value::Matrix(_)
+            | Value::Bool(_)
+            | Value::Symbolic(_) => None,
+        })
+        .collect::<Option<_>>()
+        .ok_or(CalcError::Math(MathError::TooLarge))
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Float(x)
+    }
+}
+
+impl From<Complex64> for Value {
+    /// Complex numbers on the real axis become floats.
+    fn from(z: Complex64) -> Self {
+        match z.im {
+            0. => Value::Float(z.re),
+            _ => Value::Complex(z),
+        }
+    }
+}
+
+impl From<BigRational> for Value {
+    fn from(r: BigRational) -> Self {
+        Value::Rational(r)
+    }
+}
+
+impl From<BigInt> for Value {
+    fn from(n: BigInt) -> Self {
+        Value::Rational(BigRational::from_integer(n))
+    }
+}
+
+impl std::ops::Neg for Value {
+    type Output = Value;
+
+    fn neg(self) -> Value {
+        match self {
+            Value::Float(x) => Value::Float(-x),
+            Value::Decimal(d) => Value::Decimal(d.neg()),
+            Value::Rational(r) => Value::Rational(-r),
+            Value::Complex(z) => Value::Complex(-z),
+            Value::Quantity(mut q) => {
+                q.value = -q.value;
+                Value::Quantity(q)
+            }
+            Value::Matrix(m) => Value::Matrix(m.map(|x| -x)),
+            Value::Bool(b) => Value::Bool(!b),
+            Value::Factors(f) => -f.number,
+            Value::Symbolic(s) => Value::Symbolic(Box::new(-*s)),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Decimal(d) => write!(f, "{}", d),
+            Value::Rational(r) => write!(f, "{}", r),
+            Value::Complex(z) => write!(f, "{}", z),
+            Value::Quantity(q) => write!(f, "{}", q),
+            Value::Matrix(m) => write!(f, "{}", m),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Factors(factors) => write!(f, "{}", factors),
+            Value::Symbolic(s) => write!(f, "{}", s),
+        }
+    }
+}
@@ -0,0 +1,46 @@
+/* Copyright (C) 2019  Akshay Oppiliappan <nerdypepper@tuta.io>
+ * Refer to LICENCE for more information.
+ * */
+
+//! Batch mode, `eva` reading expressions from a pipe.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn batch(input: &str) -> Output {
+    let mut eva = Command::new(env!("CARGO_BIN_EXE_eva"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    eva.stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    eva.wait_with_output().unwrap()
+}
+
+#[test]
+fn one_output_line_per_input_line() {
+    let output = batch("2 + 2\n\n# a comment\nf(x) = 2x\nf(_)\n");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "4.0000000000\n\n\n\n8.0000000000\n"
+    );
+}
+
+#[test]
+fn failures_keep_going_and_set_the_exit_code() {
+    let output = batch("1/0\n3 * 3\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "\n9.0000000000\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("<stdin>:1: 1/0"), "{}", stderr);
+    assert!(stderr.ends_with("<stdin>: 1 line failed\n"), "{}", stderr);
+}